@@ -1,9 +1,18 @@
 use std::time::Duration;
 
 use crate::domain::outputs::{
-    OutputDefinition, OutputDefinitions, OutputName, ScheduledActivation, ScheduledActivations,
+    ActivationDays, ActivationTime, Brightness, Cycle, DateRange, DayOfYear, EveryNDays, Interlock,
+    InterlockKind, OutputDefinition, OutputDefinitions, OutputName, OutputState, OverridePriority,
+    Probability, Profile, ProfileName, Ramp, Scene, SceneName, ScheduledActivation,
+    ScheduledActivations, Weekdays,
 };
+use crate::domain::pid::{Gains, PidSettings};
+use crate::domain::regulation::{Direction, DryRunProtection, Quantity, Regulation, Setpoint};
 use crate::domain::sensors::{Distance, SensorName, WaterLevelSensorDefinitions};
+use crate::domain::solar::{Location, SolarEvent};
+use crate::domain::time::Timezone;
+use crate::domain::top_off::TopOff;
+use crate::domain::{Pwm, PwmKind};
 use crate::errors::Error;
 use crate::{
     config::Config,
@@ -11,10 +20,13 @@ use crate::{
     errors::Result,
 };
 use anyhow::anyhow;
-use chrono::NaiveTime;
+use chrono::{DateTime, NaiveDate, NaiveTime, TimeDelta, Utc, Weekday};
 use lazy_static::lazy_static;
 use serde::Deserialize;
 
+const DEFAULT_SOFTWARE_PWM_FREQUENCY: f64 = 100.0;
+const DEFAULT_HARDWARE_PWM_FREQUENCY: f64 = 1000.0;
+
 lazy_static! {
     pub static ref DURATION_PARSER: duration_parser::Parser = make_parser().unwrap();
 }
@@ -22,9 +34,29 @@ lazy_static! {
 pub fn load(config: &str) -> Result<Config> {
     let config: SerializedConfig = toml::from_str(config)?;
 
+    let location = match &config.location {
+        Some(location) => Some(Location::new(location.latitude, location.longitude)?),
+        None => None,
+    };
+
     let mut output_definitions = vec![];
     for output in &config.outputs {
-        output_definitions.push(OutputDefinition::try_from(output)?);
+        output_definitions.push(output_definition(output, location.as_ref())?);
+    }
+
+    let mut interlocks = vec![];
+    for interlock in &config.interlocks {
+        interlocks.push(Interlock::try_from(interlock)?);
+    }
+
+    let mut scenes = vec![];
+    for scene in &config.scenes {
+        scenes.push(Scene::try_from(scene)?);
+    }
+
+    let mut profiles = vec![];
+    for profile in &config.profiles {
+        profiles.push(Profile::try_from(profile)?);
     }
 
     let mut water_level_sensors = vec![];
@@ -37,12 +69,60 @@ pub fn load(config: &str) -> Result<Config> {
         None => None,
     };
 
-    Config::new(
+    for output in &output_definitions {
+        if let Some(regulation) = output.regulation() {
+            if Some(regulation.sensor()) != aht_20.as_ref() {
+                return Err(anyhow!(
+                    "regulation refers to an unknown sensor '{}', only the AHT20 sensor measures temperature and humidity",
+                    regulation.sensor()
+                ));
+            }
+        }
+
+        if let Some(protection) = output.dry_run_protection() {
+            if !water_level_sensors
+                .iter()
+                .any(|v| v.name() == protection.sensor())
+            {
+                return Err(anyhow!(
+                    "dry-run protection refers to an unknown water level sensor '{}'",
+                    protection.sensor()
+                ));
+            }
+        }
+
+        if let Some(top_off) = output.top_off() {
+            if !water_level_sensors
+                .iter()
+                .any(|v| v.name() == top_off.sensor())
+            {
+                return Err(anyhow!(
+                    "top-off refers to an unknown water level sensor '{}'",
+                    top_off.sensor()
+                ));
+            }
+        }
+    }
+
+    let mut result = Config::new(
         config.address,
-        OutputDefinitions::new(&output_definitions)?,
+        OutputDefinitions::new(&output_definitions)?
+            .with_interlocks(&interlocks)?
+            .with_scenes(&scenes)?
+            .with_profiles(&profiles)?,
         WaterLevelSensorDefinitions::new(&water_level_sensors)?,
         aht_20,
-    )
+    )?;
+
+    if let Some(state_file) = config.state_file {
+        result = result.with_state_file(state_file);
+    }
+
+    if let Some(timezone) = config.timezone {
+        result = result.with_timezone(Timezone::new(&timezone)?);
+    }
+
+    Ok(result)
 }
 
 #[derive(Deserialize)]
@@ -51,56 +131,659 @@ struct SerializedConfig {
     outputs: Vec<SerializedOutput>,
     water_level_sensors: Vec<SerializedWaterLevelSensor>,
     aht_20: Option<String>,
+    location: Option<SerializedLocation>,
+    #[serde(default)]
+    interlocks: Vec<SerializedInterlock>,
+    #[serde(default)]
+    scenes: Vec<SerializedScene>,
+    #[serde(default)]
+    profiles: Vec<SerializedProfile>,
+    state_file: Option<String>,
+    timezone: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SerializedScene {
+    name: String,
+    #[serde(rename = "for")]
+    for_string: String,
+    priority: Option<u32>,
+    outputs: Vec<SerializedSceneOutput>,
+}
+
+#[derive(Deserialize)]
+struct SerializedSceneOutput {
+    name: String,
+    state: String,
+}
+
+impl TryFrom<&SerializedScene> for Scene {
+    type Error = Error;
+
+    fn try_from(value: &SerializedScene) -> std::result::Result<Self, Self::Error> {
+        let mut states = vec![];
+        for output in &value.outputs {
+            states.push((OutputName::new(&output.name)?, parse_state(&output.state)?));
+        }
+
+        Ok(Scene::new(
+            SceneName::new(&value.name)?,
+            parse_duration(&value.for_string)?,
+            &states,
+        )?
+        .with_priority(OverridePriority::new(value.priority.unwrap_or_default())))
+    }
+}
+
+#[derive(Deserialize)]
+struct SerializedProfile {
+    name: String,
+    from: Option<String>,
+    until: Option<String>,
+}
+
+impl TryFrom<&SerializedProfile> for Profile {
+    type Error = Error;
+
+    fn try_from(value: &SerializedProfile) -> std::result::Result<Self, Self::Error> {
+        let profile = Profile::new(ProfileName::new(&value.name)?);
+        match (&value.from, &value.until) {
+            (Some(from), Some(until)) => Ok(profile.with_dates(DateRange::new(
+                parse_day_of_year(from)?,
+                parse_day_of_year(until)?,
+            )?)),
+            (None, None) => Ok(profile),
+            _ => Err(anyhow!(
+                "from and until should either be both set or both shouldn't be set"
+            )),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct SerializedInterlock {
+    output: String,
+    excludes: Option<String>,
+    requires: Option<String>,
+}
+
+impl TryFrom<&SerializedInterlock> for Interlock {
+    type Error = Error;
+
+    fn try_from(value: &SerializedInterlock) -> std::result::Result<Self, Self::Error> {
+        let (kind, other) = match (&value.excludes, &value.requires) {
+            (Some(other), None) => (InterlockKind::Excludes, other),
+            (None, Some(other)) => (InterlockKind::Requires, other),
+            _ => {
+                return Err(anyhow!(
+                    "an interlock should either exclude or require another output"
+                ))
+            }
+        };
+        Interlock::new(
+            OutputName::new(&value.output)?,
+            kind,
+            OutputName::new(other)?,
+        )
+    }
+}
+
+#[derive(Deserialize)]
+struct SerializedLocation {
+    latitude: f64,
+    longitude: f64,
 }
 
 #[derive(Deserialize)]
 struct SerializedOutput {
     name: String,
     pin: u8,
+    pwm: Option<String>,
+    pwm_frequency: Option<f64>,
+    max_on_time: Option<String>,
+    daily_budget: Option<String>,
+    min_on: Option<String>,
+    min_off: Option<String>,
     #[serde(default)]
     activations: Vec<SerializedScheduledActivation>,
+    #[serde(default)]
+    cycles: Vec<SerializedCycle>,
+    #[serde(default)]
+    profiles: Vec<SerializedOutputProfile>,
+    regulation: Option<SerializedRegulation>,
+    dry_run_protection: Option<SerializedDryRunProtection>,
+    top_off: Option<SerializedTopOff>,
+    #[serde(default)]
+    active_low: bool,
+    safe_state: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SerializedOutputProfile {
+    name: String,
+    #[serde(default)]
+    activations: Vec<SerializedScheduledActivation>,
+    #[serde(default)]
+    cycles: Vec<SerializedCycle>,
 }
 
-impl TryFrom<&SerializedOutput> for OutputDefinition {
+#[derive(Deserialize)]
+struct SerializedTopOff {
+    sensor: String,
+    low: f32,
+    high: f32,
+    max_fill_time: String,
+    min_rise: Option<f32>,
+    min_rise_within: Option<String>,
+}
+
+impl TryFrom<&SerializedTopOff> for TopOff {
     type Error = Error;
 
-    fn try_from(value: &SerializedOutput) -> std::result::Result<Self, Self::Error> {
-        let mut activations_vec = vec![];
-        for activation in &value.activations {
-            let err = Err(anyhow!(
-                "start_every and times should either be both set or both shouldn't be set"
-            ));
-            let when = NaiveTime::parse_from_str(&activation.when, "%H:%M:%S")?;
-            let duration = DURATION_PARSER.parse(&activation.for_string)?;
-            let new_activation = ScheduledActivation::new(when, duration.as_secs() as u32)?;
-
-            match &activation.start_every {
-                Some(start_every) => match &activation.times {
-                    Some(times) => {
-                        let start_every = DURATION_PARSER.parse(start_every)?;
-                        activations_vec.append(
-                            &mut new_activation.repeat(start_every.as_secs() as u32, *times)?,
-                        );
-                    }
-                    None => {
-                        return err;
-                    }
-                },
-                None => match &activation.times {
-                    Some(_times) => return err,
-                    None => {
-                        activations_vec.push(new_activation);
+    fn try_from(value: &SerializedTopOff) -> std::result::Result<Self, Self::Error> {
+        let top_off = TopOff::new(
+            SensorName::new(&value.sensor)?,
+            value.low,
+            value.high,
+            DURATION_PARSER.parse(&value.max_fill_time)?.as_secs() as u32,
+        )?;
+
+        match (value.min_rise, &value.min_rise_within) {
+            (Some(min_rise), Some(within)) => {
+                top_off.with_min_rise(min_rise, DURATION_PARSER.parse(within)?.as_secs() as u32)
+            }
+            (None, None) => Ok(top_off),
+            _ => Err(anyhow!(
+                "min_rise and min_rise_within must be specified together"
+            )),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct SerializedDryRunProtection {
+    sensor: String,
+    min_water_level: f32,
+    #[serde(default)]
+    hysteresis: f32,
+}
+
+impl TryFrom<&SerializedDryRunProtection> for DryRunProtection {
+    type Error = Error;
+
+    fn try_from(value: &SerializedDryRunProtection) -> std::result::Result<Self, Self::Error> {
+        DryRunProtection::new(
+            SensorName::new(&value.sensor)?,
+            value.min_water_level,
+            value.hysteresis,
+        )
+    }
+}
+
+#[derive(Deserialize)]
+struct SerializedRegulation {
+    sensor: String,
+    reading: String,
+    direction: Option<String>,
+    setpoint: f32,
+    #[serde(default)]
+    hysteresis: f32,
+    #[serde(default)]
+    windows: Vec<SerializedSetpointWindow>,
+    pid: Option<SerializedPid>,
+}
+
+#[derive(Deserialize)]
+struct SerializedSetpointWindow {
+    from: String,
+    until: String,
+    setpoint: f32,
+    #[serde(default)]
+    hysteresis: f32,
+}
+
+#[derive(Deserialize)]
+struct SerializedPid {
+    proportional: f64,
+    #[serde(default)]
+    integral: f64,
+    #[serde(default)]
+    derivative: f64,
+    window: String,
+}
+
+impl TryFrom<&SerializedRegulation> for Regulation {
+    type Error = Error;
+
+    fn try_from(value: &SerializedRegulation) -> std::result::Result<Self, Self::Error> {
+        let quantity = match value.reading.as_str() {
+            "temperature" => Quantity::Temperature,
+            "humidity" => Quantity::Humidity,
+            other => return Err(anyhow!("unknown reading '{other}'")),
+        };
+
+        let direction = match value.direction.as_deref() {
+            Some("raise") | None => Direction::Raise,
+            Some("lower") => Direction::Lower,
+            Some(other) => return Err(anyhow!("unknown direction '{other}'")),
+        };
+
+        let mut regulation = Regulation::new(
+            SensorName::new(&value.sensor)?,
+            quantity,
+            direction,
+            Setpoint::new(value.setpoint, value.hysteresis)?,
+        );
+        for window in &value.windows {
+            regulation = regulation.with_window(
+                NaiveTime::parse_from_str(&window.from, "%H:%M:%S")?,
+                NaiveTime::parse_from_str(&window.until, "%H:%M:%S")?,
+                Setpoint::new(window.setpoint, window.hysteresis)?,
+            )?;
+        }
+
+        if let Some(pid) = &value.pid {
+            regulation = regulation.with_pid(PidSettings::new(
+                Gains::new(pid.proportional, pid.integral, pid.derivative)?,
+                DURATION_PARSER.parse(&pid.window)?.as_secs() as u32,
+            )?);
+        }
+
+        Ok(regulation)
+    }
+}
+
+#[derive(Deserialize)]
+struct SerializedCycle {
+    on: String,
+    off: String,
+    between: Option<Vec<String>>,
+}
+
+impl TryFrom<&SerializedCycle> for Cycle {
+    type Error = Error;
+
+    fn try_from(value: &SerializedCycle) -> std::result::Result<Self, Self::Error> {
+        let cycle = Cycle::new(parse_duration(&value.on)?, parse_duration(&value.off)?)?;
+
+        match value.between.as_deref() {
+            Some([from, until]) => cycle.with_window(
+                NaiveTime::parse_from_str(from, "%H:%M:%S")?,
+                NaiveTime::parse_from_str(until, "%H:%M:%S")?,
+            ),
+            Some(_) => Err(anyhow!(
+                "between should be a start and an end e.g. [\"08:00:00\", \"20:00:00\"]"
+            )),
+            None => Ok(cycle),
+        }
+    }
+}
+
+fn output_definition(
+    value: &SerializedOutput,
+    location: Option<&Location>,
+) -> Result<OutputDefinition> {
+    let mut definition = OutputDefinition::new(
+        OutputName::new(&value.name)?,
+        PinNumber::new(value.pin)?,
+        scheduled_activations(&value.name, &value.activations, &value.cycles, location)?,
+    );
+
+    for profile in &value.profiles {
+        // the randomised activations of each profile should differ from the default ones
+        let seed_name = format!("{} {}", value.name, profile.name);
+        definition = definition.with_profile(
+            ProfileName::new(&profile.name)?,
+            scheduled_activations(&seed_name, &profile.activations, &profile.cycles, location)?,
+        )?;
+    }
+
+    if let Some(max_on_time) = &value.max_on_time {
+        definition =
+            definition.with_max_on_time(DURATION_PARSER.parse(max_on_time)?.as_secs() as u32)?;
+    }
+
+    if let Some(daily_budget) = &value.daily_budget {
+        definition =
+            definition.with_daily_budget(DURATION_PARSER.parse(daily_budget)?.as_secs() as u32)?;
+    }
+
+    if let Some(regulation) = &value.regulation {
+        definition = definition.with_regulation(Regulation::try_from(regulation)?);
+    }
+
+    if let Some(protection) = &value.dry_run_protection {
+        definition = definition.with_dry_run_protection(DryRunProtection::try_from(protection)?);
+    }
+
+    if let Some(top_off) = &value.top_off {
+        definition = definition.with_top_off(TopOff::try_from(top_off)?);
+    }
+
+    if let Some(min_on) = &value.min_on {
+        definition = definition.with_min_on_time(DURATION_PARSER.parse(min_on)?.as_secs() as u32)?;
+    }
+
+    if let Some(min_off) = &value.min_off {
+        definition =
+            definition.with_min_off_time(DURATION_PARSER.parse(min_off)?.as_secs() as u32)?;
+    }
+
+    if value.active_low {
+        definition = definition.with_active_low();
+    }
+
+    if let Some(safe_state) = &value.safe_state {
+        definition = definition.with_safe_state(parse_state(safe_state)?)?;
+    }
+
+    match pwm(value)? {
+        Some(pwm) => Ok(definition.with_pwm(pwm)),
+        None => Ok(definition),
+    }
+}
+
+fn scheduled_activations(
+    seed_name: &str,
+    activations: &[SerializedScheduledActivation],
+    cycles: &[SerializedCycle],
+    location: Option<&Location>,
+) -> Result<ScheduledActivations> {
+    let mut activations_vec = vec![];
+    for (i, activation) in activations.iter().enumerate() {
+        let err = Err(anyhow!(
+            "start_every and times should either be both set or both shouldn't be set"
+        ));
+        let when = parse_activation_time(&activation.when, location)?;
+        let duration = parse_duration(&activation.for_string)?;
+        let mut new_activation =
+            ScheduledActivation::new(when, duration)?.with_days(activation_days(activation)?);
+
+        if let Some(ramp) = &activation.ramp {
+            let ramp_when = match &ramp.when {
+                Some(ramp_when) => parse_activation_time(ramp_when, location)?,
+                None => when,
+            };
+            let ramp_duration = match &ramp.for_string {
+                Some(ramp_for) => parse_duration(ramp_for)?,
+                None => duration,
+            };
+            new_activation = new_activation.with_ramp(Ramp::new(
+                NaiveDate::parse_from_str(&ramp.from, "%Y-%m-%d")?,
+                NaiveDate::parse_from_str(&ramp.until, "%Y-%m-%d")?,
+                ramp_when,
+                ramp_duration,
+            )?)?;
+        }
+
+        if let Some(brightness) = &activation.brightness {
+            new_activation = new_activation.with_brightness(parse_brightness(brightness)?);
+        }
+
+        if activation.fade_in.is_some() || activation.fade_out.is_some() {
+            let fade_in = match &activation.fade_in {
+                Some(fade_in) => parse_duration(fade_in)?,
+                None => TimeDelta::zero(),
+            };
+            let fade_out = match &activation.fade_out {
+                Some(fade_out) => parse_duration(fade_out)?,
+                None => TimeDelta::zero(),
+            };
+            new_activation = new_activation.with_fade(fade_in, fade_out)?;
+        }
+
+        if let Some(jitter) = &activation.jitter {
+            new_activation = new_activation.with_jitter(parse_duration(jitter)?)?;
+        }
+
+        if let Some(probability) = activation.probability {
+            new_activation = new_activation.with_probability(Probability::new(probability)?);
+        }
+
+        let seed = if activation.jitter.is_some() || activation.probability.is_some() {
+            let seed = match activation.seed {
+                Some(seed) => seed,
+                None => default_seed(seed_name, i),
+            };
+            new_activation = new_activation.with_seed(seed);
+            Some(seed)
+        } else if activation.seed.is_some() {
+            return Err(anyhow!("seed makes no sense without jitter or probability"));
+        } else {
+            None
+        };
+
+        match &activation.start_every {
+            Some(start_every) => match &activation.times {
+                Some(times) => {
+                    let start_every = parse_duration(start_every)?;
+                    let repetitions = new_activation.repeat(start_every, *times)?;
+                    for (j, repetition) in repetitions.into_iter().enumerate() {
+                        // otherwise all repetitions would jitter by the same offset and be
+                        // skipped together
+                        activations_vec.push(match seed {
+                            Some(seed) => repetition.with_seed(repetition_seed(seed, j)),
+                            None => repetition,
+                        });
                     }
-                },
+                }
+                None => {
+                    return err;
+                }
+            },
+            None => match &activation.times {
+                Some(_times) => return err,
+                None => {
+                    activations_vec.push(new_activation);
+                }
+            },
+        }
+    }
+
+    for cycle in cycles {
+        activations_vec.append(&mut Cycle::try_from(cycle)?.expand()?);
+    }
+
+    ScheduledActivations::new(&activations_vec)
+}
+
+// Stable across restarts so that the randomised activations don't change when the program is
+// restarted in the middle of the day. FNV-1a, as DefaultHasher makes no such promises.
+fn default_seed(output_name: &str, activation_index: usize) -> u64 {
+    fnv(output_name
+        .bytes()
+        .chain((activation_index as u64).to_le_bytes()))
+}
+
+// The first repetition keeps the seed of the activation itself.
+fn repetition_seed(seed: u64, repetition: usize) -> u64 {
+    if repetition == 0 {
+        return seed;
+    }
+    fnv(seed
+        .to_le_bytes()
+        .into_iter()
+        .chain((repetition as u64).to_le_bytes()))
+}
+
+fn fnv(bytes: impl Iterator<Item = u8>) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+fn pwm(value: &SerializedOutput) -> Result<Option<Pwm>> {
+    let kind = match value.pwm.as_deref() {
+        Some("software") => PwmKind::Software,
+        Some("hardware") => PwmKind::Hardware,
+        Some(other) => return Err(anyhow!("unknown pwm kind '{other}'")),
+        None => {
+            if value.pwm_frequency.is_some() {
+                return Err(anyhow!("pwm_frequency makes no sense without pwm"));
+            }
+            return Ok(None);
+        }
+    };
+
+    let frequency = match (value.pwm_frequency, kind) {
+        (Some(frequency), _) => frequency,
+        (None, PwmKind::Software) => DEFAULT_SOFTWARE_PWM_FREQUENCY,
+        (None, PwmKind::Hardware) => DEFAULT_HARDWARE_PWM_FREQUENCY,
+    };
+
+    Ok(Some(Pwm::new(kind, frequency)?))
+}
+
+pub fn format_state(state: &OutputState) -> String {
+    match state {
+        OutputState::On => "on".to_string(),
+        OutputState::Off => "off".to_string(),
+        // the brightness is stored as a float so e.g. 40% would come out as 40.000004%
+        OutputState::Dimmed(brightness) => format!("{:.1}%", brightness.fraction() * 100.0),
+    }
+}
+
+pub fn parse_state(s: &str) -> Result<OutputState> {
+    match s.to_uppercase().as_str() {
+        "ON" => Ok(OutputState::On),
+        "OFF" => Ok(OutputState::Off),
+        v if v.ends_with('%') => Ok(parse_brightness(v)?.into()),
+        _ => Err(anyhow!("invalid state")),
+    }
+}
+
+// Start of an override given either as a clock time, in which case it's the next time the clock
+// shows it, an RFC 3339 datetime or a duration from now.
+pub fn parse_override_start(
+    s: &str,
+    now: &DateTime<Utc>,
+    timezone: &Timezone,
+) -> Result<DateTime<Utc>> {
+    let s = s.trim();
+
+    for format in ["%H:%M:%S", "%H:%M"] {
+        if let Ok(time) = NaiveTime::parse_from_str(s, format) {
+            let wall_clock = timezone.wall_clock(now);
+            let mut start = wall_clock.date().and_time(time);
+            if start < wall_clock {
+                start += TimeDelta::days(1);
             }
+            return Ok(timezone.resolve(&start).to_utc().max(*now));
         }
+    }
 
-        Ok(Self::new(
-            OutputName::new(&value.name)?,
-            PinNumber::new(value.pin)?,
-            ScheduledActivations::new(&activations_vec)?,
-        ))
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(s) {
+        let start = datetime.to_utc();
+        if start < *now {
+            return Err(anyhow!("override can't start in the past"));
+        }
+        return Ok(start);
     }
+
+    let duration = DURATION_PARSER
+        .parse(s)
+        .map_err(|_| anyhow!("start must be a clock time, an RFC 3339 datetime or a duration"))?;
+    Ok(*now + TimeDelta::from_std(duration)?)
+}
+
+pub fn parse_brightness(s: &str) -> Result<Brightness> {
+    let percentage = s
+        .strip_suffix('%')
+        .ok_or(anyhow!("brightness must be a percentage e.g. '40%'"))?
+        .trim()
+        .parse::<f32>()?;
+    Brightness::new(percentage / 100.0)
+}
+
+fn activation_days(activation: &SerializedScheduledActivation) -> Result<ActivationDays> {
+    let weekdays = match &activation.days {
+        Some(days) => {
+            let mut weekdays = vec![];
+            for day in days {
+                weekdays.push(
+                    day.parse::<Weekday>()
+                        .map_err(|_| anyhow!("invalid day of the week '{day}'"))?,
+                );
+            }
+            Weekdays::new(&weekdays)?
+        }
+        None => Weekdays::all(),
+    };
+
+    let date_range = match (&activation.from, &activation.until) {
+        (Some(from), Some(until)) => Some(DateRange::new(
+            parse_day_of_year(from)?,
+            parse_day_of_year(until)?,
+        )?),
+        (None, None) => None,
+        _ => {
+            return Err(anyhow!(
+                "from and until should either be both set or both shouldn't be set"
+            ))
+        }
+    };
+
+    let every = match &activation.every {
+        Some(every) => {
+            let every = DURATION_PARSER.parse(every)?;
+            let day = Duration::from_secs(24 * 60 * 60);
+            if every.as_nanos() % day.as_nanos() != 0 {
+                return Err(anyhow!("every must be a whole number of days"));
+            }
+            let starting = match &activation.starting {
+                Some(starting) => NaiveDate::parse_from_str(starting, "%Y-%m-%d")?,
+                None => NaiveDate::default(), // the unix epoch
+            };
+            Some(EveryNDays::new(
+                (every.as_secs() / day.as_secs()).try_into()?,
+                starting,
+            )?)
+        }
+        None => match &activation.starting {
+            Some(_) => return Err(anyhow!("starting makes no sense without every")),
+            None => None,
+        },
+    };
+
+    Ok(ActivationDays::new(weekdays, date_range, every))
+}
+
+fn parse_day_of_year(s: &str) -> Result<DayOfYear> {
+    let (month, day) = s
+        .split_once('-')
+        .ok_or(anyhow!("invalid day '{s}', use e.g. '03-31'"))?;
+    DayOfYear::new(month.parse()?, day.parse()?)
+}
+
+fn parse_activation_time(s: &str, location: Option<&Location>) -> Result<ActivationTime> {
+    let (event, offset) = if let Some(offset) = s.strip_prefix("sunrise") {
+        (SolarEvent::Sunrise, offset)
+    } else if let Some(offset) = s.strip_prefix("sunset") {
+        (SolarEvent::Sunset, offset)
+    } else {
+        return Ok(NaiveTime::parse_from_str(s, "%H:%M:%S")?.into());
+    };
+
+    let location = location.ok_or(anyhow!(
+        "activations relative to sunrise or sunset require the location to be configured"
+    ))?;
+
+    let offset = if offset.is_empty() {
+        TimeDelta::zero()
+    } else if let Some(offset) = offset.strip_prefix(" + ") {
+        TimeDelta::from_std(DURATION_PARSER.parse(offset)?)?
+    } else if let Some(offset) = offset.strip_prefix(" - ") {
+        -TimeDelta::from_std(DURATION_PARSER.parse(offset)?)?
+    } else {
+        return Err(anyhow!(
+            "invalid offset, use e.g. 'sunrise + 30 minutes' or 'sunset - 1 hour'"
+        ));
+    };
+
+    ActivationTime::new_solar(event, offset, *location)
 }
 
 #[derive(Deserialize)]
@@ -111,6 +794,31 @@ struct SerializedScheduledActivation {
 
     start_every: Option<String>,
     times: Option<u32>,
+
+    days: Option<Vec<String>>,
+    from: Option<String>,
+    until: Option<String>,
+    every: Option<String>,
+    starting: Option<String>,
+
+    ramp: Option<SerializedRamp>,
+
+    brightness: Option<String>,
+    fade_in: Option<String>,
+    fade_out: Option<String>,
+
+    jitter: Option<String>,
+    probability: Option<f64>,
+    seed: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct SerializedRamp {
+    from: String,
+    until: String,
+    when: Option<String>,
+    #[serde(rename = "for")]
+    for_string: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -136,9 +844,22 @@ impl TryFrom<&SerializedWaterLevelSensor> for WaterLevelSensorDefinition {
     }
 }
 
+// Activations are precise down to a millisecond, unlike most other durations which are only
+// precise down to a second.
+fn parse_duration(s: &str) -> Result<TimeDelta> {
+    Ok(TimeDelta::from_std(DURATION_PARSER.parse(s)?)?)
+}
+
 fn make_parser() -> Result<duration_parser::Parser> {
     Ok(duration_parser::Parser::new(
         duration_parser::Config::new(duration_parser::Units::new(&[
+            duration_parser::Unit::new(
+                duration_parser::UnitMagnitude::new(Duration::from_millis(1))?,
+                &[
+                    duration_parser::UnitName::new("millisecond".to_string())?,
+                    duration_parser::UnitName::new("milliseconds".to_string())?,
+                ],
+            )?,
             duration_parser::Unit::new(
                 duration_parser::UnitMagnitude::new(Duration::from_secs(1))?,
                 &[
@@ -160,6 +881,13 @@ fn make_parser() -> Result<duration_parser::Parser> {
                     duration_parser::UnitName::new("hours".to_string())?,
                 ],
             )?,
+            duration_parser::Unit::new(
+                duration_parser::UnitMagnitude::new(Duration::from_secs(24 * 60 * 60))?,
+                &[
+                    duration_parser::UnitName::new("day".to_string())?,
+                    duration_parser::UnitName::new("days".to_string())?,
+                ],
+            )?,
         ])?)?
         .with_policy_for_spaces_between_value_and_unit(duration_parser::SpacePolicy::RequireOne)
         .with_policy_for_spaces_between_components(duration_parser::SpacePolicy::RequireOne),
@@ -192,16 +920,22 @@ mod tests {
                             vec![
                                 ScheduledActivation::new(
                                     NaiveTime::from_hms_opt(17, 30, 00).unwrap(),
-                                    600,
+                                    TimeDelta::seconds(600),
                                 )?,
                                 ScheduledActivation::new(
                                     NaiveTime::from_hms_opt(18, 00, 00).unwrap(),
-                                    600,
+                                    TimeDelta::seconds(600),
                                 )?,
                             ]
                             .as_ref(),
                         )?,
-                    ),
+                    )
+                    .with_max_on_time(15 * 60)?
+                    .with_daily_budget(60 * 60)?
+                    .with_min_on_time(30)?
+                    .with_min_off_time(60)?
+                    .with_active_low()
+                    .with_safe_state(OutputState::On)?,
                     OutputDefinition::new(
                         OutputName::new("Output 2")?,
                         PinNumber::new(28)?,
@@ -209,24 +943,227 @@ mod tests {
                             vec![
                                 ScheduledActivation::new(
                                     NaiveTime::from_hms_opt(17, 30, 00).unwrap(),
-                                    30,
+                                    TimeDelta::seconds(30),
                                 )?,
                                 ScheduledActivation::new(
                                     NaiveTime::from_hms_opt(18, 30, 00).unwrap(),
-                                    60 * 60 + 10 * 60 + 20,
+                                    TimeDelta::seconds(60 * 60 + 10 * 60 + 20),
                                 )?,
+                                ScheduledActivation::new(
+                                    NaiveTime::from_hms_opt(20, 00, 00).unwrap(),
+                                    TimeDelta::milliseconds(1500),
+                                )?,
+                                ScheduledActivation::new(
+                                    NaiveTime::from_hms_opt(21, 00, 00).unwrap(),
+                                    TimeDelta::seconds(5 * 60),
+                                )?
+                                .with_days(ActivationDays::new(
+                                    Weekdays::new(&[Weekday::Mon, Weekday::Thu])?,
+                                    Some(DateRange::new(
+                                        DayOfYear::new(11, 1)?,
+                                        DayOfYear::new(3, 31)?,
+                                    )?),
+                                    None,
+                                )),
+                                ScheduledActivation::new(
+                                    NaiveTime::from_hms_opt(22, 00, 00).unwrap(),
+                                    TimeDelta::seconds(15 * 60),
+                                )?
+                                .with_days(ActivationDays::new(
+                                    Weekdays::all(),
+                                    None,
+                                    Some(EveryNDays::new(
+                                        3,
+                                        NaiveDate::from_ymd_opt(2025, 1, 1).unwrap(),
+                                    )?),
+                                )),
+                                ScheduledActivation::new(
+                                    NaiveTime::from_hms_opt(6, 00, 00).unwrap(),
+                                    TimeDelta::seconds(60),
+                                )?
+                                .with_jitter(TimeDelta::seconds(15 * 60))?
+                                .with_probability(Probability::new(0.7)?)
+                                .with_seed(42),
                             ]
                             .as_ref(),
                         )?,
-                    ),
+                    )
+                    .with_profile(
+                        ProfileName::new("vacation")?,
+                        ScheduledActivations::new(&[])?,
+                    )?
+                    .with_dry_run_protection(DryRunProtection::new(
+                        SensorName::new("Water level sensor")?,
+                        20.0,
+                        5.0,
+                    )?),
                     OutputDefinition::new(
                         OutputName::new("Output 3")?,
                         PinNumber::new(29)?,
-                        ScheduledActivations::new(vec![].as_ref())?,
+                        ScheduledActivations::new(
+                            vec![ScheduledActivation::new(
+                                ActivationTime::new_solar(
+                                    SolarEvent::Sunrise,
+                                    TimeDelta::minutes(30),
+                                    Location::new(1.35, 103.82)?,
+                                )?,
+                                TimeDelta::seconds(11 * 60 * 60),
+                            )?]
+                            .as_ref(),
+                        )?,
+                    )
+                    .with_profile(
+                        ProfileName::new("dry_season")?,
+                        ScheduledActivations::new(&[ScheduledActivation::new(
+                            ActivationTime::new_solar(
+                                SolarEvent::Sunrise,
+                                TimeDelta::hours(1),
+                                Location::new(1.35, 103.82)?,
+                            )?,
+                            TimeDelta::hours(10),
+                        )?])?,
+                    )?,
+                    OutputDefinition::new(
+                        OutputName::new("Output 4")?,
+                        PinNumber::new(30)?,
+                        ScheduledActivations::new(
+                            vec![ScheduledActivation::new(
+                                NaiveTime::from_hms_opt(8, 00, 00).unwrap(),
+                                TimeDelta::seconds(10 * 60 * 60),
+                            )?
+                            .with_ramp(Ramp::new(
+                                NaiveDate::from_ymd_opt(2025, 10, 1).unwrap(),
+                                NaiveDate::from_ymd_opt(2025, 11, 12).unwrap(),
+                                NaiveTime::from_hms_opt(7, 00, 00).unwrap(),
+                                TimeDelta::seconds(12 * 60 * 60),
+                            )?)?]
+                            .as_ref(),
+                        )?,
+                    ),
+                    OutputDefinition::new(
+                        OutputName::new("Output 5")?,
+                        PinNumber::new(12)?,
+                        ScheduledActivations::new(
+                            vec![ScheduledActivation::new(
+                                NaiveTime::from_hms_opt(9, 00, 00).unwrap(),
+                                TimeDelta::seconds(8 * 60 * 60),
+                            )?
+                            .with_brightness(Brightness::new(0.6)?)
+                            .with_fade(TimeDelta::seconds(30 * 60), TimeDelta::seconds(60 * 60))?]
+                            .as_ref(),
+                        )?,
+                    )
+                    .with_pwm(Pwm::new(PwmKind::Hardware, 500.0)?),
+                    OutputDefinition::new(
+                        OutputName::new("Output 6")?,
+                        PinNumber::new(31)?,
+                        ScheduledActivations::new(
+                            [
+                                Cycle::new(
+                                    TimeDelta::seconds(10 * 60),
+                                    TimeDelta::seconds(50 * 60),
+                                )?
+                                .with_window(
+                                    NaiveTime::from_hms_opt(8, 00, 00).unwrap(),
+                                    NaiveTime::from_hms_opt(20, 00, 00).unwrap(),
+                                )?
+                                .expand()?,
+                                Cycle::new(
+                                    TimeDelta::seconds(5 * 60),
+                                    TimeDelta::seconds(55 * 60),
+                                )?
+                                .with_window(
+                                    NaiveTime::from_hms_opt(20, 00, 00).unwrap(),
+                                    NaiveTime::from_hms_opt(8, 00, 00).unwrap(),
+                                )?
+                                .expand()?,
+                            ]
+                            .concat()
+                            .as_ref(),
+                        )?,
+                    ),
+                    OutputDefinition::new(
+                        OutputName::new("Output 7")?,
+                        PinNumber::new(32)?,
+                        ScheduledActivations::new(&[])?,
+                    )
+                    .with_regulation(
+                        Regulation::new(
+                            SensorName::new("AHT20 sensor")?,
+                            Quantity::Temperature,
+                            Direction::Raise,
+                            Setpoint::new(24.0, 1.0)?,
+                        )
+                        .with_window(
+                            NaiveTime::from_hms_opt(20, 00, 00).unwrap(),
+                            NaiveTime::from_hms_opt(8, 00, 00).unwrap(),
+                            Setpoint::new(20.0, 1.0)?,
+                        )?,
+                    ),
+                    OutputDefinition::new(
+                        OutputName::new("Output 8")?,
+                        PinNumber::new(33)?,
+                        ScheduledActivations::new(&[])?,
+                    )
+                    .with_regulation(
+                        Regulation::new(
+                            SensorName::new("AHT20 sensor")?,
+                            Quantity::Temperature,
+                            Direction::Raise,
+                            Setpoint::new(26.0, 0.0)?,
+                        )
+                        .with_pid(PidSettings::new(Gains::new(0.3, 0.0005, 0.0)?, 5 * 60)?),
+                    ),
+                    OutputDefinition::new(
+                        OutputName::new("Output 9")?,
+                        PinNumber::new(34)?,
+                        ScheduledActivations::new(&[])?,
+                    )
+                    .with_top_off(
+                        TopOff::new(SensorName::new("Water level sensor")?, 40.0, 60.0, 5 * 60)?
+                            .with_min_rise(2.0, 3 * 60)?,
                     ),
                 ]
                 .as_ref(),
-            )?,
+            )?
+            .with_interlocks(&[
+                Interlock::new(
+                    OutputName::new("Output 1")?,
+                    InterlockKind::Excludes,
+                    OutputName::new("Output 6")?,
+                )?,
+                Interlock::new(
+                    OutputName::new("Output 5")?,
+                    InterlockKind::Requires,
+                    OutputName::new("Output 4")?,
+                )?,
+            ])?
+            .with_scenes(&[
+                Scene::new(
+                    SceneName::new("feeding")?,
+                    TimeDelta::minutes(20),
+                    &[
+                        (OutputName::new("Output 2")?, OutputState::Off),
+                        (OutputName::new("Output 3")?, OutputState::On),
+                    ],
+                )?,
+                Scene::new(
+                    SceneName::new("maintenance")?,
+                    TimeDelta::hours(1),
+                    &[
+                        (OutputName::new("Output 1")?, OutputState::Off),
+                        (OutputName::new("Output 2")?, OutputState::Off),
+                    ],
+                )?
+                .with_priority(OverridePriority::new(100)),
+            ])?
+            .with_profiles(&[
+                Profile::new(ProfileName::new("dry_season")?).with_dates(DateRange::new(
+                    DayOfYear::new(6, 1)?,
+                    DayOfYear::new(9, 30)?,
+                )?),
+                Profile::new(ProfileName::new("vacation")?),
+            ])?,
             WaterLevelSensorDefinitions::new(
                 vec![WaterLevelSensorDefinition::new(
                     SensorName::new("Water level sensor")?,
@@ -238,10 +1175,146 @@ mod tests {
                 .as_ref(),
             )?,
             Some(SensorName::new("AHT20 sensor")?),
-        )?;
+        )?
+        .with_state_file("vivarium_state.toml")
+        .with_timezone(Timezone::new("Asia/Singapore")?);
 
         assert_eq!(config, expected_config);
 
         Ok(())
     }
+
+    #[test]
+    fn test_parse_override_start() -> Result<()> {
+        // noon in Warsaw
+        let now = NaiveDate::from_ymd_opt(2024, 6, 15)
+            .unwrap()
+            .and_hms_opt(10, 0, 0)
+            .unwrap()
+            .and_utc();
+
+        let timezone = Timezone::new("Europe/Warsaw")?;
+
+        assert_eq!(
+            parse_override_start("14:00", &now, &timezone)?,
+            now + TimeDelta::hours(2)
+        );
+        assert_eq!(
+            parse_override_start("11:30:00", &now, &timezone)?,
+            now + TimeDelta::hours(23) + TimeDelta::minutes(30)
+        );
+        assert_eq!(
+            parse_override_start("10 minutes", &now, &timezone)?,
+            now + TimeDelta::minutes(10)
+        );
+        assert_eq!(
+            parse_override_start("2024-06-17T10:00:00Z", &now, &timezone)?,
+            now + TimeDelta::days(2)
+        );
+        assert_eq!(
+            parse_override_start("2024-06-17T12:00:00+02:00", &now, &timezone)?,
+            now + TimeDelta::days(2)
+        );
+        assert!(parse_override_start("2024-06-13T12:00:00+02:00", &now, &timezone).is_err());
+        assert!(parse_override_start("tomorrow-ish", &now, &timezone).is_err());
+
+        // 02:30 happens twice on that day, starting in 10 minutes during the second time means
+        // 02:40 the second time and not the first
+        let repeated = NaiveDate::from_ymd_opt(2024, 10, 27)
+            .unwrap()
+            .and_hms_opt(1, 30, 0)
+            .unwrap()
+            .and_utc();
+        assert_eq!(
+            parse_override_start("10 minutes", &repeated, &timezone)?,
+            repeated + TimeDelta::minutes(10)
+        );
+        assert_eq!(
+            parse_override_start("03:00", &repeated, &timezone)?,
+            repeated + TimeDelta::minutes(30)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_state() -> Result<()> {
+        assert_eq!(format_state(&parse_state("40%")?), "40.0%");
+        for state in ["on", "off", "40.0%", "12.5%", "0.1%", "99.9%"] {
+            assert_eq!(format_state(&parse_state(state)?), state);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_activation_time() -> Result<()> {
+        let location = Location::new(1.35, 103.82)?;
+
+        assert_eq!(
+            parse_activation_time("17:30:00", None)?,
+            ActivationTime::Fixed(NaiveTime::from_hms_opt(17, 30, 00).unwrap())
+        );
+        assert_eq!(
+            parse_activation_time("sunrise", Some(&location))?,
+            ActivationTime::new_solar(SolarEvent::Sunrise, TimeDelta::zero(), location)?
+        );
+        assert_eq!(
+            parse_activation_time("sunrise + 30 minutes", Some(&location))?,
+            ActivationTime::new_solar(SolarEvent::Sunrise, TimeDelta::minutes(30), location)?
+        );
+        assert_eq!(
+            parse_activation_time("sunset - 1 hour", Some(&location))?,
+            ActivationTime::new_solar(SolarEvent::Sunset, TimeDelta::hours(-1), location)?
+        );
+        assert!(parse_activation_time("sunset", None).is_err());
+        assert!(parse_activation_time("sunset 1 hour", Some(&location)).is_err());
+        assert!(parse_activation_time("sunset + 2 days", Some(&location)).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_repeated_seeds() -> Result<()> {
+        let output: SerializedOutput = toml::from_str(
+            r#"
+            name = "Output"
+            pin = 1
+
+            [[activations]]
+            when = "08:00:00"
+            for = "1 minute"
+            start_every = "1 hour"
+            times = 3
+            jitter = "10 minutes"
+            seed = 42
+            "#,
+        )?;
+
+        let seeds: Vec<u64> = (0..3).map(|j| repetition_seed(42, j)).collect();
+        assert_eq!(seeds[0], 42);
+        assert!(seeds[1] != seeds[0] && seeds[2] != seeds[0] && seeds[2] != seeds[1]);
+
+        let activations = ScheduledActivation::new(
+            NaiveTime::from_hms_opt(8, 0, 0).unwrap(),
+            TimeDelta::minutes(1),
+        )?
+        .with_jitter(TimeDelta::minutes(10))?
+        .with_seed(42)
+        .repeat(TimeDelta::hours(1), 3)?
+        .into_iter()
+        .zip(seeds)
+        .map(|(activation, seed)| activation.with_seed(seed))
+        .collect::<Vec<_>>();
+        assert_eq!(
+            output_definition(&output, None)?,
+            OutputDefinition::new(
+                OutputName::new("Output")?,
+                PinNumber::new(1)?,
+                ScheduledActivations::new(&activations)?,
+            )
+        );
+
+        Ok(())
+    }
 }
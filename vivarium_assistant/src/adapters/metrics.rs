@@ -1,17 +1,24 @@
 use crate::{
     domain::{
-        outputs::{OutputName, OutputState},
+        outputs::{Limit, OutputName, OutputState, OverrideStatus, Profile, ProfileName},
         sensors::{Humidity, SensorName, Temperature, WaterLevel},
+        top_off::TopOffFailure,
     },
     errors::Result,
 };
-use chrono::Utc;
+use chrono::{TimeDelta, Utc};
 use prometheus::{labels, Gauge, GaugeVec, Opts, Registry};
 
 #[derive(Clone)]
 pub struct Metrics {
     registry: prometheus::Registry,
     output_gauge: GaugeVec,
+    output_limit_gauge: GaugeVec,
+    top_off_failure_gauge: GaugeVec,
+    override_gauge: GaugeVec,
+    on_today_gauge: GaugeVec,
+    hold_gauge: Gauge,
+    profile_gauge: GaugeVec,
     water_level_gauge: GaugeVec,
     temperature_gauge: GaugeVec,
     humidity_gauge: GaugeVec,
@@ -25,6 +32,51 @@ impl Metrics {
         let output_gauge = GaugeVec::new(Opts::new("outputs", "state of the outputs"), &["name"])?;
         registry.register(Box::new(output_gauge.clone()))?;
 
+        let output_limit_gauge = GaugeVec::new(
+            Opts::new(
+                "output_limits_exceeded",
+                "outputs kept off because they exceeded one of their limits",
+            ),
+            &["name", "limit"],
+        )?;
+        registry.register(Box::new(output_limit_gauge.clone()))?;
+
+        let top_off_failure_gauge = GaugeVec::new(
+            Opts::new(
+                "top_off_failures",
+                "outputs which stopped topping off the water because something went wrong",
+            ),
+            &["name", "reason"],
+        )?;
+        registry.register(Box::new(top_off_failure_gauge.clone()))?;
+
+        let override_gauge = GaugeVec::new(
+            Opts::new(
+                "override_priorities",
+                "priority of the override in effect for each output, -1 if there is none",
+            ),
+            &["name"],
+        )?;
+        registry.register(Box::new(override_gauge.clone()))?;
+
+        let on_today_gauge = GaugeVec::new(
+            Opts::new(
+                "output_on_seconds_today",
+                "for how many seconds each output has been on today",
+            ),
+            &["name"],
+        )?;
+        registry.register(Box::new(on_today_gauge.clone()))?;
+
+        let hold_gauge = Gauge::new("hold", "whether all outputs are currently held")?;
+        registry.register(Box::new(hold_gauge.clone()))?;
+
+        let profile_gauge = GaugeVec::new(
+            Opts::new("profiles", "whether each of the profiles is active"),
+            &["name"],
+        )?;
+        registry.register(Box::new(profile_gauge.clone()))?;
+
         let water_level_gauge = GaugeVec::new(
             Opts::new("water_levels", "water level reported by the sensors"),
             &["name"],
@@ -49,6 +101,12 @@ impl Metrics {
         Ok(Self {
             registry,
             output_gauge,
+            output_limit_gauge,
+            top_off_failure_gauge,
+            override_gauge,
+            on_today_gauge,
+            hold_gauge,
+            profile_gauge,
             water_level_gauge,
             temperature_gauge,
             humidity_gauge,
@@ -66,10 +124,70 @@ impl Metrics {
             .with(&labels! {
                 "name" => output.name(),
             })
-            .set(match state {
-                OutputState::On => 1.0,
-                OutputState::Off => 0.0,
-            });
+            .set(state.brightness().fraction().into());
+    }
+
+    pub fn report_output_limit(&mut self, output: &OutputName, exceeded: Option<Limit>) {
+        for (limit, label) in [
+            (Limit::MaxOnTime, "max_on_time"),
+            (Limit::DailyBudget, "daily_budget"),
+        ] {
+            self.output_limit_gauge
+                .with(&labels! {
+                    "name" => output.name(),
+                    "limit" => label,
+                })
+                .set(if exceeded == Some(limit) { 1.0 } else { 0.0 });
+        }
+    }
+
+    pub fn report_top_off_failure(&mut self, output: &OutputName, failed: Option<TopOffFailure>) {
+        for (failure, label) in [
+            (TopOffFailure::MaxFillTime, "max_fill_time"),
+            (TopOffFailure::NoRise, "no_rise"),
+            (TopOffFailure::NoReadings, "no_readings"),
+        ] {
+            self.top_off_failure_gauge
+                .with(&labels! {
+                    "name" => output.name(),
+                    "reason" => label,
+                })
+                .set(if failed == Some(failure) { 1.0 } else { 0.0 });
+        }
+    }
+
+    pub fn report_override(&mut self, output: &OutputName, in_effect: Option<&OverrideStatus>) {
+        self.override_gauge
+            .with(&labels! {
+                "name" => output.name(),
+            })
+            .set(in_effect.map_or(-1.0, |v| v.priority().priority().into()));
+    }
+
+    pub fn report_on_today(&mut self, output: &OutputName, on_today: &TimeDelta) {
+        self.on_today_gauge
+            .with(&labels! {
+                "name" => output.name(),
+            })
+            .set(on_today.num_milliseconds() as f64 / 1000.0);
+    }
+
+    pub fn report_hold(&mut self, held: bool) {
+        self.hold_gauge.set(if held { 1.0 } else { 0.0 });
+    }
+
+    pub fn report_profile(&mut self, profiles: &[Profile], active: Option<&ProfileName>) {
+        for profile in profiles {
+            self.profile_gauge
+                .with(&labels! {
+                    "name" => profile.name().name(),
+                })
+                .set(if Some(profile.name()) == active {
+                    1.0
+                } else {
+                    0.0
+                });
+        }
     }
 
     pub fn report_water_level(&mut self, sensor: &SensorName, level: &WaterLevel) {
@@ -1,9 +1,10 @@
 pub mod config;
 pub mod metrics;
 pub mod raspberrypi;
+pub mod state;
 
 use crate::{
-    domain::{self, outputs, PinNumber},
+    domain::{self, time, PinNumber},
     errors::Result,
 };
 use anyhow::anyhow;
@@ -19,7 +20,7 @@ impl CurrentTimeProvider {
     }
 }
 
-impl outputs::CurrentTimeProvider for CurrentTimeProvider {
+impl time::CurrentTimeProvider for CurrentTimeProvider {
     fn now(&self) -> chrono::DateTime<Utc> {
         Utc::now()
     }
@@ -47,8 +48,21 @@ impl Default for MockGPIO {
 }
 
 impl domain::GPIO<MockOutputPin, MockInputPin> for MockGPIO {
-    fn output(&self, number: &PinNumber) -> Result<MockOutputPin> {
-        Ok(MockOutputPin::new(*number))
+    fn output(&self, number: &PinNumber, initial: domain::OutputPinState) -> Result<MockOutputPin> {
+        let duty_cycle = match initial {
+            domain::OutputPinState::Low => domain::DutyCycle::off(),
+            domain::OutputPinState::High => domain::DutyCycle::full(),
+        };
+        Ok(MockOutputPin::new(*number, duty_cycle))
+    }
+
+    fn pwm_output(
+        &self,
+        number: &PinNumber,
+        _pwm: &domain::Pwm,
+        initial: domain::DutyCycle,
+    ) -> Result<MockOutputPin> {
+        Ok(MockOutputPin::new(*number, initial))
     }
 
     fn input(&self, _number: &PinNumber) -> Result<MockInputPin> {
@@ -58,31 +72,46 @@ impl domain::GPIO<MockOutputPin, MockInputPin> for MockGPIO {
 
 pub struct MockOutputPin {
     number: PinNumber,
-    state: domain::OutputPinState,
+    duty_cycle: domain::DutyCycle,
 }
 
 impl MockOutputPin {
-    pub fn new(number: PinNumber) -> Self {
-        Self {
-            state: domain::OutputPinState::High,
-            number,
-        }
+    pub fn new(number: PinNumber, duty_cycle: domain::DutyCycle) -> Self {
+        Self { number, duty_cycle }
     }
 }
 
 impl domain::OutputPin for MockOutputPin {
     fn set_low(&mut self) {
         debug!("setting pin {:?} low", self.number);
-        self.state = domain::OutputPinState::Low;
+        self.duty_cycle = domain::DutyCycle::off();
     }
 
     fn set_high(&mut self) {
         debug!("setting pin {:?} high", self.number);
-        self.state = domain::OutputPinState::High;
+        self.duty_cycle = domain::DutyCycle::full();
     }
 
     fn state(&self) -> domain::OutputPinState {
-        self.state
+        if self.duty_cycle > domain::DutyCycle::off() {
+            domain::OutputPinState::High
+        } else {
+            domain::OutputPinState::Low
+        }
+    }
+
+    fn set_duty_cycle(&mut self, duty_cycle: domain::DutyCycle) -> Result<()> {
+        debug!(
+            "setting pin {:?} duty cycle to {}",
+            self.number,
+            duty_cycle.fraction()
+        );
+        self.duty_cycle = duty_cycle;
+        Ok(())
+    }
+
+    fn duty_cycle(&self) -> domain::DutyCycle {
+        self.duty_cycle
     }
 }
 
@@ -5,9 +5,11 @@ use crate::{
     errors::{Error, Result},
 };
 use anyhow::anyhow;
+use log::error;
 use rppal::{
     gpio::{self},
     i2c::I2c,
+    pwm,
 };
 use std::time::Duration;
 
@@ -22,13 +24,78 @@ impl GPIO {
             gpio: gpio::Gpio::new()?,
         })
     }
+
+    // Calling into_output on a pin which is already an output doesn't change its level. The pins
+    // aren't reset when dropped so that they stay in the safe states set by the fail-safe.
+    fn output_pin(
+        &self,
+        number: &PinNumber,
+        initial: domain::OutputPinState,
+    ) -> Result<gpio::OutputPin> {
+        let pin = self.gpio.get(number.into())?;
+        let mut output_pin = if pin.mode() == gpio::Mode::Output {
+            pin.into_output()
+        } else {
+            match initial {
+                domain::OutputPinState::Low => pin.into_output_low(),
+                domain::OutputPinState::High => pin.into_output_high(),
+            }
+        };
+        output_pin.set_reset_on_drop(false);
+        Ok(output_pin)
+    }
 }
 
 impl domain::GPIO<OutputPin, InputPin> for GPIO {
-    fn output(&self, number: &PinNumber) -> Result<OutputPin> {
-        let pin = self.gpio.get(number.into())?;
-        let output_pin = pin.into_output();
-        Ok(OutputPin::new(output_pin))
+    fn output(&self, number: &PinNumber, initial: domain::OutputPinState) -> Result<OutputPin> {
+        let output_pin = self.output_pin(number, initial)?;
+        Ok(OutputPin::new(PinKind::Binary(output_pin)))
+    }
+
+    fn pwm_output(
+        &self,
+        number: &PinNumber,
+        definition: &domain::Pwm,
+        initial: domain::DutyCycle,
+    ) -> Result<OutputPin> {
+        match definition.kind() {
+            domain::PwmKind::Software => {
+                let output_pin = self.output_pin(number, initial.into())?;
+                Ok(OutputPin::new(PinKind::SoftwarePwm {
+                    pin: output_pin,
+                    frequency: definition.frequency(),
+                }))
+            }
+            domain::PwmKind::Hardware => {
+                // assumes that the default pins were enabled with the pwm-2chan overlay
+                let channel = match number.number() {
+                    12 | 18 => pwm::Channel::Pwm0,
+                    13 | 19 => pwm::Channel::Pwm1,
+                    _ => {
+                        return Err(anyhow!(
+                            "pin {} doesn't support hardware PWM",
+                            number.number()
+                        ))
+                    }
+                };
+                // Pwm::new doesn't change the settings of the channel so one which is already
+                // running keeps its duty cycle, just like the pins which are already outputs
+                let mut pwm = pwm::Pwm::new(channel)?;
+                let duty_cycle = if pwm.is_enabled()? {
+                    domain::DutyCycle::new(pwm.duty_cycle()?.clamp(0.0, 1.0))?
+                } else {
+                    pwm.set_polarity(pwm::Polarity::Normal)?;
+                    initial
+                };
+                pwm.set_frequency(definition.frequency(), duty_cycle.fraction())?;
+                pwm.enable()?;
+                pwm.set_reset_on_drop(false);
+                Ok(OutputPin {
+                    pin: PinKind::HardwarePwm(pwm),
+                    duty_cycle,
+                })
+            }
+        }
     }
 
     fn input(&self, number: &PinNumber) -> Result<InputPin> {
@@ -38,32 +105,91 @@ impl domain::GPIO<OutputPin, InputPin> for GPIO {
     }
 }
 
+enum PinKind {
+    Binary(gpio::OutputPin),
+    SoftwarePwm {
+        pin: gpio::OutputPin,
+        frequency: f64,
+    },
+    HardwarePwm(pwm::Pwm),
+}
+
 pub struct OutputPin {
-    pin: gpio::OutputPin,
+    pin: PinKind,
+    duty_cycle: domain::DutyCycle,
 }
 
 impl OutputPin {
-    fn new(pin: gpio::OutputPin) -> Self {
-        Self { pin }
+    fn new(pin: PinKind) -> Self {
+        let duty_cycle = match &pin {
+            PinKind::Binary(pin) | PinKind::SoftwarePwm { pin, .. } => {
+                if pin.is_set_high() {
+                    domain::DutyCycle::full()
+                } else {
+                    domain::DutyCycle::off()
+                }
+            }
+            PinKind::HardwarePwm(_) => domain::DutyCycle::off(),
+        };
+        Self { pin, duty_cycle }
     }
 }
 
 impl domain::OutputPin for OutputPin {
     fn set_low(&mut self) {
-        self.pin.set_low();
+        if let Err(err) = self.set_duty_cycle(domain::DutyCycle::off()) {
+            error!("error setting the pin low: {err}");
+        }
     }
 
     fn set_high(&mut self) {
-        self.pin.set_high();
+        if let Err(err) = self.set_duty_cycle(domain::DutyCycle::full()) {
+            error!("error setting the pin high: {err}");
+        }
     }
 
     fn state(&self) -> domain::OutputPinState {
-        if self.pin.is_set_high() {
+        if self.duty_cycle > domain::DutyCycle::off() {
             domain::OutputPinState::High
         } else {
             domain::OutputPinState::Low
         }
     }
+
+    fn set_duty_cycle(&mut self, duty_cycle: domain::DutyCycle) -> Result<()> {
+        match &mut self.pin {
+            PinKind::Binary(pin) => {
+                if duty_cycle > domain::DutyCycle::off() {
+                    pin.set_high();
+                    self.duty_cycle = domain::DutyCycle::full();
+                } else {
+                    pin.set_low();
+                    self.duty_cycle = domain::DutyCycle::off();
+                }
+                return Ok(());
+            }
+            PinKind::SoftwarePwm { pin, frequency } => {
+                if duty_cycle == domain::DutyCycle::off() {
+                    pin.clear_pwm()?;
+                    pin.set_low();
+                } else if duty_cycle == domain::DutyCycle::full() {
+                    pin.clear_pwm()?;
+                    pin.set_high();
+                } else {
+                    pin.set_pwm_frequency(*frequency, duty_cycle.fraction())?;
+                }
+            }
+            PinKind::HardwarePwm(pwm) => {
+                pwm.set_duty_cycle(duty_cycle.fraction())?;
+            }
+        }
+        self.duty_cycle = duty_cycle;
+        Ok(())
+    }
+
+    fn duty_cycle(&self) -> domain::DutyCycle {
+        self.duty_cycle
+    }
 }
 
 pub struct InputPin {
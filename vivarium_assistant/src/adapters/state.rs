@@ -0,0 +1,324 @@
+use crate::adapters::config::{format_state, parse_state};
+use crate::domain::outputs::{
+    ActiveProfile, Hold, OutputName, OverrideId, OverridePriority, OverrideStatus, ProfileName,
+    SceneName,
+};
+use crate::domain::time::Timezone;
+use crate::errors::Result;
+use chrono::{DateTime, NaiveDate};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct State {
+    overrides: Vec<OverrideStatus>,
+    hold: Option<Hold>,
+    profile: Option<ActiveProfile>,
+}
+
+impl State {
+    pub fn new(overrides: &[OverrideStatus]) -> Self {
+        Self {
+            overrides: overrides.to_vec(),
+            hold: None,
+            profile: None,
+        }
+    }
+
+    pub fn with_hold(self, hold: Option<Hold>) -> Self {
+        Self { hold, ..self }
+    }
+
+    pub fn with_profile(self, profile: Option<ActiveProfile>) -> Self {
+        Self { profile, ..self }
+    }
+
+    pub fn overrides(&self) -> &[OverrideStatus] {
+        &self.overrides
+    }
+
+    pub fn hold(&self) -> Option<Hold> {
+        self.hold
+    }
+
+    pub fn profile(&self) -> Option<&ActiveProfile> {
+        self.profile.as_ref()
+    }
+}
+
+// Keeps the state which has to survive restarts. The Pi boots from an SD card so this should only
+// be written to when something actually changed. Times are stored with their UTC offset as the
+// same local time happens twice when the clocks go back and the timezone can change between
+// restarts.
+pub struct StateFile {
+    path: PathBuf,
+    timezone: Timezone,
+}
+
+impl StateFile {
+    pub fn new(path: impl Into<PathBuf>, timezone: Timezone) -> Self {
+        Self {
+            path: path.into(),
+            timezone,
+        }
+    }
+
+    // A missing file simply means that there is nothing to restore yet.
+    pub fn load(&self) -> Result<State> {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(State::default()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let state: SerializedState = toml::from_str(&content)?;
+        let overrides = state
+            .overrides
+            .iter()
+            .map(SerializedOverride::parse)
+            .collect::<Result<Vec<_>>>()?;
+        let hold = state.hold.as_ref().map(|v| v.parse()).transpose()?;
+        let profile = state
+            .profile
+            .as_ref()
+            .map(ActiveProfile::try_from)
+            .transpose()?;
+        Ok(State::new(&overrides).with_hold(hold).with_profile(profile))
+    }
+
+    // The new content is written to a temporary file which then replaces the old one so that
+    // losing power halfway through leaves either the old or the new state on the disk.
+    pub fn save(&self, state: &State) -> Result<()> {
+        let state = SerializedState {
+            overrides: state
+                .overrides
+                .iter()
+                .map(|v| SerializedOverride::new(v, &self.timezone))
+                .collect(),
+            hold: state
+                .hold
+                .as_ref()
+                .map(|v| SerializedHold::new(v, &self.timezone)),
+            profile: state.profile.as_ref().map(SerializedProfile::from),
+        };
+        let content = toml::to_string(&state)?;
+
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(content.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &self.path)?;
+
+        let dir = match self.path.parent() {
+            Some(dir) if dir != Path::new("") => dir,
+            _ => Path::new("."),
+        };
+        fs::File::open(dir)?.sync_all()?;
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SerializedState {
+    #[serde(default)]
+    overrides: Vec<SerializedOverride>,
+    hold: Option<SerializedHold>,
+    profile: Option<SerializedProfile>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SerializedOverride {
+    id: u64,
+    output: String,
+    state: String,
+    #[serde(default)]
+    priority: u32,
+    start: String,
+    end: String,
+    scene: Option<String>,
+}
+
+impl SerializedOverride {
+    fn new(value: &OverrideStatus, timezone: &Timezone) -> Self {
+        Self {
+            id: value.id().id(),
+            output: value.output().name().to_string(),
+            state: format_state(&value.state()),
+            priority: value.priority().priority(),
+            start: timezone.fixed_offset(&value.start()).to_rfc3339(),
+            end: timezone.fixed_offset(&value.end()).to_rfc3339(),
+            scene: value.scene().map(|v| v.name().to_string()),
+        }
+    }
+
+    fn parse(&self) -> Result<OverrideStatus> {
+        let status = OverrideStatus::new(
+            OverrideId::new(self.id),
+            OutputName::new(&self.output)?,
+            parse_state(&self.state)?,
+            DateTime::parse_from_rfc3339(&self.start)?.to_utc(),
+            DateTime::parse_from_rfc3339(&self.end)?.to_utc(),
+        )?
+        .with_priority(OverridePriority::new(self.priority));
+
+        match &self.scene {
+            Some(scene) => Ok(status.with_scene(SceneName::new(scene)?)),
+            None => Ok(status),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SerializedHold {
+    state: Option<String>,
+    until: Option<String>,
+}
+
+impl SerializedHold {
+    fn new(value: &Hold, timezone: &Timezone) -> Self {
+        Self {
+            state: value.state().map(|v| format_state(&v)),
+            until: value
+                .until()
+                .map(|v| timezone.fixed_offset(&v).to_rfc3339()),
+        }
+    }
+
+    fn parse(&self) -> Result<Hold> {
+        let mut hold = Hold::new();
+        if let Some(state) = &self.state {
+            hold = hold.with_state(parse_state(state)?)?;
+        }
+        if let Some(until) = &self.until {
+            hold = hold.with_until(DateTime::parse_from_rfc3339(until)?.to_utc());
+        }
+        Ok(hold)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SerializedProfile {
+    name: Option<String>,
+    date: String,
+}
+
+impl From<&ActiveProfile> for SerializedProfile {
+    fn from(value: &ActiveProfile) -> Self {
+        Self {
+            name: value.name().map(|v| v.name().to_string()),
+            date: value.date().format(DATE_FORMAT).to_string(),
+        }
+    }
+}
+
+impl TryFrom<&SerializedProfile> for ActiveProfile {
+    type Error = crate::errors::Error;
+
+    fn try_from(value: &SerializedProfile) -> std::result::Result<Self, Self::Error> {
+        let name = match &value.name {
+            Some(name) => Some(ProfileName::new(name)?),
+            None => None,
+        };
+        Ok(ActiveProfile::new(
+            name,
+            NaiveDate::parse_from_str(&value.date, DATE_FORMAT)?,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::outputs::{Brightness, OutputState};
+    use chrono::NaiveDate;
+
+    #[test]
+    fn test_save_and_load() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("vivarium_state_{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let timezone = Timezone::new("Europe/Warsaw")?;
+        let state_file = StateFile::new(dir.join("state.toml"), timezone);
+
+        assert_eq!(state_file.load()?, State::default());
+
+        let start = NaiveDate::from_ymd_opt(2024, 6, 15)
+            .unwrap()
+            .and_hms_milli_opt(12, 30, 15, 250)
+            .unwrap();
+        let start_utc = start.and_utc();
+        let overrides = vec![
+            OverrideStatus::new(
+                OverrideId::new(1),
+                OutputName::new("Output 1")?,
+                OutputState::Off,
+                start_utc,
+                start_utc + chrono::TimeDelta::hours(2),
+            )?,
+            OverrideStatus::new(
+                OverrideId::new(2),
+                OutputName::new("Output 2")?,
+                OutputState::Dimmed(Brightness::new(0.4)?),
+                start_utc,
+                start_utc + chrono::TimeDelta::minutes(5),
+            )?
+            .with_priority(OverridePriority::new(10))
+            .with_scene(SceneName::new("feeding")?),
+        ];
+        let state = State::new(&overrides);
+        state_file.save(&state)?;
+        assert_eq!(state_file.load()?, state);
+
+        let state = State::new(&[]).with_hold(Some(
+            Hold::new()
+                .with_state(OutputState::On)?
+                .with_until(start_utc + chrono::TimeDelta::minutes(30)),
+        ));
+        state_file.save(&state)?;
+        assert_eq!(state_file.load()?, state);
+
+        let state = State::new(&[]).with_hold(Some(Hold::new()));
+        state_file.save(&state)?;
+        assert_eq!(state_file.load()?, state);
+
+        for name in [None, Some(ProfileName::new("vacation")?)] {
+            let state = State::new(&[]).with_profile(Some(ActiveProfile::new(name, start.date())));
+            state_file.save(&state)?;
+            assert_eq!(state_file.load()?, state);
+        }
+
+        // 02:30 happens twice on that day, the offset tells the two apart
+        let first = NaiveDate::from_ymd_opt(2024, 10, 27)
+            .unwrap()
+            .and_hms_opt(0, 30, 0)
+            .unwrap()
+            .and_utc();
+        for (until, expected) in [
+            (first, "2024-10-27T02:30:00+02:00"),
+            (
+                first + chrono::TimeDelta::hours(1),
+                "2024-10-27T02:30:00+01:00",
+            ),
+        ] {
+            let state = State::new(&[]).with_hold(Some(Hold::new().with_until(until)));
+            state_file.save(&state)?;
+            assert!(fs::read_to_string(dir.join("state.toml"))?.contains(expected));
+            assert_eq!(state_file.load()?, state);
+
+            let other_timezone = StateFile::new(dir.join("state.toml"), Timezone::new("UTC")?);
+            assert_eq!(other_timezone.load()?, state);
+        }
+
+        state_file.save(&State::default())?;
+        assert_eq!(state_file.load()?, State::default());
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -3,16 +3,16 @@ use std::time::Duration;
 use vivarium_assistant::domain::OutputPin;
 use vivarium_assistant::{
     adapters::raspberrypi,
-    domain::{PinNumber, GPIO},
+    domain::{OutputPinState, PinNumber, GPIO},
     errors::Result,
 };
 
 fn main() -> Result<()> {
     let gpio = raspberrypi::GPIO::new()?;
 
-    let mut pin_light = gpio.output(&PinNumber::new(25)?)?;
-    let mut pin_misting = gpio.output(&PinNumber::new(24)?)?;
-    let mut pin_beacon = gpio.output(&PinNumber::new(22)?)?;
+    let mut pin_light = gpio.output(&PinNumber::new(25)?, OutputPinState::Low)?;
+    let mut pin_misting = gpio.output(&PinNumber::new(24)?, OutputPinState::Low)?;
+    let mut pin_beacon = gpio.output(&PinNumber::new(22)?, OutputPinState::Low)?;
 
     pin_light.set_high();
 
@@ -26,8 +26,10 @@ fn main() -> Result<()> {
 
     thread::sleep(Duration::from_secs(5));
 
+    // the pins keep their levels after the program exits
     pin_beacon.set_low();
     pin_misting.set_low();
+    pin_light.set_low();
 
     Ok(())
 }
@@ -1,13 +1,13 @@
 use vivarium_assistant::domain::sensors::DistanceSensor;
 use vivarium_assistant::{
     adapters::raspberrypi,
-    domain::{sensors::HCSR04, PinNumber, GPIO},
+    domain::{sensors::HCSR04, OutputPinState, PinNumber, GPIO},
     errors::Result,
 };
 
 fn main() -> Result<()> {
     let gpio = raspberrypi::GPIO::new()?;
-    let trig = gpio.output(&PinNumber::new(17)?)?;
+    let trig = gpio.output(&PinNumber::new(17)?, OutputPinState::Low)?;
     let echo = gpio.input(&PinNumber::new(18)?)?;
 
     let mut sensor = HCSR04::new(trig, echo)?;
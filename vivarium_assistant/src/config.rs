@@ -2,6 +2,7 @@ use crate::{
     domain::{
         outputs::OutputDefinitions,
         sensors::{SensorName, WaterLevelSensorDefinitions},
+        time::Timezone,
     },
     errors::Result,
 };
@@ -11,6 +12,8 @@ pub struct Config {
     water_level_sensors: WaterLevelSensorDefinitions,
     address: String,
     aht_20: Option<SensorName>,
+    state_file: Option<String>,
+    timezone: Timezone,
 }
 
 impl Config {
@@ -25,9 +28,24 @@ impl Config {
             outputs,
             water_level_sensors,
             aht_20,
+            state_file: None,
+            timezone: Timezone::default(),
         })
     }
 
+    // Overrides are persisted in this file so that they survive restarts.
+    pub fn with_state_file(self, state_file: impl Into<String>) -> Self {
+        Self {
+            state_file: Some(state_file.into()),
+            ..self
+        }
+    }
+
+    // Schedules are evaluated in this timezone, by default the timezone of the system is used.
+    pub fn with_timezone(self, timezone: Timezone) -> Self {
+        Self { timezone, ..self }
+    }
+
     pub fn outputs(&self) -> &OutputDefinitions {
         &self.outputs
     }
@@ -43,4 +61,12 @@ impl Config {
     pub fn aht_20(&self) -> &Option<SensorName> {
         &self.aht_20
     }
+
+    pub fn state_file(&self) -> Option<&str> {
+        self.state_file.as_deref()
+    }
+
+    pub fn timezone(&self) -> Timezone {
+        self.timezone
+    }
 }
@@ -1,7 +1,13 @@
 pub mod outputs;
+pub mod pid;
+pub mod regulation;
 pub mod sensors;
+pub mod solar;
+pub mod time;
+pub mod top_off;
 
 use crate::errors::Result;
+use anyhow::anyhow;
 use std::time::Duration;
 
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -25,8 +31,82 @@ impl From<&PinNumber> for u8 {
     }
 }
 
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PwmKind {
+    Software,
+    Hardware,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Pwm {
+    kind: PwmKind,
+    frequency: f64,
+}
+
+impl Pwm {
+    pub fn new(kind: PwmKind, frequency: f64) -> Result<Self> {
+        if !frequency.is_finite() {
+            return Err(anyhow!("WHY CAN'T YOU JUST BE NORMAL?!"));
+        }
+
+        if frequency <= 0.0 {
+            return Err(anyhow!("frequency must be positive"));
+        }
+
+        Ok(Self { kind, frequency })
+    }
+
+    pub fn kind(&self) -> PwmKind {
+        self.kind
+    }
+
+    pub fn frequency(&self) -> f64 {
+        self.frequency
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
+pub struct DutyCycle {
+    fraction: f64,
+}
+
+impl DutyCycle {
+    pub fn new(fraction: f64) -> Result<Self> {
+        if !fraction.is_finite() {
+            return Err(anyhow!("WHY CAN'T YOU JUST BE NORMAL?!"));
+        }
+
+        if !(0.0..=1.0).contains(&fraction) {
+            return Err(anyhow!("duty cycle must be between 0 and 1"));
+        }
+
+        Ok(Self { fraction })
+    }
+
+    pub fn off() -> Self {
+        Self { fraction: 0.0 }
+    }
+
+    pub fn full() -> Self {
+        Self { fraction: 1.0 }
+    }
+
+    pub fn fraction(&self) -> f64 {
+        self.fraction
+    }
+
+    pub fn inverted(&self) -> Self {
+        Self {
+            fraction: 1.0 - self.fraction,
+        }
+    }
+}
+
+// Pins which are already outputs keep their level so that e.g. restarting the program doesn't make
+// the relays twitch, the other ones start at the given level.
 pub trait GPIO<A: OutputPin, B: InputPin> {
-    fn output(&self, number: &PinNumber) -> Result<A>;
+    fn output(&self, number: &PinNumber, initial: OutputPinState) -> Result<A>;
+    fn pwm_output(&self, number: &PinNumber, pwm: &Pwm, initial: DutyCycle) -> Result<A>;
     fn input(&self, number: &PinNumber) -> Result<B>;
 }
 
@@ -34,6 +114,10 @@ pub trait OutputPin {
     fn set_low(&mut self);
     fn set_high(&mut self);
     fn state(&self) -> OutputPinState;
+
+    // Pins which weren't set up for PWM are simply high for any duty cycle that isn't zero.
+    fn set_duty_cycle(&mut self, duty_cycle: DutyCycle) -> Result<()>;
+    fn duty_cycle(&self) -> DutyCycle;
 }
 
 pub trait InputPin {
@@ -58,6 +142,16 @@ pub enum OutputPinState {
     High,
 }
 
+impl From<DutyCycle> for OutputPinState {
+    fn from(value: DutyCycle) -> Self {
+        if value > DutyCycle::off() {
+            Self::High
+        } else {
+            Self::Low
+        }
+    }
+}
+
 pub trait I2C {
     fn set_slave_address(&mut self, slave_address: u16) -> Result<()>;
     fn write_read(&mut self, write_buffer: &[u8], read_buffer: &mut [u8]) -> Result<()>;
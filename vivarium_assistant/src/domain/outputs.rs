@@ -1,894 +1,6290 @@
-use super::{InputPin, OutputPin, OutputPinState, PinNumber, GPIO};
+use super::pid::TimeProportionedPid;
+use super::regulation::{DryRunProtection, Quantity, Readings, Regulation};
+use super::sensors::SensorName;
+use super::solar::{self, Location, SolarEvent};
+use super::time::{Clock, CurrentTimeProvider, Timezone};
+use super::top_off::{TopOff, TopOffFailure, TopOffState};
+use super::{DutyCycle, InputPin, OutputPin, OutputPinState, PinNumber, Pwm, GPIO};
 use crate::errors::Result;
 use anyhow::anyhow;
 use chrono::TimeDelta;
-use chrono::{DateTime, Local, NaiveTime, Utc};
-use log::info;
+use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime, NaiveTime, Utc, Weekday};
+use log::{debug, error, info, warn};
 use std::fmt::Display;
-use std::time::Duration;
 
-pub trait CurrentTimeProvider {
-    fn now(&self) -> DateTime<Utc>;
-}
+pub const DEFAULT_PREVIEW: TimeDelta = TimeDelta::days(1);
+const MAX_PREVIEW: TimeDelta = TimeDelta::days(7);
 
 #[derive(Copy, Debug, Clone, PartialEq)]
-pub struct ScheduledActivation {
-    when: NaiveTime,
-    for_seconds: u32,
+pub enum ActivationTime {
+    Fixed(NaiveTime),
+    Solar {
+        event: SolarEvent,
+        offset: TimeDelta,
+        location: Location,
+    },
 }
 
-impl ScheduledActivation {
-    const SECONDS_IN_AN_IMAGINARY_DAY: u32 = 24 * 60 * 60;
-
-    pub fn new(when: NaiveTime, for_seconds: u32) -> Result<Self> {
-        if for_seconds == 0 {
-            return Err(anyhow!("activating for 0 seconds is nonsense"));
-        }
-
-        if for_seconds > ScheduledActivation::SECONDS_IN_AN_IMAGINARY_DAY {
-            return Err(anyhow!(format!(
-                "since this type effectively represents durations on an imaginary clock face this
-                time the day really is up to {} seconds long and it isn't just the programmer's
-                delusion; the provided for_seconds exceeds that",
-                ScheduledActivation::SECONDS_IN_AN_IMAGINARY_DAY
-            )));
+impl ActivationTime {
+    pub fn new_solar(event: SolarEvent, offset: TimeDelta, location: Location) -> Result<Self> {
+        if offset.abs() >= TimeDelta::days(1) {
+            return Err(anyhow!(
+                "offsetting sunrise or sunset by a day or more makes no sense"
+            ));
         }
 
-        Ok(Self { when, for_seconds })
+        Ok(Self::Solar {
+            event,
+            offset,
+            location,
+        })
     }
 
-    pub fn overlaps(&self, other: &Self) -> bool {
-        if self.has_inside(&other.when) {
-            return true;
-        }
-        if self.has_inside(&other.end()) {
-            return true;
-        }
-        if other.has_inside(&self.end()) {
-            return true;
+    fn on(&self, date: &NaiveDate, timezone: &Timezone) -> NaiveDateTime {
+        match self {
+            ActivationTime::Fixed(time) => date.and_time(*time),
+            ActivationTime::Solar {
+                event,
+                offset,
+                location,
+            } => timezone.local(&solar::calculate(*event, date, location)) + *offset,
         }
-
-        false
     }
 
-    pub fn has_inside(&self, time: &NaiveTime) -> bool {
-        let start = self.when;
-        let end = self.end();
-
-        if start == end {
-            return true;
+    fn shifted(&self, by: TimeDelta) -> Self {
+        match self {
+            ActivationTime::Fixed(time) => ActivationTime::Fixed(*time + by),
+            ActivationTime::Solar {
+                event,
+                offset,
+                location,
+            } => ActivationTime::Solar {
+                event: *event,
+                offset: *offset + by,
+                location: *location,
+            },
         }
+    }
 
-        let jumps_over_midnight = end < start;
-        if jumps_over_midnight {
-            time >= &start || time <= &end
-        } else {
-            time >= &start && time <= &end
-        }
+    fn is_fixed(&self) -> bool {
+        matches!(self, ActivationTime::Fixed(_))
     }
+}
 
-    pub fn repeat(&self, start_every_seconds: u32, times: u32) -> Result<Vec<ScheduledActivation>> {
-        if start_every_seconds == 0 {
-            return Err(anyhow!("repeating every zero seconds makes no sense"));
-        }
+impl From<NaiveTime> for ActivationTime {
+    fn from(value: NaiveTime) -> Self {
+        ActivationTime::Fixed(value)
+    }
+}
 
-        if start_every_seconds <= self.for_seconds {
-            return Err(anyhow!("repeating more often than or just as often as for_seconds makes no sense, the results will overlap"));
+impl Display for ActivationTime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ActivationTime::Fixed(time) => write!(f, "{}", time),
+            ActivationTime::Solar { event, offset, .. } => {
+                let event = match event {
+                    SolarEvent::Sunrise => "sunrise",
+                    SolarEvent::Sunset => "sunset",
+                };
+                if offset < &TimeDelta::zero() {
+                    write!(f, "{} - {} seconds", event, offset.abs().num_seconds())
+                } else {
+                    write!(f, "{} + {} seconds", event, offset.num_seconds())
+                }
+            }
         }
+    }
+}
 
-        if times <= 1 {
+#[derive(Copy, Debug, Clone, PartialEq)]
+pub struct Weekdays {
+    mask: u8,
+}
+
+impl Weekdays {
+    pub fn new(weekdays: &[Weekday]) -> Result<Self> {
+        if weekdays.is_empty() {
             return Err(anyhow!(
-                "repeating less than two times makes no sense, just use the existing activation"
+                "activating on no days of the week at all is nonsense"
             ));
         }
 
-        let mut result = vec![];
-        let start_every = Duration::from_secs(start_every_seconds.into());
-        for i in 0..times {
-            let jump = start_every * i;
-            if jump.as_secs() >= ScheduledActivation::SECONDS_IN_AN_IMAGINARY_DAY.into() {
-                return Err(anyhow!(
-                    "there is no way we meant to jump by more than a whole day or a whole day when repeating"
-                ));
-            }
-            let when = self.when + jump;
-            let when_jumps_over_midnight = when < self.when;
-            if when_jumps_over_midnight {
-                return Err(anyhow!(
-                    "I find it highly suspicious that we jumped over midnight when repeating, I suspect we didn't want this to happen"
-                ));
-            }
-
-            let activation = ScheduledActivation::new(when, self.for_seconds)?;
-            result.push(activation);
+        let mut mask = 0;
+        for weekday in weekdays {
+            mask |= 1 << weekday.num_days_from_monday();
         }
-        Ok(result)
+        Ok(Self { mask })
+    }
+
+    pub fn all() -> Self {
+        Self { mask: 0b1111111 }
     }
 
-    fn end(&self) -> NaiveTime {
-        self.when + TimeDelta::seconds(self.for_seconds as i64)
+    pub fn contains(&self, weekday: &Weekday) -> bool {
+        self.mask & (1 << weekday.num_days_from_monday()) != 0
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
-pub struct ScheduledActivations {
-    activations: Vec<ScheduledActivation>,
+#[derive(Copy, Debug, Clone, PartialEq, PartialOrd)]
+pub struct DayOfYear {
+    month: u32,
+    day: u32,
 }
 
-impl ScheduledActivations {
-    pub fn new(activations: &[ScheduledActivation]) -> Result<Self> {
-        let mut v = vec![];
-        for (i, a) in activations.iter().enumerate() {
-            for (j, b) in activations.iter().enumerate() {
-                if i != j && a.overlaps(b) {
-                    return Err(anyhow!("activations can't overlap"));
-                }
-            }
-            v.push(*a);
+impl DayOfYear {
+    pub fn new(month: u32, day: u32) -> Result<Self> {
+        // a leap year so that the 29th of February is considered to be a real thing
+        if NaiveDate::from_ymd_opt(2024, month, day).is_none() {
+            return Err(anyhow!("there is no such day as {month:02}-{day:02}"));
         }
-
-        Ok(ScheduledActivations { activations: v })
+        Ok(Self { month, day })
     }
 
-    pub fn has_inside(&self, time: &NaiveTime) -> bool {
-        for activation in &self.activations {
-            if activation.has_inside(time) {
-                return true;
-            }
+    fn of(date: &NaiveDate) -> Self {
+        Self {
+            month: date.month(),
+            day: date.day(),
         }
-        false
     }
 }
 
-#[derive(Debug, Clone, Hash, Eq, PartialEq)]
-pub struct OutputName {
-    name: String,
+// Both ends are inclusive. If from comes after until then the range wraps around the new year
+// e.g. from 11-01 until 03-31 means winter.
+#[derive(Copy, Debug, Clone, PartialEq)]
+pub struct DateRange {
+    from: DayOfYear,
+    until: DayOfYear,
 }
 
-impl OutputName {
-    pub fn new(name: impl Into<String>) -> Result<Self> {
-        let name = name.into();
-        if name.is_empty() {
-            return Err(anyhow!("output name can't be empty"));
-        }
-        Ok(Self { name })
+impl DateRange {
+    pub fn new(from: DayOfYear, until: DayOfYear) -> Result<Self> {
+        Ok(Self { from, until })
     }
 
-    pub fn name(&self) -> &str {
-        &self.name
+    pub fn includes(&self, date: &NaiveDate) -> bool {
+        self.contains(&DayOfYear::of(date))
     }
-}
 
-impl Display for OutputName {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.name)
+    // Two ranges overlap if one of them starts inside the other one, this also holds when either
+    // of them wraps around the new year.
+    fn overlaps(&self, other: &DateRange) -> bool {
+        self.contains(&other.from) || other.contains(&self.from)
     }
-}
 
-#[derive(Debug, Clone, PartialEq)]
-pub struct OutputDefinition {
-    name: OutputName,
-    pin: PinNumber,
-    activations: ScheduledActivations,
-}
-
-impl OutputDefinition {
-    pub fn new(name: OutputName, pin: PinNumber, activations: ScheduledActivations) -> Self {
-        Self {
-            name,
-            pin,
-            activations,
+    fn contains(&self, day: &DayOfYear) -> bool {
+        if self.from <= self.until {
+            *day >= self.from && *day <= self.until
+        } else {
+            *day >= self.from || *day <= self.until
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
-pub struct OutputDefinitions {
-    outputs: Vec<OutputDefinition>,
+#[derive(Copy, Debug, Clone, PartialEq)]
+pub struct EveryNDays {
+    days: u32,
+    starting: NaiveDate,
 }
 
-impl OutputDefinitions {
-    pub fn new(outputs: &[OutputDefinition]) -> Result<Self> {
-        let mut v = vec![];
-        for (i, a) in outputs.iter().enumerate() {
-            for (j, b) in outputs.iter().enumerate() {
-                if i == j {
-                    continue;
-                }
-
-                if a.name == b.name {
-                    return Err(anyhow!("identical output names"));
-                }
-
-                if a.pin == b.pin {
-                    return Err(anyhow!("duplicate pin numbers"));
-                }
-            }
-            v.push(a.clone());
+impl EveryNDays {
+    pub fn new(days: u32, starting: NaiveDate) -> Result<Self> {
+        if days == 0 {
+            return Err(anyhow!("activating every zero days makes no sense"));
         }
-
-        Ok(Self { outputs: v })
+        Ok(Self { days, starting })
     }
 
-    pub fn outputs(&self) -> &[OutputDefinition] {
-        &self.outputs
+    pub fn includes(&self, date: &NaiveDate) -> bool {
+        (*date - self.starting)
+            .num_days()
+            .rem_euclid(self.days.into())
+            == 0
     }
 }
 
-pub struct Controller<OP: OutputPin, CTP: CurrentTimeProvider> {
-    outputs: Vec<ControlledOutput<OP>>,
-    current_time_provider: CTP,
+#[derive(Copy, Debug, Clone, PartialEq)]
+pub struct ActivationDays {
+    weekdays: Weekdays,
+    date_range: Option<DateRange>,
+    every: Option<EveryNDays>,
+    only_on: Option<NaiveDate>,
 }
 
-impl<OP: OutputPin, CTP: CurrentTimeProvider> Controller<OP, CTP> {
-    pub fn new<IP: InputPin, GP: GPIO<OP, IP>>(
-        outputs: &OutputDefinitions,
-        gpio: GP,
-        current_time_provider: CTP,
-    ) -> Result<Controller<OP, CTP>> {
-        let outputs_with_pin: Result<Vec<ControlledOutput<OP>>> = outputs
-            .outputs()
-            .iter()
-            .map(|v| {
-                Ok(ControlledOutput {
-                    definition: v.clone(),
-                    overrides: vec![],
-                    pin: gpio.output(&v.pin)?,
-                })
-            })
-            .collect();
-
-        Ok(Controller {
-            outputs: outputs_with_pin?,
-            current_time_provider,
-        })
+impl ActivationDays {
+    pub fn new(
+        weekdays: Weekdays,
+        date_range: Option<DateRange>,
+        every: Option<EveryNDays>,
+    ) -> Self {
+        Self {
+            weekdays,
+            date_range,
+            every,
+            only_on: None,
+        }
     }
 
-    pub fn update_outputs(&mut self) {
-        let now = self.current_time_provider.now();
-        self.update_outputs_for_time(now.into());
+    pub fn every_day() -> Self {
+        Self::new(Weekdays::all(), None, None)
     }
 
-    fn update_outputs_for_time(&mut self, now: DateTime<Local>) {
-        for output in &mut self.outputs {
-            match output.target_state(&now.time()) {
-                OutputState::On => {
-                    if output.pin.state() != OutputPinState::High {
-                        info!("turning on output '{name}'", name = output.definition.name);
-                        output.pin.set_high();
-                    }
-                }
-                OutputState::Off => {
-                    if output.pin.state() != OutputPinState::Low {
-                        info!("turning off output '{name}'", name = output.definition.name);
-                        output.pin.set_low();
-                    }
-                }
-            }
-
-            output.cleanup_overrides(&now.time());
+    pub fn only_on(date: NaiveDate) -> Self {
+        Self {
+            only_on: Some(date),
+            ..Self::every_day()
         }
     }
 
-    pub fn add_override(
-        &mut self,
-        output_name: OutputName,
-        state: OutputState,
-        activation: ScheduledActivation,
-    ) -> Result<()> {
-        for output in &mut self.outputs {
-            if output.definition.name == output_name {
-                info!(
-                    "adding override to state {state} for output '{name}' starting at {when} and lasting {for_seconds} seconds",
-                    state = state,
-                    name = output_name,
-                    when  = activation.when,
-                    for_seconds =activation.for_seconds
-                );
-                output.overrides.push(Override::new(state, activation));
-                return Ok(());
+    pub fn includes(&self, date: &NaiveDate) -> bool {
+        if !self.weekdays.contains(&date.weekday()) {
+            return false;
+        }
+
+        if let Some(date_range) = &self.date_range {
+            if !date_range.includes(date) {
+                return false;
             }
         }
 
-        Err(anyhow!("output {:?} doesn't exist", output_name))
-    }
+        if let Some(every) = &self.every {
+            if !every.includes(date) {
+                return false;
+            }
+        }
 
-    pub fn clear_overrides(&mut self, output_name: OutputName) -> Result<()> {
-        for output in &mut self.outputs {
-            if output.definition.name == output_name {
-                output.overrides.clear();
-                return Ok(());
+        if let Some(only_on) = &self.only_on {
+            if only_on != date {
+                return false;
             }
         }
 
-        Err(anyhow!("output {:?} doesn't exist", output_name))
+        true
     }
 
-    pub fn fail_safe(&mut self) {
-        for output in &mut self.outputs {
-            output.pin.set_low();
-        }
+    fn is_every_day(&self) -> bool {
+        self == &Self::every_day()
     }
+}
 
-    pub fn status(&self) -> Vec<OutputStatus> {
-        let mut result = vec![];
-        for output in &self.outputs {
-            let status = OutputStatus {
-                name: output.definition.name.clone(),
-                state: output.pin.state().into(),
-            };
-            result.push(status);
-        }
-        result
+impl Default for ActivationDays {
+    fn default() -> Self {
+        Self::every_day()
     }
 }
 
-pub struct OutputStatus {
-    pub name: OutputName,
-    pub state: OutputState,
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Brightness {
+    fraction: f32,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub enum OutputState {
-    On,
-    Off,
-}
+impl Brightness {
+    pub fn new(fraction: f32) -> Result<Self> {
+        if !fraction.is_finite() {
+            return Err(anyhow!("WHY CAN'T YOU JUST BE NORMAL?!"));
+        }
 
-impl From<OutputPinState> for OutputState {
-    fn from(value: OutputPinState) -> Self {
-        match value {
-            OutputPinState::Low => Self::Off,
-            OutputPinState::High => Self::On,
+        if fraction < 0.0 {
+            return Err(anyhow!("brightness can't be negative"));
+        }
+
+        if fraction > 1.0 {
+            return Err(anyhow!("brightness can't be above 100%"));
+        }
+
+        Ok(Self { fraction })
+    }
+
+    pub fn full() -> Self {
+        Self { fraction: 1.0 }
+    }
+
+    pub fn off() -> Self {
+        Self { fraction: 0.0 }
+    }
+
+    pub fn fraction(&self) -> f32 {
+        self.fraction
+    }
+
+    fn scaled(&self, factor: f32) -> Self {
+        Self {
+            fraction: (self.fraction * factor).clamp(0.0, 1.0),
         }
     }
 }
 
-impl Display for OutputState {
+impl Display for Brightness {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            OutputState::On => write!(f, "on"),
-            OutputState::Off => write!(f, "off"),
-        }
+        write!(f, "{:.0}%", self.fraction * 100.0)
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
-struct Override {
-    state: OutputState,
-    activation: ScheduledActivation,
-    was_triggered: bool,
+impl From<Brightness> for DutyCycle {
+    fn from(value: Brightness) -> Self {
+        // brightness is already guaranteed to be between 0 and 1
+        DutyCycle {
+            fraction: value.fraction.into(),
+        }
+    }
 }
 
-impl Override {
-    fn new(state: OutputState, activation: ScheduledActivation) -> Self {
-        Self {
-            state,
-            activation,
-            was_triggered: false,
+impl From<DutyCycle> for Brightness {
+    fn from(value: DutyCycle) -> Self {
+        Brightness {
+            fraction: value.fraction as f32,
         }
     }
 }
 
-struct ControlledOutput<OP: OutputPin> {
-    definition: OutputDefinition,
-    pin: OP,
-    overrides: Vec<Override>,
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Probability {
+    probability: f64,
 }
 
-impl<OP: OutputPin> ControlledOutput<OP> {
-    fn target_state(&mut self, now: &NaiveTime) -> OutputState {
-        for o in &mut self.overrides {
-            if o.activation.has_inside(now) {
-                o.was_triggered = true;
-                return o.state;
-            }
+impl Probability {
+    pub fn new(probability: f64) -> Result<Self> {
+        if !probability.is_finite() {
+            return Err(anyhow!("WHY CAN'T YOU JUST BE NORMAL?!"));
         }
 
-        if self.definition.activations.has_inside(now) {
-            OutputState::On
-        } else {
-            OutputState::Off
+        if probability <= 0.0 {
+            return Err(anyhow!(
+                "an activation that never happens should just be removed"
+            ));
         }
+
+        if probability > 1.0 {
+            return Err(anyhow!("probability can't be above 1"));
+        }
+
+        Ok(Self { probability })
     }
 
-    fn cleanup_overrides(&mut self, now: &NaiveTime) {
-        self.overrides
-            .retain(|v| v.activation.has_inside(now) || !v.was_triggered);
+    pub fn certain() -> Self {
+        Self { probability: 1.0 }
+    }
+
+    pub fn probability(&self) -> f64 {
+        self.probability
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+// Slides the start time and the duration of an activation from its own values to the ones given
+// here a little each day so that e.g. the photoperiod can change with the seasons without a sudden
+// jump.
+#[derive(Copy, Debug, Clone, PartialEq)]
+pub struct Ramp {
+    from: NaiveDate,
+    until: NaiveDate,
+    when: ActivationTime,
+    duration: TimeDelta,
+}
 
-    #[cfg(test)]
+impl Ramp {
+    pub fn new(
+        from: NaiveDate,
+        until: NaiveDate,
+        when: impl Into<ActivationTime>,
+        duration: TimeDelta,
+    ) -> Result<Self> {
+        if until <= from {
+            return Err(anyhow!("the ramp has to end after it starts"));
+        }
+
+        ScheduledActivation::validate_duration(duration)?;
+
+        Ok(Self {
+            from,
+            until,
+            when: when.into(),
+            duration,
+        })
+    }
+
+    // How far along the ramp we are on the given date, from 0 to 1.
+    fn progress(&self, date: &NaiveDate) -> f64 {
+        let elapsed = (*date - self.from).num_days() as f64;
+        let total = (self.until - self.from).num_days() as f64;
+        (elapsed / total).clamp(0.0, 1.0)
+    }
+
+    fn shifted(&self, by: TimeDelta) -> Self {
+        Self {
+            when: self.when.shifted(by),
+            ..*self
+        }
+    }
+}
+
+#[derive(Copy, Debug, Clone, PartialEq)]
+pub struct ScheduledActivation {
+    when: ActivationTime,
+    duration: TimeDelta,
+    days: ActivationDays,
+    ramp: Option<Ramp>,
+    brightness: Brightness,
+    fade_in: TimeDelta,
+    fade_out: TimeDelta,
+    jitter: TimeDelta,
+    probability: Probability,
+    seed: u64,
+    timezone: Timezone,
+}
+
+impl ScheduledActivation {
+    const SECONDS_IN_AN_IMAGINARY_DAY: u32 = 24 * 60 * 60;
+    const MAX_JITTER_SECONDS: u32 = 12 * 60 * 60;
+
+    // Solar times drift a little from one year to the next but not enough for this to matter.
+    const REFERENCE_YEAR: i32 = 2024;
+
+    // Long enough for the weekdays, the seasons and every N days restrictions to cycle through
+    // all of their combinations for any sane values of N.
+    const REFERENCE_YEARS: i32 = 2;
+
+    // Durations have a precision of a millisecond so that e.g. a mister can be pulsed for half a
+    // second.
+    pub fn new(when: impl Into<ActivationTime>, duration: TimeDelta) -> Result<Self> {
+        ScheduledActivation::validate_duration(duration)?;
+
+        Ok(Self {
+            when: when.into(),
+            duration,
+            days: ActivationDays::every_day(),
+            ramp: None,
+            brightness: Brightness::full(),
+            fade_in: TimeDelta::zero(),
+            fade_out: TimeDelta::zero(),
+            jitter: TimeDelta::zero(),
+            probability: Probability::certain(),
+            seed: 0,
+            timezone: Timezone::default(),
+        })
+    }
+
+    // Happens only once e.g. an override scheduled for a specific date and time.
+    pub fn once(start: NaiveDateTime, duration: TimeDelta) -> Result<Self> {
+        Ok(ScheduledActivation::new(start.time(), duration)?
+            .with_days(ActivationDays::only_on(start.date())))
+    }
+
+    pub fn with_days(self, days: ActivationDays) -> Self {
+        Self { days, ..self }
+    }
+
+    pub fn with_ramp(self, ramp: Ramp) -> Result<Self> {
+        if self.fade_in + self.fade_out > ramp.duration {
+            return Err(anyhow!(
+                "fading in and out takes longer than the activation itself"
+            ));
+        }
+
+        Ok(Self {
+            ramp: Some(ramp),
+            ..self
+        })
+    }
+
+    pub fn with_brightness(self, brightness: Brightness) -> Self {
+        Self { brightness, ..self }
+    }
+
+    // Fades happen within the activation, the output goes from zero to full brightness over
+    // fade_in after the start and back to zero over fade_out before the end.
+    pub fn with_fade(self, fade_in: TimeDelta, fade_out: TimeDelta) -> Result<Self> {
+        if fade_in < TimeDelta::zero() || fade_out < TimeDelta::zero() {
+            return Err(anyhow!("fading for a negative amount of time is nonsense"));
+        }
+
+        if fade_in + fade_out > self.shortest_duration() {
+            return Err(anyhow!(
+                "fading in and out takes longer than the activation itself"
+            ));
+        }
+
+        Ok(Self {
+            fade_in,
+            fade_out,
+            ..self
+        })
+    }
+
+    // Every day the activation starts at a random moment within jitter of its nominal start
+    // time.
+    pub fn with_jitter(self, jitter: TimeDelta) -> Result<Self> {
+        if jitter < TimeDelta::zero() {
+            return Err(anyhow!(
+                "jittering by a negative amount of time is nonsense"
+            ));
+        }
+
+        if jitter > TimeDelta::seconds(ScheduledActivation::MAX_JITTER_SECONDS.into()) {
+            return Err(anyhow!(
+                "jittering by more than {} seconds means that the activation happens whenever",
+                ScheduledActivation::MAX_JITTER_SECONDS
+            ));
+        }
+
+        Ok(Self { jitter, ..self })
+    }
+
+    // Every day the activation happens only with the given probability.
+    pub fn with_probability(self, probability: Probability) -> Self {
+        Self {
+            probability,
+            ..self
+        }
+    }
+
+    // The same seed always results in the same days being skipped and the same jitter being
+    // applied on a given date, also across restarts.
+    pub fn with_seed(self, seed: u64) -> Self {
+        Self { seed, ..self }
+    }
+
+    // Set by the controller so that the activations are evaluated in the timezone of its clock.
+    pub fn with_timezone(self, timezone: Timezone) -> Self {
+        Self { timezone, ..self }
+    }
+
+    // Compares the widest possible occurrences so that randomised activations can't overlap no
+    // matter what they end up doing on a given day.
+    pub fn overlaps(&self, other: &Self) -> bool {
+        for date in self.reference_dates(other) {
+            let Some((start, end)) = self.widest_occurrence_on(&date) else {
+                continue;
+            };
+            for other_date in surrounding_dates(&date) {
+                let Some((other_start, other_end)) = other.widest_occurrence_on(&other_date) else {
+                    continue;
+                };
+                if start <= other_end && other_start <= end {
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+
+    pub fn has_inside(&self, time: &NaiveDateTime) -> bool {
+        self.brightness_at(time).is_some()
+    }
+
+    // Returns the occurrence which is happening at the given time or the next one if there is no
+    // such occurrence. Only looks at the surrounding days unless the activation happens only once.
+    pub fn next_occurrence(&self, time: &NaiveDateTime) -> Option<(NaiveDateTime, NaiveDateTime)> {
+        surrounding_dates(&time.date())
+            .iter()
+            .chain(&self.days.only_on)
+            .filter_map(|date| self.occurrence_on(date))
+            .find(|(_, end)| end >= time)
+    }
+
+    // Returns the moment at which the activation either starts or stops applying. Ends are
+    // inclusive so the activation stops applying a millisecond after its end.
+    pub fn next_change(&self, time: &NaiveDateTime) -> Option<NaiveDateTime> {
+        let (start, end) = self.next_occurrence(time)?;
+        if start > *time {
+            Some(start)
+        } else {
+            Some(end + TimeDelta::milliseconds(1))
+        }
+    }
+
+    pub fn brightness_at(&self, time: &NaiveDateTime) -> Option<Brightness> {
+        for date in surrounding_dates(&time.date()) {
+            if let Some((start, end)) = self.occurrence_on(&date) {
+                if time >= &start && time <= &end {
+                    let fade_in = fade_factor(*time - start, self.fade_in);
+                    let fade_out = fade_factor(end - *time, self.fade_out);
+                    return Some(self.brightness.scaled(fade_in.min(fade_out)));
+                }
+            }
+        }
+        None
+    }
+
+    fn is_dimmed(&self) -> bool {
+        self.brightness != Brightness::full()
+            || self.fade_in > TimeDelta::zero()
+            || self.fade_out > TimeDelta::zero()
+    }
+
+    pub fn repeat(&self, start_every: TimeDelta, times: u32) -> Result<Vec<ScheduledActivation>> {
+        if start_every <= TimeDelta::zero() {
+            return Err(anyhow!("repeating every zero seconds makes no sense"));
+        }
+
+        if start_every <= self.longest_duration() {
+            return Err(anyhow!("repeating more often than or just as often as the activation lasts makes no sense, the results will overlap"));
+        }
+
+        if times <= 1 {
+            return Err(anyhow!(
+                "repeating less than two times makes no sense, just use the existing activation"
+            ));
+        }
+
+        let mut result = vec![];
+        for i in 0..times {
+            let jump = start_every * i.try_into()?;
+            if jump >= TimeDelta::seconds(ScheduledActivation::SECONDS_IN_AN_IMAGINARY_DAY.into()) {
+                return Err(anyhow!(
+                    "there is no way we meant to jump by more than a whole day or a whole day when repeating"
+                ));
+            }
+            let when = self.when.shifted(jump);
+            let ramp = self.ramp.map(|v| v.shifted(jump));
+            let jumps = [(self.when, when)]
+                .into_iter()
+                .chain(self.ramp.zip(ramp).map(|(a, b)| (a.when, b.when)));
+            for (before, after) in jumps {
+                if let (ActivationTime::Fixed(before), ActivationTime::Fixed(after)) =
+                    (before, after)
+                {
+                    let jumps_over_midnight = after < before;
+                    if jumps_over_midnight {
+                        return Err(anyhow!(
+                            "I find it highly suspicious that we jumped over midnight when repeating, I suspect we didn't want this to happen"
+                        ));
+                    }
+                }
+            }
+
+            let activation = ScheduledActivation {
+                when,
+                ramp,
+                ..*self
+            };
+            result.push(activation);
+        }
+        Ok(result)
+    }
+
+    // Activations are re-resolved for every date as e.g. sunrise happens at a different time each
+    // day. Returns nothing if the activation doesn't start on the given date at all.
+    fn occurrence_on(&self, date: &NaiveDate) -> Option<(NaiveDateTime, NaiveDateTime)> {
+        let (start, end) = self.nominal_occurrence_on(date)?;
+
+        if self.probability != Probability::certain()
+            && random(self.seed, date, 0) >= self.probability.probability
+        {
+            return None;
+        }
+
+        if self.jitter == TimeDelta::zero() {
+            return Some((start, end));
+        }
+
+        let jitter =
+            (random(self.seed, date, 1) * 2.0 - 1.0) * self.jitter.num_milliseconds() as f64;
+        let jitter = TimeDelta::milliseconds(jitter as i64);
+        Some((start + jitter, end + jitter))
+    }
+
+    fn widest_occurrence_on(&self, date: &NaiveDate) -> Option<(NaiveDateTime, NaiveDateTime)> {
+        let (start, end) = self.nominal_occurrence_on(date)?;
+        Some((start - self.jitter, end + self.jitter))
+    }
+
+    fn nominal_occurrence_on(&self, date: &NaiveDate) -> Option<(NaiveDateTime, NaiveDateTime)> {
+        if !self.days.includes(date) {
+            return None;
+        }
+
+        let start = self.when.on(date, &self.timezone);
+        let end = start + self.duration;
+
+        let (start, end) = match &self.ramp {
+            Some(ramp) => {
+                let progress = ramp.progress(date);
+                let ramp_start = ramp.when.on(date, &self.timezone);
+                let ramp_end = ramp_start + ramp.duration;
+                (
+                    interpolate(&start, &ramp_start, progress),
+                    interpolate(&end, &ramp_end, progress),
+                )
+            }
+            None => (start, end),
+        };
+
+        // activations starting during the hour skipped when the clocks go forward would never
+        // happen so they are moved forward together with the clocks
+        let shifted = self.timezone.skip_gap(&start);
+        Some((shifted, end + (shifted - start)))
+    }
+
+    fn reference_dates(&self, other: &Self) -> Vec<NaiveDate> {
+        let first_day = NaiveDate::from_yo_opt(ScheduledActivation::REFERENCE_YEAR, 1).unwrap();
+        if self.is_the_same_every_day() && other.is_the_same_every_day() {
+            return vec![first_day];
+        }
+
+        let mut dates: Vec<NaiveDate> = first_day
+            .iter_days()
+            .take_while(|v| {
+                v.year()
+                    < ScheduledActivation::REFERENCE_YEAR + ScheduledActivation::REFERENCE_YEARS
+            })
+            .collect();
+
+        // ramps may be far away from the reference years so we have to check them separately
+        // together with the surrounding period during which they stay constant
+        for ramp in [self.ramp, other.ramp].iter().flatten() {
+            let margin = TimeDelta::days(366);
+            dates.extend(
+                (ramp.from - margin)
+                    .iter_days()
+                    .take_while(|v| v <= &(ramp.until + margin)),
+            );
+        }
+
+        dates
+    }
+
+    fn is_the_same_every_day(&self) -> bool {
+        self.when.is_fixed() && self.days.is_every_day() && self.ramp.is_none()
+    }
+
+    fn shortest_duration(&self) -> TimeDelta {
+        match &self.ramp {
+            Some(ramp) => self.duration.min(ramp.duration),
+            None => self.duration,
+        }
+    }
+
+    fn longest_duration(&self) -> TimeDelta {
+        match &self.ramp {
+            Some(ramp) => self.duration.max(ramp.duration),
+            None => self.duration,
+        }
+    }
+
+    fn validate_duration(duration: TimeDelta) -> Result<()> {
+        if duration <= TimeDelta::zero() {
+            return Err(anyhow!("activating for 0 seconds is nonsense"));
+        }
+
+        if duration > TimeDelta::seconds(ScheduledActivation::SECONDS_IN_AN_IMAGINARY_DAY.into()) {
+            return Err(anyhow!(format!(
+                "since this type effectively represents durations on an imaginary clock face this
+                time the day really is up to {} seconds long and it isn't just the programmer's
+                delusion; the provided duration exceeds that",
+                ScheduledActivation::SECONDS_IN_AN_IMAGINARY_DAY
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+// SplitMix64 mixing the seed, the date and the purpose for which the number is going to be used.
+// Returns a number in [0, 1) which is always the same for the same inputs.
+fn random(seed: u64, date: &NaiveDate, stream: u64) -> f64 {
+    let mut x = seed
+        ^ (date.num_days_from_ce() as u64).wrapping_mul(0x9E3779B97F4A7C15)
+        ^ stream.wrapping_mul(0xD1B54A32D192ED03);
+    x = x.wrapping_add(0x9E3779B97F4A7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D049BB133111EB);
+    x ^= x >> 31;
+    (x >> 11) as f64 / (1u64 << 53) as f64
+}
+
+fn fade_factor(elapsed: TimeDelta, fade: TimeDelta) -> f32 {
+    if fade == TimeDelta::zero() {
+        return 1.0;
+    }
+    (elapsed.num_milliseconds() as f32 / fade.num_milliseconds() as f32).min(1.0)
+}
+
+fn interpolate(a: &NaiveDateTime, b: &NaiveDateTime, progress: f64) -> NaiveDateTime {
+    let difference = (*b - *a).num_milliseconds() as f64;
+    *a + TimeDelta::milliseconds((difference * progress).round() as i64)
+}
+
+fn surrounding_dates(date: &NaiveDate) -> [NaiveDate; 3] {
+    [
+        date.pred_opt().unwrap_or(*date),
+        *date,
+        date.succ_opt().unwrap_or(*date),
+    ]
+}
+
+// Alternates between being on for on and off for off. The cycle starts over at the start of the
+// window every day, without a window it runs all day starting at midnight.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cycle {
+    on: TimeDelta,
+    off: TimeDelta,
+    window: Option<(NaiveTime, NaiveTime)>,
+}
+
+impl Cycle {
+    pub fn new(on: TimeDelta, off: TimeDelta) -> Result<Self> {
+        ScheduledActivation::validate_duration(on)?;
+
+        if off <= TimeDelta::zero() {
+            return Err(anyhow!(
+                "a cycle which is never off is just an output that is always on"
+            ));
+        }
+
+        Ok(Self {
+            on,
+            off,
+            window: None,
+        })
+    }
+
+    // The window may wrap around midnight e.g. from 20:00 until 08:00.
+    pub fn with_window(self, from: NaiveTime, until: NaiveTime) -> Result<Self> {
+        if from == until {
+            return Err(anyhow!(
+                "a window which starts and ends at the same time is either empty or lasts all day, just drop it if you meant the latter"
+            ));
+        }
+
+        Ok(Self {
+            window: Some((from, until)),
+            ..self
+        })
+    }
+
+    pub fn expand(&self) -> Result<Vec<ScheduledActivation>> {
+        let day = TimeDelta::seconds(ScheduledActivation::SECONDS_IN_AN_IMAGINARY_DAY.into());
+        let (from, window) = match self.window {
+            Some((from, until)) => {
+                let window = until - from;
+                let window = if window < TimeDelta::zero() {
+                    window + day
+                } else {
+                    window
+                };
+                (from, window)
+            }
+            None => (NaiveTime::MIN, day),
+        };
+
+        let mut result = vec![];
+        let mut offset = TimeDelta::zero();
+        while offset < window {
+            // the last activation gets cut short if needed so that it ends a second before the
+            // window closes and the cycle starts over
+            let duration = self.on.min(window - offset - TimeDelta::seconds(1));
+            if duration <= TimeDelta::zero() {
+                break;
+            }
+
+            let when = from + offset;
+            result.push(ScheduledActivation::new(when, duration)?);
+            offset += self.on + self.off;
+        }
+        Ok(result)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScheduledActivations {
+    activations: Vec<ScheduledActivation>,
+}
+
+impl ScheduledActivations {
+    pub fn new(activations: &[ScheduledActivation]) -> Result<Self> {
+        let mut v = vec![];
+        for (i, a) in activations.iter().enumerate() {
+            for (j, b) in activations.iter().enumerate() {
+                if i != j && a.overlaps(b) {
+                    return Err(anyhow!("activations can't overlap"));
+                }
+            }
+            v.push(*a);
+        }
+
+        Ok(ScheduledActivations { activations: v })
+    }
+
+    pub fn with_timezone(self, timezone: Timezone) -> Self {
+        Self {
+            activations: self
+                .activations
+                .iter()
+                .map(|v| v.with_timezone(timezone))
+                .collect(),
+        }
+    }
+
+    pub fn has_inside(&self, time: &NaiveDateTime) -> bool {
+        self.brightness_at(time).is_some()
+    }
+
+    pub fn brightness_at(&self, time: &NaiveDateTime) -> Option<Brightness> {
+        for activation in &self.activations {
+            if let Some(brightness) = activation.brightness_at(time) {
+                return Some(brightness);
+            }
+        }
+        None
+    }
+
+    pub fn next_change(&self, time: &NaiveDateTime) -> Option<NaiveDateTime> {
+        self.activations
+            .iter()
+            .filter_map(|v| v.next_change(time))
+            .min()
+    }
+
+    // Same as brightness_at but ignores fading in and out.
+    pub fn nominal_brightness_at(&self, time: &NaiveDateTime) -> Option<Brightness> {
+        self.activations
+            .iter()
+            .find(|v| v.has_inside(time))
+            .map(|v| v.brightness)
+    }
+
+    fn are_dimmed(&self) -> bool {
+        self.activations.iter().any(|v| v.is_dimmed())
+    }
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+pub struct OutputName {
+    name: String,
+}
+
+impl OutputName {
+    pub fn new(name: impl Into<String>) -> Result<Self> {
+        let name = name.into();
+        if name.is_empty() {
+            return Err(anyhow!("output name can't be empty"));
+        }
+        Ok(Self { name })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Display for OutputName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputDefinition {
+    name: OutputName,
+    pin: PinNumber,
+    activations: ScheduledActivations,
+    profiles: Vec<(ProfileName, ScheduledActivations)>,
+    pwm: Option<Pwm>,
+    max_on_seconds: Option<u32>,
+    daily_budget_seconds: Option<u32>,
+    min_on_seconds: Option<u32>,
+    min_off_seconds: Option<u32>,
+    regulation: Option<Regulation>,
+    dry_run_protection: Option<DryRunProtection>,
+    top_off: Option<TopOff>,
+    active_low: bool,
+    safe_state: OutputState,
+}
+
+impl OutputDefinition {
+    pub fn new(name: OutputName, pin: PinNumber, activations: ScheduledActivations) -> Self {
+        Self {
+            name,
+            pin,
+            activations,
+            profiles: vec![],
+            pwm: None,
+            max_on_seconds: None,
+            daily_budget_seconds: None,
+            min_on_seconds: None,
+            min_off_seconds: None,
+            regulation: None,
+            dry_run_protection: None,
+            top_off: None,
+            active_low: false,
+            safe_state: OutputState::Off,
+        }
+    }
+
+    // Replaces the activations while the given profile is active. Outputs without activations
+    // for the active profile keep using their usual ones.
+    pub fn with_profile(
+        self,
+        profile: ProfileName,
+        activations: ScheduledActivations,
+    ) -> Result<Self> {
+        if self.profiles.iter().any(|(name, _)| *name == profile) {
+            return Err(anyhow!(
+                "output '{}' can only have one set of activations for profile '{profile}'",
+                self.name
+            ));
+        }
+
+        let mut profiles = self.profiles;
+        profiles.push((profile, activations));
+        Ok(Self { profiles, ..self })
+    }
+
+    fn activations(&self, profile: Option<&ProfileName>) -> &ScheduledActivations {
+        self.profiles
+            .iter()
+            .find(|(name, _)| Some(name) == profile)
+            .map_or(&self.activations, |(_, activations)| activations)
+    }
+
+    // Uses the output as a refill pump which tops off the water whenever none of the activations
+    // say otherwise.
+    pub fn with_top_off(self, top_off: TopOff) -> Self {
+        Self {
+            top_off: Some(top_off),
+            ..self
+        }
+    }
+
+    pub fn top_off(&self) -> Option<&TopOff> {
+        self.top_off.as_ref()
+    }
+
+    // Keeps the output off while the water level is too low, no matter what the schedule or the
+    // overrides say.
+    pub fn with_dry_run_protection(self, dry_run_protection: DryRunProtection) -> Self {
+        Self {
+            dry_run_protection: Some(dry_run_protection),
+            ..self
+        }
+    }
+
+    pub fn dry_run_protection(&self) -> Option<&DryRunProtection> {
+        self.dry_run_protection.as_ref()
+    }
+
+    // Turns the output on based on sensor readings whenever none of the activations say
+    // otherwise.
+    pub fn with_regulation(self, regulation: Regulation) -> Self {
+        Self {
+            regulation: Some(regulation),
+            ..self
+        }
+    }
+
+    pub fn regulation(&self) -> Option<&Regulation> {
+        self.regulation.as_ref()
+    }
+
+    pub fn with_pwm(self, pwm: Pwm) -> Self {
+        Self {
+            pwm: Some(pwm),
+            ..self
+        }
+    }
+
+    // Many relay boards turn the relay on when the pin is low.
+    pub fn with_active_low(self) -> Self {
+        Self {
+            active_low: true,
+            ..self
+        }
+    }
+
+    // The state the output is put in when something goes horribly wrong, e.g. a circulation pump
+    // should rather keep running.
+    pub fn with_safe_state(self, safe_state: OutputState) -> Result<Self> {
+        if let OutputState::Dimmed(_) = safe_state {
+            return Err(anyhow!("the safe state has to be either on or off"));
+        }
+
+        Ok(Self { safe_state, ..self })
+    }
+
+    // The output gets turned off once it has been on for max_on_seconds in a row and stays off
+    // until whatever turned it on ends, no matter what the schedule or the overrides say.
+    pub fn with_max_on_time(self, max_on_seconds: u32) -> Result<Self> {
+        OutputDefinition::validate_limit(max_on_seconds)?;
+        Ok(Self {
+            max_on_seconds: Some(max_on_seconds),
+            ..self
+        })
+    }
+
+    // The output gets turned off once it has been on for daily_budget_seconds in total on a given
+    // day and stays off until the next day, no matter what the schedule or the overrides say.
+    pub fn with_daily_budget(self, daily_budget_seconds: u32) -> Result<Self> {
+        OutputDefinition::validate_limit(daily_budget_seconds)?;
+        Ok(Self {
+            daily_budget_seconds: Some(daily_budget_seconds),
+            ..self
+        })
+    }
+
+    // Protects relays and motors from rapid cycling, the output is kept on for at least
+    // min_on_seconds after being turned on. Interlocks and limits can still turn it off earlier.
+    pub fn with_min_on_time(self, min_on_seconds: u32) -> Result<Self> {
+        OutputDefinition::validate_min_time(min_on_seconds)?;
+        Ok(Self {
+            min_on_seconds: Some(min_on_seconds),
+            ..self
+        })
+    }
+
+    // Protects relays and motors from rapid cycling, the output is kept off for at least
+    // min_off_seconds after being turned off.
+    pub fn with_min_off_time(self, min_off_seconds: u32) -> Result<Self> {
+        OutputDefinition::validate_min_time(min_off_seconds)?;
+        Ok(Self {
+            min_off_seconds: Some(min_off_seconds),
+            ..self
+        })
+    }
+
+    // Outputs without pwm can only be switched fully on or off.
+    fn accepts(&self, state: &OutputState) -> bool {
+        self.pwm.is_some() || !matches!(state, OutputState::Dimmed(_))
+    }
+
+    // Converts between the duty cycle of the output and the duty cycle of the pin, both ways.
+    fn polarity(&self, duty_cycle: DutyCycle) -> DutyCycle {
+        if self.active_low {
+            duty_cycle.inverted()
+        } else {
+            duty_cycle
+        }
+    }
+
+    fn validate_min_time(seconds: u32) -> Result<()> {
+        if seconds == 0 {
+            return Err(anyhow!(
+                "a minimum of zero seconds doesn't protect anything, just leave it out"
+            ));
+        }
+
+        if seconds >= ScheduledActivation::SECONDS_IN_AN_IMAGINARY_DAY {
+            return Err(anyhow!(
+                "keeping the output in the same state for a whole day or more is not rapid cycling protection"
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn validate_limit(seconds: u32) -> Result<()> {
+        if seconds == 0 {
+            return Err(anyhow!(
+                "a limit of zero seconds means that the output can never be turned on, just remove its activations"
+            ));
+        }
+
+        if seconds >= ScheduledActivation::SECONDS_IN_AN_IMAGINARY_DAY {
+            return Err(anyhow!(
+                "a limit of a whole day or more isn't going to limit anything"
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    MaxOnTime,
+    DailyBudget,
+}
+
+impl Display for Limit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Limit::MaxOnTime => write!(f, "maximum on-time"),
+            Limit::DailyBudget => write!(f, "daily runtime budget"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InterlockKind {
+    // The output can't be on while the other output is on.
+    Excludes,
+    // The output can't be on while the other output is off.
+    Requires,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Interlock {
+    output: OutputName,
+    kind: InterlockKind,
+    other: OutputName,
+}
+
+impl Interlock {
+    pub fn new(output: OutputName, kind: InterlockKind, other: OutputName) -> Result<Self> {
+        if output == other {
+            return Err(anyhow!("an output can't be interlocked with itself"));
+        }
+
+        Ok(Self {
+            output,
+            kind,
+            other,
+        })
+    }
+
+    pub fn output(&self) -> &OutputName {
+        &self.output
+    }
+
+    pub fn kind(&self) -> InterlockKind {
+        self.kind
+    }
+
+    pub fn other(&self) -> &OutputName {
+        &self.other
+    }
+
+    fn blocks(&self, other_state: &OutputState) -> bool {
+        match self.kind {
+            InterlockKind::Excludes => other_state != &OutputState::Off,
+            InterlockKind::Requires => other_state == &OutputState::Off,
+        }
+    }
+}
+
+impl Display for Interlock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            InterlockKind::Excludes => write!(
+                f,
+                "output '{}' can't be on while output '{}' is on",
+                self.output, self.other
+            ),
+            InterlockKind::Requires => write!(
+                f,
+                "output '{}' can't be on while output '{}' is off",
+                self.output, self.other
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+pub struct SceneName {
+    name: String,
+}
+
+impl SceneName {
+    pub fn new(name: impl Into<String>) -> Result<Self> {
+        let name = name.into();
+        if name.is_empty() {
+            return Err(anyhow!("scene name can't be empty"));
+        }
+        Ok(Self { name })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Display for SceneName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+// Temporarily puts several outputs in the given states, e.g. turns everything off for the duration
+// of maintenance. The overrides making up a scene are added and cancelled together.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scene {
+    name: SceneName,
+    duration: TimeDelta,
+    priority: OverridePriority,
+    states: Vec<(OutputName, OutputState)>,
+}
+
+impl Scene {
+    pub fn new(
+        name: SceneName,
+        duration: TimeDelta,
+        states: &[(OutputName, OutputState)],
+    ) -> Result<Self> {
+        ScheduledActivation::validate_duration(duration)?;
+
+        if states.is_empty() {
+            return Err(anyhow!(
+                "a scene which doesn't change any outputs is pointless"
+            ));
+        }
+
+        for (i, (a, _)) in states.iter().enumerate() {
+            if states.iter().skip(i + 1).any(|(b, _)| a == b) {
+                return Err(anyhow!(
+                    "a scene can only set the state of output '{a}' once"
+                ));
+            }
+        }
+
+        Ok(Self {
+            name,
+            duration,
+            priority: OverridePriority::default(),
+            states: states.to_vec(),
+        })
+    }
+
+    pub fn with_priority(self, priority: OverridePriority) -> Self {
+        Self { priority, ..self }
+    }
+
+    pub fn name(&self) -> &SceneName {
+        &self.name
+    }
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+pub struct ProfileName {
+    name: String,
+}
+
+impl ProfileName {
+    pub fn new(name: impl Into<String>) -> Result<Self> {
+        let name = name.into();
+        if name.is_empty() {
+            return Err(anyhow!("profile name can't be empty"));
+        }
+        Ok(Self { name })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Display for ProfileName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+// Alternative schedule, e.g. for the dry season or for when nobody is at home. Profiles with dates
+// are switched to automatically once their date range starts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    name: ProfileName,
+    dates: Option<DateRange>,
+}
+
+impl Profile {
+    pub fn new(name: ProfileName) -> Self {
+        Self { name, dates: None }
+    }
+
+    pub fn with_dates(self, dates: DateRange) -> Self {
+        Self {
+            dates: Some(dates),
+            ..self
+        }
+    }
+
+    pub fn name(&self) -> &ProfileName {
+        &self.name
+    }
+}
+
+#[derive(Debug)]
+pub struct BlockedByInterlock {
+    interlock: Interlock,
+}
+
+impl BlockedByInterlock {
+    pub fn interlock(&self) -> &Interlock {
+        &self.interlock
+    }
+}
+
+impl Display for BlockedByInterlock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "blocked by an interlock: {}", self.interlock)
+    }
+}
+
+impl std::error::Error for BlockedByInterlock {}
+
+#[derive(Debug)]
+pub struct RunningDry {
+    output: OutputName,
+    sensor: SensorName,
+}
+
+impl RunningDry {
+    pub fn output(&self) -> &OutputName {
+        &self.output
+    }
+
+    pub fn sensor(&self) -> &SensorName {
+        &self.sensor
+    }
+}
+
+impl Display for RunningDry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "output '{}' is kept off as the water level reported by sensor '{}' is too low",
+            self.output, self.sensor
+        )
+    }
+}
+
+impl std::error::Error for RunningDry {}
+
+#[derive(Debug)]
+pub struct ConflictingOverride {
+    output: OutputName,
+    id: OverrideId,
+    priority: OverridePriority,
+}
+
+impl ConflictingOverride {
+    pub fn output(&self) -> &OutputName {
+        &self.output
+    }
+
+    pub fn id(&self) -> OverrideId {
+        self.id
+    }
+}
+
+impl Display for ConflictingOverride {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "override {} of output '{}' with the same priority {} sets a different state at the same time, remove it or pick a different priority",
+            self.id, self.output, self.priority
+        )
+    }
+}
+
+impl std::error::Error for ConflictingOverride {}
+
+#[derive(Debug)]
+pub struct UnknownOverride {
+    output: OutputName,
+    id: OverrideId,
+}
+
+impl UnknownOverride {
+    pub fn output(&self) -> &OutputName {
+        &self.output
+    }
+
+    pub fn id(&self) -> OverrideId {
+        self.id
+    }
+}
+
+impl Display for UnknownOverride {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "output '{}' doesn't have an override with id {}",
+            self.output, self.id
+        )
+    }
+}
+
+impl std::error::Error for UnknownOverride {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputDefinitions {
+    outputs: Vec<OutputDefinition>,
+    interlocks: Vec<Interlock>,
+    scenes: Vec<Scene>,
+    profiles: Vec<Profile>,
+}
+
+impl OutputDefinitions {
+    pub fn new(outputs: &[OutputDefinition]) -> Result<Self> {
+        let mut v = vec![];
+        for (i, a) in outputs.iter().enumerate() {
+            if a.pwm.is_none()
+                && (a.activations.are_dimmed() || a.profiles.iter().any(|(_, v)| v.are_dimmed()))
+            {
+                return Err(anyhow!(
+                    "output '{}' isn't dimmable so its activations can't set brightness or fade",
+                    a.name
+                ));
+            }
+
+            for (j, b) in outputs.iter().enumerate() {
+                if i == j {
+                    continue;
+                }
+
+                if a.name == b.name {
+                    return Err(anyhow!("identical output names"));
+                }
+
+                if a.pin == b.pin {
+                    return Err(anyhow!("duplicate pin numbers"));
+                }
+            }
+            v.push(a.clone());
+        }
+
+        Ok(Self {
+            outputs: v,
+            interlocks: vec![],
+            scenes: vec![],
+            profiles: vec![],
+        })
+    }
+
+    pub fn with_interlocks(self, interlocks: &[Interlock]) -> Result<Self> {
+        for (i, a) in interlocks.iter().enumerate() {
+            for name in [&a.output, &a.other] {
+                if !self.outputs.iter().any(|v| &v.name == name) {
+                    return Err(anyhow!("interlock refers to an unknown output '{name}'"));
+                }
+            }
+
+            for (j, b) in interlocks.iter().enumerate() {
+                if i != j && a.output == b.output && a.other == b.other {
+                    return Err(anyhow!(
+                        "there can be only one interlock between the same two outputs"
+                    ));
+                }
+            }
+
+            // failing safe and holding put the outputs in their safe states without looking at
+            // the interlocks
+            let safe_state = |name: &OutputName| {
+                self.outputs
+                    .iter()
+                    .find(|v| &v.name == name)
+                    .map(|v| v.safe_state)
+            };
+            if let (Some(state), Some(other_state)) = (safe_state(&a.output), safe_state(&a.other))
+            {
+                if state != OutputState::Off && a.blocks(&other_state) {
+                    return Err(anyhow!("the safe states break the interlock as {a}"));
+                }
+            }
+        }
+
+        Ok(Self {
+            interlocks: interlocks.to_vec(),
+            ..self
+        })
+    }
+
+    pub fn with_scenes(self, scenes: &[Scene]) -> Result<Self> {
+        for (i, a) in scenes.iter().enumerate() {
+            for (name, state) in &a.states {
+                let Some(output) = self.outputs.iter().find(|v| &v.name == name) else {
+                    return Err(anyhow!(
+                        "scene '{}' refers to an unknown output '{name}'",
+                        a.name
+                    ));
+                };
+
+                if !output.accepts(state) {
+                    return Err(anyhow!(
+                        "scene '{}' dims output '{name}' which isn't dimmable",
+                        a.name
+                    ));
+                }
+            }
+
+            for (j, b) in scenes.iter().enumerate() {
+                if i != j && a.name == b.name {
+                    return Err(anyhow!("identical scene names"));
+                }
+            }
+        }
+
+        Ok(Self {
+            scenes: scenes.to_vec(),
+            ..self
+        })
+    }
+
+    pub fn with_profiles(self, profiles: &[Profile]) -> Result<Self> {
+        for (i, a) in profiles.iter().enumerate() {
+            for b in profiles.iter().skip(i + 1) {
+                if a.name == b.name {
+                    return Err(anyhow!("identical profile names"));
+                }
+
+                // otherwise it would be unclear which profile to switch to
+                if let (Some(a_dates), Some(b_dates)) = (a.dates, b.dates) {
+                    if a_dates.overlaps(&b_dates) {
+                        return Err(anyhow!(
+                            "the dates of profiles '{}' and '{}' overlap",
+                            a.name,
+                            b.name
+                        ));
+                    }
+                }
+            }
+        }
+
+        for output in &self.outputs {
+            for (name, _) in &output.profiles {
+                if !profiles.iter().any(|v| &v.name == name) {
+                    return Err(anyhow!(
+                        "output '{}' refers to an unknown profile '{name}'",
+                        output.name
+                    ));
+                }
+            }
+        }
+
+        Ok(Self {
+            profiles: profiles.to_vec(),
+            ..self
+        })
+    }
+
+    pub fn outputs(&self) -> &[OutputDefinition] {
+        &self.outputs
+    }
+
+    pub fn interlocks(&self) -> &[Interlock] {
+        &self.interlocks
+    }
+
+    pub fn scenes(&self) -> &[Scene] {
+        &self.scenes
+    }
+
+    pub fn profiles(&self) -> &[Profile] {
+        &self.profiles
+    }
+}
+
+pub struct Controller<OP: OutputPin, CTP: CurrentTimeProvider> {
+    outputs: Vec<ControlledOutput<OP>>,
+    next_override_id: OverrideId,
+    interlocks: Vec<Interlock>,
+    readings: Readings,
+    clock: Clock<CTP>,
+    started: bool,
+    scenes: Vec<Scene>,
+    hold: Option<Hold>,
+    profiles: Vec<Profile>,
+    profile: ActiveProfile,
+}
+
+impl<OP: OutputPin, CTP: CurrentTimeProvider> Controller<OP, CTP> {
+    pub fn new<IP: InputPin, GP: GPIO<OP, IP>>(
+        outputs: &OutputDefinitions,
+        gpio: GP,
+        clock: Clock<CTP>,
+    ) -> Result<Controller<OP, CTP>> {
+        let mut outputs_with_pin: Vec<ControlledOutput<OP>> = vec![];
+        let new_outputs = outputs.outputs().iter().map(|v| {
+            // the outputs stay in their safe states until the first update
+            let initial = v.polarity(DutyCycle::from(v.safe_state.brightness()));
+            let pin = match &v.pwm {
+                Some(pwm) => gpio.pwm_output(&v.pin, pwm, initial)?,
+                None => gpio.output(&v.pin, initial.into())?,
+            };
+            Ok(ControlledOutput {
+                definition: OutputDefinition {
+                    activations: v.activations.clone().with_timezone(clock.timezone()),
+                    profiles: v
+                        .profiles
+                        .iter()
+                        .map(|(name, activations)| {
+                            (
+                                name.clone(),
+                                activations.clone().with_timezone(clock.timezone()),
+                            )
+                        })
+                        .collect(),
+                    ..v.clone()
+                },
+                overrides: vec![],
+                in_effect: None,
+                pin,
+                blocked_by: None,
+                runtime: Runtime::default(),
+                deferred: None,
+                regulating: false,
+                running_dry: v.dry_run_protection.is_some(),
+                top_off: TopOffState::default(),
+                pid: v
+                    .regulation
+                    .as_ref()
+                    .and_then(|v| v.pid())
+                    .map(TimeProportionedPid::new),
+            })
+        });
+        for output in new_outputs {
+            match output {
+                Ok(output) => outputs_with_pin.push(output),
+                Err(err) => {
+                    // pins which already were outputs kept their levels
+                    for output in &mut outputs_with_pin {
+                        let state = output.definition.safe_state;
+                        output.set_pin(&state);
+                    }
+                    return Err(err);
+                }
+            }
+        }
+
+        let today = clock.now().date();
+        let profile = ActiveProfile {
+            name: scheduled_profile(outputs.profiles(), &today).cloned(),
+            date: today,
+        };
+
+        Ok(Controller {
+            outputs: outputs_with_pin,
+            next_override_id: OverrideId::new(1),
+            interlocks: outputs.interlocks().to_vec(),
+            readings: Readings::new(),
+            clock,
+            started: false,
+            scenes: outputs.scenes().to_vec(),
+            hold: None,
+            profiles: outputs.profiles().to_vec(),
+            profile,
+        })
+    }
+
+    pub fn update_outputs(&mut self) {
+        let now = self.clock.now_utc();
+        self.update_outputs_for_time(now);
+    }
+
+    // Only the schedules follow the wall clock, everything which measures how long something has
+    // been going on uses the actual time as the wall clock stands still when the clocks go back.
+    fn update_outputs_for_time(&mut self, now_utc: DateTime<Utc>) {
+        let now = self.clock.timezone().wall_clock(&now_utc);
+        if self.hold.is_some_and(|v| !v.applies_at(&now_utc)) {
+            info!("the hold ran out, resuming the automation");
+            self.hold = None;
+        }
+
+        let profile = profile_on(&self.profiles, &self.profile, &now.date());
+        if profile.name != self.profile.name {
+            match &profile.name {
+                Some(name) => info!("switching to profile '{name}'"),
+                None => info!("switching to the default schedule"),
+            }
+        }
+        self.profile = profile;
+
+        let hold = self.hold;
+        let profile = self.profile.name.clone();
+        let mut states: Vec<OutputState> = self
+            .outputs
+            .iter_mut()
+            .map(|v| {
+                // the overrides still run out and the regulation keeps track of the readings
+                // during a hold, only the results are ignored
+                let state = v.target_state(&now, &now_utc, &self.readings, profile.as_ref());
+                let state = match &hold {
+                    Some(hold) => hold.state_of(&v.definition),
+                    None => state,
+                };
+                let state = v.protect_from_running_dry(state, &now_utc, &self.readings);
+                let state = v.enforce_limits(state, &now, &now_utc);
+                v.defer_transitions(state, &now_utc)
+            })
+            .collect();
+        let blocked_by = self.apply_interlocks(&mut states);
+
+        if !self.started {
+            for (output, state) in self.outputs.iter().zip(&states) {
+                let current: OutputState = Brightness::from(output.duty_cycle()).into();
+                if current != *state {
+                    info!(
+                        "output '{name}' is {current} after starting up but it should be {state}",
+                        name = output.definition.name
+                    );
+                }
+            }
+            self.started = true;
+        }
+
+        for ((output, state), blocked_by) in self.outputs.iter_mut().zip(states).zip(blocked_by) {
+            output.update_blocked_by(blocked_by);
+            if output.deferred.is_some_and(|v| v.state == state) {
+                output.deferred = None;
+            }
+            output.apply(&state, &now_utc);
+            output.cleanup_overrides(&now_utc);
+        }
+    }
+
+    fn apply_interlocks(&self, states: &mut [OutputState]) -> Vec<Option<Interlock>> {
+        let names: Vec<&OutputName> = self.outputs.iter().map(|v| &v.definition.name).collect();
+        apply_interlocks(&self.interlocks, &names, states)
+    }
+
+    // Overrides with a higher priority win over the ones with a lower priority. Overlapping
+    // overrides with the same priority are only allowed if they set the same state as otherwise it
+    // would be unclear which one should be in effect.
+    pub fn add_override(
+        &mut self,
+        output_name: OutputName,
+        new_override: NewOverride,
+    ) -> Result<OverrideId> {
+        let NewOverride {
+            state,
+            start,
+            duration,
+            priority,
+        } = new_override;
+        let now = self.clock.now_utc();
+        let end = start + duration;
+        if end < now {
+            return Err(anyhow!("the override would already be over"));
+        }
+
+        if let Some(output) = self
+            .outputs
+            .iter()
+            .find(|v| v.definition.name == output_name)
+        {
+            if !output.definition.accepts(&state) {
+                return Err(anyhow!(
+                    "output '{output_name}' isn't dimmable so it can only be turned on or off"
+                ));
+            }
+
+            if let Some(max_on_seconds) = output.definition.max_on_seconds {
+                if state != OutputState::Off && duration > TimeDelta::seconds(max_on_seconds.into())
+                {
+                    return Err(anyhow!(
+                        "output '{output_name}' may only be on for {max_on_seconds} seconds in a row, the override would be cut short"
+                    ));
+                }
+            }
+
+            if let Some(conflicting) = output.conflicting_override(state, priority, &start, &end) {
+                let err = ConflictingOverride {
+                    output: output_name,
+                    id: conflicting.id,
+                    priority,
+                };
+                warn!("rejecting an override as {err}");
+                return Err(err.into());
+            }
+        }
+
+        // the water level may well change until then so only the overrides starting right away
+        // are checked against it
+        if state != OutputState::Off && start <= now {
+            if let Some(output) = self
+                .outputs
+                .iter()
+                .find(|v| v.definition.name == output_name && v.running_dry)
+            {
+                if let Some(protection) = &output.definition.dry_run_protection {
+                    let err = RunningDry {
+                        output: output_name,
+                        sensor: protection.sensor().clone(),
+                    };
+                    warn!("rejecting an override as {err}");
+                    return Err(err.into());
+                }
+            }
+        }
+
+        // overrides starting later are checked against what the other outputs are going to do
+        // when they start
+        if state != OutputState::Off {
+            if let Some(i) = self
+                .outputs
+                .iter()
+                .position(|v| v.definition.name == output_name)
+            {
+                let at = start.max(now);
+                let wall_clock = self.clock.timezone().wall_clock(&at);
+                let profile = profile_on(&self.profiles, &self.profile, &wall_clock.date());
+                let mut states: Vec<OutputState> = self
+                    .outputs
+                    .iter()
+                    .map(|v| v.peek_state(&wall_clock, &at, profile.name.as_ref()))
+                    .collect();
+                states[i] = state;
+                if let Some(interlock) = self.apply_interlocks(&mut states).swap_remove(i) {
+                    warn!("rejecting an override for output '{output_name}' starting at {wall_clock} as {interlock}");
+                    return Err(BlockedByInterlock { interlock }.into());
+                }
+            }
+        }
+
+        for output in &mut self.outputs {
+            if output.definition.name == output_name {
+                let id = self.next_override_id;
+                info!(
+                    "adding override {id} with priority {priority} to state {state} for output '{name}' starting at {when} and lasting {seconds} seconds",
+                    state = state,
+                    name = output_name,
+                    when = self.clock.timezone().fixed_offset(&start),
+                    seconds = duration.num_milliseconds() as f64 / 1000.0
+                );
+                output
+                    .overrides
+                    .push(Override::new(id, state, start, end).with_priority(priority));
+                self.next_override_id = id.next();
+                return Ok(id);
+            }
+        }
+
+        Err(anyhow!("output {:?} doesn't exist", output_name))
+    }
+
+    // Overrides which are in effect or which are yet to start.
+    pub fn overrides(&self) -> Vec<OverrideStatus> {
+        let now = self.clock.now_utc();
+
+        let mut result = vec![];
+        for output in &self.outputs {
+            for o in &output.overrides {
+                if o.end >= now {
+                    result.push(o.status(&output.definition.name));
+                }
+            }
+        }
+        result
+    }
+
+    pub fn output_overrides(&self, output_name: &OutputName) -> Result<Vec<OverrideStatus>> {
+        if !self
+            .outputs
+            .iter()
+            .any(|v| &v.definition.name == output_name)
+        {
+            return Err(anyhow!("output {:?} doesn't exist", output_name));
+        }
+
+        Ok(self
+            .overrides()
+            .into_iter()
+            .filter(|v| &v.output == output_name)
+            .collect())
+    }
+
+    // Override which won over the others during the last update.
+    pub fn override_in_effect(&self, output_name: &OutputName) -> Result<Option<OverrideId>> {
+        match self
+            .outputs
+            .iter()
+            .find(|v| &v.definition.name == output_name)
+        {
+            Some(output) => Ok(output.in_effect.as_ref().map(|v| v.id)),
+            None => Err(anyhow!("output {:?} doesn't exist", output_name)),
+        }
+    }
+
+    pub fn remove_override(&mut self, output_name: OutputName, id: OverrideId) -> Result<()> {
+        let Some(output) = self
+            .outputs
+            .iter_mut()
+            .find(|v| v.definition.name == output_name)
+        else {
+            return Err(anyhow!("output {:?} doesn't exist", output_name));
+        };
+
+        let Some(i) = output.overrides.iter().position(|v| v.id == id) else {
+            return Err(UnknownOverride {
+                output: output_name,
+                id,
+            }
+            .into());
+        };
+
+        info!("removing override {id} for output '{output_name}'");
+        output.overrides.remove(i);
+        Ok(())
+    }
+
+    // Either all overrides making up the scene are added or none of them are. The outputs are
+    // turned off first so that the interlocks don't get in the way. Applying a scene which is
+    // already in effect starts it over, its previous overrides are put back if that fails.
+    pub fn apply_scene(&mut self, name: &SceneName) -> Result<Vec<OverrideId>> {
+        let Some(scene) = self.scenes.iter().find(|v| &v.name == name).cloned() else {
+            return Err(anyhow!("scene '{name}' doesn't exist"));
+        };
+
+        let now = self.clock.now_utc();
+        let mut states = scene.states.clone();
+        states.sort_by_key(|(_, state)| *state != OutputState::Off);
+
+        // the previous overrides of the scene would otherwise conflict with the new ones
+        let mut previous: Vec<(usize, Override)> = vec![];
+        for (i, output) in self.outputs.iter_mut().enumerate() {
+            let (scene, other): (Vec<Override>, Vec<Override>) = output
+                .overrides
+                .drain(..)
+                .partition(|o| o.scene.as_ref() == Some(name));
+            output.overrides = other;
+            previous.extend(scene.into_iter().map(|o| (i, o)));
+        }
+
+        info!("applying scene '{name}'");
+        let mut added: Vec<(OutputName, OverrideId)> = vec![];
+        for (output_name, state) in states {
+            let result = NewOverride::new(state, now, scene.duration).and_then(|new_override| {
+                self.add_override(
+                    output_name.clone(),
+                    new_override.with_priority(scene.priority),
+                )
+            });
+            match result {
+                Ok(id) => added.push((output_name, id)),
+                Err(err) => {
+                    warn!("rolling back scene '{name}' as one of its overrides was rejected");
+                    for (output_name, id) in added {
+                        if let Err(err) = self.remove_override(output_name, id) {
+                            error!("error rolling back scene '{name}': {err}");
+                        }
+                    }
+                    for (i, o) in previous {
+                        self.outputs[i].overrides.push(o);
+                    }
+                    return Err(err);
+                }
+            }
+        }
+
+        for output in &mut self.outputs {
+            for o in &mut output.overrides {
+                if added.iter().any(|(_, id)| *id == o.id) {
+                    o.scene = Some(name.clone());
+                }
+            }
+        }
+        Ok(added.into_iter().map(|(_, id)| id).collect())
+    }
+
+    pub fn cancel_scene(&mut self, name: &SceneName) -> Result<()> {
+        if !self.scenes.iter().any(|v| &v.name == name) {
+            return Err(anyhow!("scene '{name}' doesn't exist"));
+        }
+
+        info!("cancelling scene '{name}'");
+        for output in &mut self.outputs {
+            output.overrides.retain(|o| o.scene.as_ref() != Some(name));
+        }
+        Ok(())
+    }
+
+    // Restores the overrides as they were without checking them against the interlocks etc. as
+    // that already happened when they were added. Overrides which expired in the meantime or
+    // which refer to outputs that are no longer there are dropped.
+    pub fn restore_overrides(&mut self, overrides: &[OverrideStatus]) {
+        let now = self.clock.now_utc();
+
+        for saved in overrides {
+            if saved.id >= self.next_override_id {
+                self.next_override_id = saved.id.next();
+            }
+
+            let name = &saved.output;
+            if saved.end < now {
+                info!(
+                    "dropping override to state {state} for output '{name}' as it expired at {end}",
+                    state = saved.state,
+                    end = self.clock.timezone().fixed_offset(&saved.end)
+                );
+                continue;
+            }
+
+            let Some(output) = self.outputs.iter_mut().find(|v| &v.definition.name == name) else {
+                warn!("dropping override for output '{name}' as that output no longer exists");
+                continue;
+            };
+
+            info!(
+                "restoring override to state {state} for output '{name}' lasting until {end}",
+                state = saved.state,
+                end = self.clock.timezone().fixed_offset(&saved.end)
+            );
+            output.overrides.push(
+                Override::new(saved.id, saved.state, saved.start, saved.end)
+                    .with_priority(saved.priority)
+                    .with_scene(saved.scene.clone()),
+            );
+        }
+    }
+
+    pub fn report_reading(&mut self, sensor: &SensorName, quantity: Quantity, value: f32) {
+        let now = self.clock.now_utc();
+        self.readings.put(sensor, quantity, value, now);
+    }
+
+    pub fn clear_overrides(&mut self, output_name: OutputName) -> Result<()> {
+        for output in &mut self.outputs {
+            if output.definition.name == output_name {
+                output.overrides.clear();
+                return Ok(());
+            }
+        }
+
+        Err(anyhow!("output {:?} doesn't exist", output_name))
+    }
+
+    pub fn fail_safe(&mut self) {
+        for output in &mut self.outputs {
+            let state = output.definition.safe_state;
+            output.set_pin(&state);
+        }
+    }
+
+    // Activations and overrides can be shorter than the interval between the updates so the
+    // caller should make sure to update the outputs again no later than after the returned amount
+    // of time.
+    pub fn next_change(&self) -> Option<TimeDelta> {
+        let now = self.clock.now();
+        let now_utc = self.clock.now_utc();
+        let scheduled = self
+            .outputs
+            .iter()
+            .filter_map(|v| {
+                v.definition
+                    .activations(self.profile.name.as_ref())
+                    .next_change(&now)
+            })
+            .map(|v| v - now);
+        let overrides = self
+            .outputs
+            .iter()
+            .flat_map(|v| &v.overrides)
+            .filter_map(|o| o.next_change(&now_utc))
+            .chain(self.hold.and_then(|v| v.next_change(&now_utc)))
+            .map(|v| v - now_utc);
+        scheduled
+            .chain(overrides)
+            .min()
+            .map(|v| v.max(TimeDelta::zero()))
+    }
+
+    // Freezes all outputs until resumed or until the hold runs out. Holding again replaces the
+    // previous hold.
+    pub fn hold(&mut self, hold: Hold) -> Result<()> {
+        let now = self.clock.now_utc();
+        if !hold.applies_at(&now) {
+            return Err(anyhow!("the hold would already be over"));
+        }
+
+        let timezone = self.clock.timezone();
+        match (hold.state, hold.until.map(|v| timezone.fixed_offset(&v))) {
+            (Some(state), Some(until)) => {
+                warn!("holding all outputs {state} until {until}")
+            }
+            (Some(state), None) => warn!("holding all outputs {state} until resumed"),
+            (None, Some(until)) => {
+                warn!("holding all outputs in their safe states until {until}")
+            }
+            (None, None) => warn!("holding all outputs in their safe states until resumed"),
+        }
+        self.hold = Some(hold);
+        Ok(())
+    }
+
+    pub fn resume(&mut self) {
+        if self.hold.take().is_some() {
+            info!("resuming the automation");
+        }
+    }
+
+    pub fn current_hold(&self) -> Option<Hold> {
+        self.hold
+    }
+
+    // Unlike the overrides the hold is restored even if it refers to e.g. a state which makes no
+    // sense anymore as it is better to keep the outputs frozen than to lose it.
+    pub fn restore_hold(&mut self, hold: Hold) {
+        let now = self.clock.now_utc();
+        if !hold.applies_at(&now) {
+            info!("dropping the hold as it ran out while the program wasn't running");
+            return;
+        }
+
+        warn!("restoring the hold, the outputs stay frozen until it is resumed or runs out");
+        self.hold = Some(hold);
+    }
+
+    // Switches to the given profile or back to the default schedule. The profile is kept until
+    // another one is switched to, either by hand or automatically.
+    pub fn switch_profile(&mut self, name: Option<&ProfileName>) -> Result<()> {
+        if let Some(name) = name {
+            if !self.profiles.iter().any(|v| &v.name == name) {
+                return Err(anyhow!("unknown profile '{name}'"));
+            }
+        }
+
+        match name {
+            Some(name) => info!("switching to profile '{name}'"),
+            None => info!("switching to the default schedule"),
+        }
+        self.profile = ActiveProfile {
+            name: name.cloned(),
+            date: self.clock.now().date(),
+        };
+        Ok(())
+    }
+
+    pub fn current_profile(&self) -> ActiveProfile {
+        self.profile.clone()
+    }
+
+    pub fn profiles(&self) -> &[Profile] {
+        &self.profiles
+    }
+
+    // Profiles which were removed from the config are ignored. The automatic switching catches up
+    // with the dates which passed while the program wasn't running during the next update.
+    pub fn restore_profile(&mut self, profile: ActiveProfile) {
+        if let Some(name) = &profile.name {
+            if !self.profiles.iter().any(|v| &v.name == name) {
+                warn!("not restoring the unknown profile '{name}'");
+                return;
+            }
+        }
+
+        self.profile = profile;
+    }
+
+    // Predicts what the outputs will do from now on, see ScheduleSnapshot::preview.
+    pub fn preview(&self, duration: TimeDelta) -> Result<Vec<Transition>> {
+        self.schedule().preview(duration)
+    }
+
+    // Copy of everything the preview needs so that it can run without holding up the controller.
+    pub fn schedule(&self) -> ScheduleSnapshot {
+        ScheduleSnapshot {
+            outputs: self
+                .outputs
+                .iter()
+                .map(|v| ScheduledOutput {
+                    definition: v.definition.clone(),
+                    overrides: v.overrides.clone(),
+                })
+                .collect(),
+            interlocks: self.interlocks.clone(),
+            profiles: self.profiles.clone(),
+            profile: self.profile.clone(),
+            hold: self.hold,
+            now: self.clock.now(),
+            now_utc: self.clock.now_utc(),
+            timezone: self.clock.timezone(),
+        }
+    }
+
+    pub fn status(&self) -> Vec<OutputStatus> {
+        let mut result = vec![];
+        for output in &self.outputs {
+            let status = OutputStatus {
+                name: output.definition.name.clone(),
+                state: Brightness::from(output.duty_cycle()).into(),
+                blocked_by: output.blocked_by.clone(),
+                limit_exceeded: output.runtime.exceeded,
+                deferred: output.deferred,
+                running_dry: output.running_dry,
+                top_off_failed: output.top_off.failure(),
+                override_in_effect: output.in_effect.clone(),
+                on_today: output.runtime.on_today,
+            };
+            result.push(status);
+        }
+        result
+    }
+}
+
+pub struct OutputStatus {
+    pub name: OutputName,
+    pub state: OutputState,
+    pub blocked_by: Option<Interlock>,
+    pub limit_exceeded: Option<Limit>,
+    pub deferred: Option<DeferredTransition>,
+    pub running_dry: bool,
+    pub top_off_failed: Option<TopOffFailure>,
+    pub override_in_effect: Option<OverrideStatus>,
+    pub on_today: TimeDelta,
+}
+
+fn scheduled_profile<'a>(profiles: &'a [Profile], date: &NaiveDate) -> Option<&'a ProfileName> {
+    profiles
+        .iter()
+        .find(|v| v.dates.is_some_and(|dates| dates.includes(date)))
+        .map(|v| &v.name)
+}
+
+// Automatically switches profiles whenever the profile which should be active according to
+// the dates changed since the last time this was checked, no matter if the current profile was
+// switched to by hand.
+fn profile_on(profiles: &[Profile], active: &ActiveProfile, date: &NaiveDate) -> ActiveProfile {
+    let mut day = active.date;
+    while day < *date {
+        let next = day + TimeDelta::days(1);
+        if scheduled_profile(profiles, &day) != scheduled_profile(profiles, &next) {
+            return ActiveProfile {
+                name: scheduled_profile(profiles, date).cloned(),
+                date: *date,
+            };
+        }
+        day = next;
+    }
+
+    ActiveProfile {
+        name: active.name.clone(),
+        date: active.date.max(*date),
+    }
+}
+
+fn next_profile_switch(profiles: &[Profile], time: &NaiveDateTime) -> Option<NaiveDateTime> {
+    if !profiles.iter().any(|v| v.dates.is_some()) {
+        return None;
+    }
+    (time.date() + TimeDelta::days(1)).and_hms_opt(0, 0, 0)
+}
+
+// Interlocks are checked against the states the other outputs end up in, e.g. if A excludes B
+// and B excludes C then C being on turns B off which in turn lets A be on. When there is no
+// single answer, e.g. two outputs excluding each other both want to be on, the outputs in
+// question stay off. The order of the outputs doesn't matter.
+fn apply_interlocks(
+    interlocks: &[Interlock],
+    names: &[&OutputName],
+    states: &mut [OutputState],
+) -> Vec<Option<Interlock>> {
+    let desired = states.to_vec();
+    let mut blocked_by = vec![None; states.len()];
+    let mut previous = None;
+    let mut settled = false;
+    for _ in 0..=2 * states.len() {
+        let next: Vec<OutputState> = names
+            .iter()
+            .zip(&desired)
+            .zip(&mut blocked_by)
+            .map(|((name, state), blocked_by)| {
+                if *state == OutputState::Off {
+                    return OutputState::Off;
+                }
+                match blocking_interlock(interlocks, names, name, states) {
+                    Some(interlock) => {
+                        *blocked_by = Some(interlock.clone());
+                        OutputState::Off
+                    }
+                    None => *state,
+                }
+            })
+            .collect();
+
+        if next == states {
+            settled = true;
+            break;
+        }
+        previous = Some(states.to_vec());
+        states.copy_from_slice(&next);
+    }
+
+    // the outputs flip back and forth, the ones which are off in either case stay off
+    if let (false, Some(previous)) = (settled, previous) {
+        for (state, previous) in states.iter_mut().zip(previous) {
+            if previous == OutputState::Off {
+                *state = OutputState::Off;
+            }
+        }
+    }
+
+    // only ever turns outputs off so going over the interlocks until nothing changes is
+    // guaranteed to end
+    loop {
+        let blocked: Vec<(usize, Interlock)> = names
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| states[*i] != OutputState::Off)
+            .filter_map(|(i, name)| {
+                blocking_interlock(interlocks, names, name, states)
+                    .map(|interlock| (i, interlock.clone()))
+            })
+            .collect();
+        if blocked.is_empty() {
+            break;
+        }
+        for (i, interlock) in blocked {
+            states[i] = OutputState::Off;
+            blocked_by[i] = Some(interlock);
+        }
+    }
+
+    for (state, blocked_by) in states.iter().zip(&mut blocked_by) {
+        if *state != OutputState::Off {
+            *blocked_by = None;
+        }
+    }
+    blocked_by
+}
+
+fn blocking_interlock<'a>(
+    interlocks: &'a [Interlock],
+    names: &[&OutputName],
+    name: &OutputName,
+    states: &[OutputState],
+) -> Option<&'a Interlock> {
+    interlocks.iter().find(|interlock| {
+        &interlock.output == name
+            && names
+                .iter()
+                .position(|v| **v == interlock.other)
+                .is_some_and(|i| interlock.blocks(&states[i]))
+    })
+}
+
+// What the preview needs to know about the controller, copied so that the preview doesn't hold up
+// the controller while it runs.
+#[derive(Debug, Clone)]
+pub struct ScheduleSnapshot {
+    outputs: Vec<ScheduledOutput>,
+    interlocks: Vec<Interlock>,
+    profiles: Vec<Profile>,
+    profile: ActiveProfile,
+    hold: Option<Hold>,
+    now: NaiveDateTime,
+    now_utc: DateTime<Utc>,
+    timezone: Timezone,
+}
+
+impl ScheduleSnapshot {
+    // Predicts what the outputs will do from now on based on the schedules, the overrides and the
+    // interlocks. Regulation, topping off, dry run protection and the limits depend on the
+    // readings and on what actually happened so they are ignored. The first transition of every
+    // output describes its state right now.
+    pub fn preview(&self, duration: TimeDelta) -> Result<Vec<Transition>> {
+        if duration <= TimeDelta::zero() || duration > MAX_PREVIEW {
+            return Err(anyhow!(
+                "the preview can cover up to {} days",
+                MAX_PREVIEW.num_days()
+            ));
+        }
+
+        let mut time = self.now;
+        let until = time + duration;
+        let mut result = vec![];
+        let mut previous: Vec<Option<OutputState>> = vec![None; self.outputs.len()];
+        let mut profile = self.profile.clone();
+        loop {
+            profile = profile_on(&self.profiles, &profile, &time.date());
+            let hold = self.hold.filter(|v| v.applies_at(&self.utc(&time)));
+            let mut states: Vec<OutputState> = self
+                .outputs
+                .iter()
+                .map(|v| match &hold {
+                    Some(hold) => hold.state_of(&v.definition),
+                    None => v.scheduled_state(&time, &self.utc(&time), profile.name.as_ref()),
+                })
+                .collect();
+            let names: Vec<&OutputName> = self.outputs.iter().map(|v| &v.definition.name).collect();
+            apply_interlocks(&self.interlocks, &names, &mut states);
+
+            for ((output, state), previous) in self.outputs.iter().zip(states).zip(&mut previous) {
+                if *previous != Some(state) {
+                    result.push(Transition {
+                        time,
+                        output: output.definition.name.clone(),
+                        state,
+                    });
+                    *previous = Some(state);
+                }
+            }
+
+            let next = self
+                .outputs
+                .iter()
+                .filter_map(|v| {
+                    v.definition
+                        .activations(profile.name.as_ref())
+                        .next_change(&time)
+                })
+                .chain(self.next_override_change(&time))
+                .chain(self.next_hold_change(hold, &time))
+                .chain(next_profile_switch(&self.profiles, &time))
+                .min();
+            match next {
+                Some(next) if next <= until => time = next,
+                _ => return Ok(result),
+            }
+        }
+    }
+
+    // The preview moves along the wall clock so while it stands still the actual time is the one
+    // from when the snapshot was taken.
+    fn utc(&self, time: &NaiveDateTime) -> DateTime<Utc> {
+        self.timezone.resolve(time).to_utc().max(self.now_utc)
+    }
+
+    // Overrides ending while the wall clock stands still show up once it moves on.
+    fn next_override_change(&self, time: &NaiveDateTime) -> Option<NaiveDateTime> {
+        let now_utc = self.utc(time);
+        self.outputs
+            .iter()
+            .flat_map(|v| &v.overrides)
+            .filter_map(|o| o.next_change(&now_utc))
+            .map(|v| self.timezone.wall_clock(&v))
+            .filter(|v| v > time)
+            .min()
+    }
+
+    fn next_hold_change(&self, hold: Option<Hold>, time: &NaiveDateTime) -> Option<NaiveDateTime> {
+        hold.and_then(|v| v.next_change(&self.utc(time)))
+            .map(|v| self.timezone.wall_clock(&v))
+            .filter(|v| v > time)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ScheduledOutput {
+    definition: OutputDefinition,
+    overrides: Vec<Override>,
+}
+
+impl ScheduledOutput {
+    // Same as ControlledOutput::peek_state but only takes into account what doesn't depend on the
+    // readings.
+    fn scheduled_state(
+        &self,
+        now: &NaiveDateTime,
+        now_utc: &DateTime<Utc>,
+        profile: Option<&ProfileName>,
+    ) -> OutputState {
+        if let Some(o) = override_in_effect(&self.overrides, now_utc) {
+            return o.state;
+        }
+
+        match self
+            .definition
+            .activations(profile)
+            .nominal_brightness_at(now)
+        {
+            Some(brightness) => brightness.into(),
+            None => OutputState::Off,
+        }
+    }
+}
+
+// The profile in use, none meaning the default schedule, and the last day on which it was
+// checked whether a profile should be switched to automatically.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ActiveProfile {
+    name: Option<ProfileName>,
+    date: NaiveDate,
+}
+
+impl ActiveProfile {
+    pub fn new(name: Option<ProfileName>, date: NaiveDate) -> Self {
+        Self { name, date }
+    }
+
+    pub fn name(&self) -> Option<&ProfileName> {
+        self.name.as_ref()
+    }
+
+    pub fn date(&self) -> NaiveDate {
+        self.date
+    }
+}
+
+// Freezes all outputs either in their safe states or in the given state. Unlike the overrides it
+// also applies to activations which are yet to happen.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Hold {
+    state: Option<OutputState>,
+    until: Option<DateTime<Utc>>,
+}
+
+impl Hold {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_state(self, state: OutputState) -> Result<Self> {
+        if let OutputState::Dimmed(_) = state {
+            return Err(anyhow!("the outputs can only be held either on or off"));
+        }
+
+        Ok(Self {
+            state: Some(state),
+            ..self
+        })
+    }
+
+    pub fn with_until(self, until: DateTime<Utc>) -> Self {
+        Self {
+            until: Some(until),
+            ..self
+        }
+    }
+
+    pub fn state(&self) -> Option<OutputState> {
+        self.state
+    }
+
+    pub fn until(&self) -> Option<DateTime<Utc>> {
+        self.until
+    }
+
+    fn applies_at(&self, time: &DateTime<Utc>) -> bool {
+        self.until.is_none_or(|until| *time <= until)
+    }
+
+    fn state_of(&self, definition: &OutputDefinition) -> OutputState {
+        self.state.unwrap_or(definition.safe_state)
+    }
+
+    fn next_change(&self, time: &DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.until
+            .map(|until| until + TimeDelta::milliseconds(1))
+            .filter(|v| v > time)
+    }
+}
+
+// Change of the state of an output predicted by the preview.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transition {
+    pub time: NaiveDateTime,
+    pub output: OutputName,
+    pub state: OutputState,
+}
+
+// Override expressed using absolute times so that it can be listed over the API as well as stored
+// and restored later.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OverrideStatus {
+    id: OverrideId,
+    output: OutputName,
+    state: OutputState,
+    priority: OverridePriority,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    scene: Option<SceneName>,
+}
+
+impl OverrideStatus {
+    pub fn new(
+        id: OverrideId,
+        output: OutputName,
+        state: OutputState,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<Self> {
+        if start >= end {
+            return Err(anyhow!("override has to end after it starts"));
+        }
+
+        Ok(Self {
+            id,
+            output,
+            state,
+            priority: OverridePriority::default(),
+            start,
+            end,
+            scene: None,
+        })
+    }
+
+    pub fn with_priority(self, priority: OverridePriority) -> Self {
+        Self { priority, ..self }
+    }
+
+    pub fn with_scene(self, scene: SceneName) -> Self {
+        Self {
+            scene: Some(scene),
+            ..self
+        }
+    }
+
+    pub fn id(&self) -> OverrideId {
+        self.id
+    }
+
+    pub fn output(&self) -> &OutputName {
+        &self.output
+    }
+
+    pub fn state(&self) -> OutputState {
+        self.state
+    }
+
+    pub fn priority(&self) -> OverridePriority {
+        self.priority
+    }
+
+    pub fn start(&self) -> DateTime<Utc> {
+        self.start
+    }
+
+    pub fn end(&self) -> DateTime<Utc> {
+        self.end
+    }
+
+    // Scene which created this override.
+    pub fn scene(&self) -> Option<&SceneName> {
+        self.scene.as_ref()
+    }
+}
+
+// A transition that will happen once the output has been on or off for long enough.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DeferredTransition {
+    pub state: OutputState,
+    pub until: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputState {
+    On,
+    Off,
+    Dimmed(Brightness),
+}
+
+impl OutputState {
+    pub fn brightness(&self) -> Brightness {
+        match self {
+            OutputState::On => Brightness::full(),
+            OutputState::Off => Brightness::off(),
+            OutputState::Dimmed(brightness) => *brightness,
+        }
+    }
+}
+
+impl From<Brightness> for OutputState {
+    fn from(value: Brightness) -> Self {
+        if value == Brightness::full() {
+            Self::On
+        } else if value == Brightness::off() {
+            Self::Off
+        } else {
+            Self::Dimmed(value)
+        }
+    }
+}
+
+impl Display for OutputState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OutputState::On => write!(f, "on"),
+            OutputState::Off => write!(f, "off"),
+            OutputState::Dimmed(brightness) => write!(f, "{}", brightness),
+        }
+    }
+}
+
+// Identifies an override so that it can be removed later. Identifiers are never reused, also
+// across restarts as long as the overrides are persisted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct OverrideId {
+    id: u64,
+}
+
+impl OverrideId {
+    pub fn new(id: u64) -> Self {
+        Self { id }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn next(&self) -> Self {
+        Self { id: self.id + 1 }
+    }
+}
+
+impl Display for OverrideId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.id)
+    }
+}
+
+// Overrides with a higher priority win e.g. an emergency shutdown can beat a routine override
+// that turns the output on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct OverridePriority {
+    priority: u32,
+}
+
+impl OverridePriority {
+    pub fn new(priority: u32) -> Self {
+        Self { priority }
+    }
+
+    pub fn priority(&self) -> u32 {
+        self.priority
+    }
+}
+
+impl Display for OverridePriority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.priority)
+    }
+}
+
+// Override which is yet to be added to an output, most of them don't care about the priority.
+// Unlike the schedules overrides last for the given amount of actual time, also when the clocks
+// change in the meantime.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewOverride {
+    state: OutputState,
+    start: DateTime<Utc>,
+    duration: TimeDelta,
+    priority: OverridePriority,
+}
+
+impl NewOverride {
+    pub fn new(state: OutputState, start: DateTime<Utc>, duration: TimeDelta) -> Result<Self> {
+        ScheduledActivation::validate_duration(duration)?;
+
+        Ok(Self {
+            state,
+            start,
+            duration,
+            priority: OverridePriority::default(),
+        })
+    }
+
+    pub fn with_priority(self, priority: OverridePriority) -> Self {
+        Self { priority, ..self }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Override {
+    id: OverrideId,
+    state: OutputState,
+    priority: OverridePriority,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    scene: Option<SceneName>,
+}
+
+impl Override {
+    fn new(id: OverrideId, state: OutputState, start: DateTime<Utc>, end: DateTime<Utc>) -> Self {
+        Self {
+            id,
+            state,
+            priority: OverridePriority::default(),
+            start,
+            end,
+            scene: None,
+        }
+    }
+
+    fn with_priority(self, priority: OverridePriority) -> Self {
+        Self { priority, ..self }
+    }
+
+    fn with_scene(self, scene: Option<SceneName>) -> Self {
+        Self { scene, ..self }
+    }
+
+    fn conflicts_with(
+        &self,
+        state: OutputState,
+        priority: OverridePriority,
+        start: &DateTime<Utc>,
+        end: &DateTime<Utc>,
+    ) -> bool {
+        if self.priority != priority || self.state == state {
+            return false;
+        }
+        self.start < *end && *start < self.end
+    }
+
+    // Same as with the activations the end is inclusive.
+    fn has_inside(&self, now: &DateTime<Utc>) -> bool {
+        self.start <= *now && *now <= self.end
+    }
+
+    fn next_change(&self, now: &DateTime<Utc>) -> Option<DateTime<Utc>> {
+        if self.start > *now {
+            Some(self.start)
+        } else if self.end >= *now {
+            Some(self.end + TimeDelta::milliseconds(1))
+        } else {
+            None
+        }
+    }
+
+    fn status(&self, output: &OutputName) -> OverrideStatus {
+        OverrideStatus {
+            id: self.id,
+            output: output.clone(),
+            state: self.state,
+            priority: self.priority,
+            start: self.start,
+            end: self.end,
+            scene: self.scene.clone(),
+        }
+    }
+}
+
+// The override with the highest priority wins. Restored overrides aren't checked for conflicts so
+// if there are several ones with the same priority the newest one wins.
+fn override_in_effect<'a>(overrides: &'a [Override], now: &DateTime<Utc>) -> Option<&'a Override> {
+    overrides
+        .iter()
+        .filter(|o| o.has_inside(now))
+        .max_by_key(|o| (o.priority, o.id))
+}
+
+struct ControlledOutput<OP: OutputPin> {
+    definition: OutputDefinition,
+    pin: OP,
+    overrides: Vec<Override>,
+    in_effect: Option<OverrideStatus>,
+    blocked_by: Option<Interlock>,
+    runtime: Runtime,
+    deferred: Option<DeferredTransition>,
+    regulating: bool,
+    running_dry: bool,
+    top_off: TopOffState,
+    pid: Option<TimeProportionedPid>,
+}
+
+#[derive(Default)]
+struct Runtime {
+    last_transition: Option<DateTime<Utc>>,
+    last_update: Option<DateTime<Utc>>,
+    on_since: Option<DateTime<Utc>>,
+    date: Option<NaiveDate>,
+    on_today: TimeDelta,
+    exceeded: Option<Limit>,
+}
+
+impl<OP: OutputPin> ControlledOutput<OP> {
+    fn target_state(
+        &mut self,
+        now: &NaiveDateTime,
+        now_utc: &DateTime<Utc>,
+        readings: &Readings,
+        profile: Option<&ProfileName>,
+    ) -> OutputState {
+        self.regulate(now, now_utc, readings);
+        self.top_off(now_utc, readings);
+
+        self.in_effect =
+            override_in_effect(&self.overrides, now_utc).map(|o| o.status(&self.definition.name));
+        self.peek_state(now, now_utc, profile)
+    }
+
+    // Same as target_state but doesn't keep track of anything.
+    fn peek_state(
+        &self,
+        now: &NaiveDateTime,
+        now_utc: &DateTime<Utc>,
+        profile: Option<&ProfileName>,
+    ) -> OutputState {
+        if let Some(o) = override_in_effect(&self.overrides, now_utc) {
+            return o.state;
+        }
+
+        match self.definition.activations(profile).brightness_at(now) {
+            Some(brightness) => brightness.into(),
+            None if self.regulating || self.top_off.is_filling() => OutputState::On,
+            None => OutputState::Off,
+        }
+    }
+
+    fn conflicting_override(
+        &self,
+        state: OutputState,
+        priority: OverridePriority,
+        start: &DateTime<Utc>,
+        end: &DateTime<Utc>,
+    ) -> Option<&Override> {
+        self.overrides
+            .iter()
+            .find(|o| o.conflicts_with(state, priority, start, end))
+    }
+
+    fn regulate(&mut self, now: &NaiveDateTime, now_utc: &DateTime<Utc>, readings: &Readings) {
+        let Some(regulation) = &self.definition.regulation else {
+            return;
+        };
+        let name = &self.definition.name;
+        let quantity = regulation.quantity();
+
+        let regulating = match readings.get(regulation.sensor(), quantity, now_utc) {
+            Some(reading) => {
+                if let Some(pid) = &mut self.pid {
+                    let regulating = pid.is_on(regulation.error(reading, &now.time()), now_utc);
+                    if regulating != self.regulating {
+                        debug!(
+                            "{quantity} is {reading} so output '{name}' is now regulated {state} with a duty cycle of {duty_cycle:.2}",
+                            state = if regulating { "on" } else { "off" },
+                            duty_cycle = pid.duty_cycle(),
+                        );
+                    }
+                    self.regulating = regulating;
+                    return;
+                }
+
+                let regulating = regulation.is_on(reading, self.regulating, &now.time());
+                if regulating != self.regulating {
+                    info!(
+                        "{quantity} is {reading} so output '{name}' is now regulated {state}",
+                        state = if regulating { "on" } else { "off" }
+                    );
+                }
+                regulating
+            }
+            None => {
+                if self.regulating {
+                    warn!("no recent {quantity} readings, output '{name}' is now regulated off");
+                }
+                if let Some(pid) = &mut self.pid {
+                    pid.reset();
+                }
+                false
+            }
+        };
+        self.regulating = regulating;
+    }
+
+    fn top_off(&mut self, now: &DateTime<Utc>, readings: &Readings) {
+        let Some(top_off) = &self.definition.top_off else {
+            return;
+        };
+        let name = &self.definition.name;
+        let sensor = top_off.sensor();
+
+        let level = readings.get(sensor, Quantity::WaterLevel, now);
+        // the pin still reflects what happened since the last update
+        let pumping = self.duty_cycle() != DutyCycle::off();
+        let state = top_off.update(self.top_off, level, pumping, now);
+        match (&self.top_off, &state) {
+            (TopOffState::Idle, TopOffState::Filling { level, .. }) => info!(
+                "water level reported by sensor '{sensor}' dropped to {level:.0}%, output '{name}' is topping off the water"
+            ),
+            (TopOffState::Filling { .. }, TopOffState::Idle) => info!(
+                "water level reported by sensor '{sensor}' reached the high mark, output '{name}' is done topping off the water"
+            ),
+            (TopOffState::Filling { .. }, TopOffState::Failed(failure)) => error!(
+                "output '{name}' stopped topping off the water as the {failure}, it will stay off until the water level reported by sensor '{sensor}' reaches the high mark"
+            ),
+            (TopOffState::Failed(_), TopOffState::Idle) => info!(
+                "water level reported by sensor '{sensor}' reached the high mark, output '{name}' can top off the water again"
+            ),
+            _ => {}
+        }
+        self.top_off = state;
+    }
+
+    fn protect_from_running_dry(
+        &mut self,
+        state: OutputState,
+        now: &DateTime<Utc>,
+        readings: &Readings,
+    ) -> OutputState {
+        let Some(protection) = &self.definition.dry_run_protection else {
+            return state;
+        };
+        let name = &self.definition.name;
+        let sensor = protection.sensor();
+
+        let level = readings.get(sensor, Quantity::WaterLevel, now);
+        let running_dry = protection.is_running_dry(level, self.running_dry);
+        if running_dry != self.running_dry {
+            match level {
+                Some(level) if running_dry => error!(
+                    "water level reported by sensor '{sensor}' dropped to {level:.0}%, output '{name}' is kept off to protect it from running dry"
+                ),
+                Some(level) => info!(
+                    "water level reported by sensor '{sensor}' is back at {level:.0}%, output '{name}' is no longer kept off"
+                ),
+                None => warn!(
+                    "no recent water level readings from sensor '{sensor}', output '{name}' is kept off to protect it from running dry"
+                ),
+            }
+        }
+        self.running_dry = running_dry;
+
+        if running_dry {
+            OutputState::Off
+        } else {
+            state
+        }
+    }
+
+    // Figures out for how long the output has been on based on the state of the pin between the
+    // updates. Dimmed outputs count as being on.
+    // The daily budget is reset when the date on the wall clock changes.
+    fn enforce_limits(
+        &mut self,
+        state: OutputState,
+        now: &NaiveDateTime,
+        now_utc: &DateTime<Utc>,
+    ) -> OutputState {
+        let was_on = self.duty_cycle() != DutyCycle::off();
+        let name = &self.definition.name;
+        let runtime = &mut self.runtime;
+
+        if was_on {
+            if let Some(last_update) = runtime.last_update {
+                runtime.on_today += *now_utc - last_update;
+                runtime.on_since.get_or_insert(last_update);
+            }
+        } else {
+            runtime.on_since = None;
+        }
+        runtime.last_update = Some(*now_utc);
+
+        if runtime.date != Some(now.date()) {
+            runtime.date = Some(now.date());
+            runtime.on_today = TimeDelta::zero();
+            if runtime.exceeded == Some(Limit::DailyBudget) {
+                info!("output '{name}' has a new daily runtime budget");
+                runtime.exceeded = None;
+            }
+        }
+
+        if state == OutputState::Off && runtime.exceeded == Some(Limit::MaxOnTime) {
+            info!("output '{name}' is no longer kept off due to its maximum on-time");
+            runtime.exceeded = None;
+        }
+
+        if runtime.exceeded.is_none() && state != OutputState::Off {
+            let max_on_time_exceeded = match (self.definition.max_on_seconds, runtime.on_since) {
+                (Some(max_on_seconds), Some(on_since)) => {
+                    *now_utc - on_since >= TimeDelta::seconds(max_on_seconds.into())
+                }
+                _ => false,
+            };
+            let daily_budget_exceeded = match self.definition.daily_budget_seconds {
+                Some(daily_budget_seconds) => {
+                    runtime.on_today >= TimeDelta::seconds(daily_budget_seconds.into())
+                }
+                None => false,
+            };
+
+            if max_on_time_exceeded {
+                runtime.exceeded = Some(Limit::MaxOnTime);
+            } else if daily_budget_exceeded {
+                runtime.exceeded = Some(Limit::DailyBudget);
+            }
+
+            if let Some(limit) = runtime.exceeded {
+                error!("output '{name}' exceeded its {limit} and is being turned off");
+            }
+        }
+
+        match runtime.exceeded {
+            Some(_) => OutputState::Off,
+            None => state,
+        }
+    }
+
+    fn defer_transitions(&mut self, state: OutputState, now: &DateTime<Utc>) -> OutputState {
+        let is_on = self.duty_cycle() != DutyCycle::off();
+        let wants_on = state != OutputState::Off;
+
+        // limits and dry-run protection are there for safety so they always win
+        let min_seconds = match (is_on, wants_on) {
+            (true, false) if self.runtime.exceeded.is_none() && !self.running_dry => {
+                self.definition.min_on_seconds
+            }
+            (false, true) => self.definition.min_off_seconds,
+            _ => None,
+        };
+
+        let until = match (min_seconds, self.runtime.last_transition) {
+            (Some(min_seconds), Some(last_transition)) => {
+                last_transition + TimeDelta::seconds(min_seconds.into())
+            }
+            _ => {
+                self.deferred = None;
+                return state;
+            }
+        };
+
+        if *now >= until {
+            self.deferred = None;
+            return state;
+        }
+
+        let deferred = DeferredTransition { state, until };
+        if self.deferred != Some(deferred) {
+            info!(
+                "deferring changing the state of output '{name}' to {state} until {until} to avoid rapid cycling",
+                name = self.definition.name
+            );
+        }
+        self.deferred = Some(deferred);
+        Brightness::from(self.duty_cycle()).into()
+    }
+
+    fn update_blocked_by(&mut self, blocked_by: Option<Interlock>) {
+        if self.blocked_by != blocked_by {
+            let name = &self.definition.name;
+            match &blocked_by {
+                Some(interlock) => warn!("output '{name}' is being kept off as {interlock}"),
+                None => info!("output '{name}' is no longer blocked by any interlocks"),
+            }
+        }
+        self.blocked_by = blocked_by;
+    }
+
+    fn apply(&mut self, state: &OutputState, now: &DateTime<Utc>) {
+        let was_on = self.duty_cycle() != DutyCycle::off();
+        self.set_pin(state);
+        if was_on != (self.duty_cycle() != DutyCycle::off()) {
+            self.runtime.last_transition = Some(*now);
+        }
+    }
+
+    fn set_pin(&mut self, state: &OutputState) {
+        let name = &self.definition.name;
+
+        if self.definition.pwm.is_none() {
+            let on = state != &OutputState::Off;
+            let level = if on != self.definition.active_low {
+                OutputPinState::High
+            } else {
+                OutputPinState::Low
+            };
+            if self.pin.state() != level {
+                if on {
+                    info!("turning on output '{name}'");
+                } else {
+                    info!("turning off output '{name}'");
+                }
+                match level {
+                    OutputPinState::High => self.pin.set_high(),
+                    OutputPinState::Low => self.pin.set_low(),
+                }
+            }
+            return;
+        }
+
+        let current = self.duty_cycle();
+        let target = DutyCycle::from(state.brightness());
+        if self.pin.duty_cycle() == self.definition.polarity(target) {
+            return;
+        }
+
+        if target == DutyCycle::off() {
+            info!("turning off output '{name}'");
+        } else if current == DutyCycle::off() {
+            info!("turning on output '{name}' at {state}");
+        } else {
+            debug!("dimming output '{name}' to {state}");
+        }
+
+        if let Err(err) = self.pin.set_duty_cycle(self.definition.polarity(target)) {
+            error!("error changing the duty cycle of output '{name}': {err}");
+        }
+    }
+
+    // Duty cycle of the output as opposed to the duty cycle of the pin.
+    fn duty_cycle(&self) -> DutyCycle {
+        self.definition.polarity(self.pin.duty_cycle())
+    }
+
+    // Overrides can also run out between the updates without ever being in effect e.g. if they
+    // are very short or the program was stopped in the meantime.
+    fn cleanup_overrides(&mut self, now: &DateTime<Utc>) {
+        self.overrides.retain(|v| v.end >= *now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Local;
+
+    #[cfg(test)]
     mod scheduled_activation {
+        use std::collections::HashSet;
         use std::panic;
 
         use super::*;
 
         #[test]
-        fn test_has_inside() -> Result<()> {
+        fn test_has_inside() -> Result<()> {
+            struct TestCase<'a> {
+                name: &'a str,
+                activation: ScheduledActivation,
+                time: NaiveDateTime,
+                expected_has_inside: bool,
+            }
+
+            let test_cases = vec![
+                TestCase {
+                    name: "midnight_start",
+                    activation: ScheduledActivation::new(
+                        new_time(23, 59, 55),
+                        TimeDelta::seconds(10),
+                    )?,
+                    time: new_datetime(23, 59, 55),
+                    expected_has_inside: true,
+                },
+                TestCase {
+                    name: "midnight_middle_before",
+                    activation: ScheduledActivation::new(
+                        new_time(23, 59, 55),
+                        TimeDelta::seconds(10),
+                    )?,
+                    time: new_datetime(23, 59, 59),
+                    expected_has_inside: true,
+                },
+                TestCase {
+                    name: "midnight_middle_after",
+                    activation: ScheduledActivation::new(
+                        new_time(23, 59, 55),
+                        TimeDelta::seconds(10),
+                    )?,
+                    time: new_datetime(00, 00, 00),
+                    expected_has_inside: true,
+                },
+                TestCase {
+                    name: "midnight_end",
+                    activation: ScheduledActivation::new(
+                        new_time(23, 59, 55),
+                        TimeDelta::seconds(10),
+                    )?,
+                    time: new_datetime(00, 00, 5),
+                    expected_has_inside: true,
+                },
+                TestCase {
+                    name: "midnight_outside",
+                    activation: ScheduledActivation::new(
+                        new_time(23, 59, 55),
+                        TimeDelta::seconds(10),
+                    )?,
+                    time: new_datetime(12, 00, 00),
+                    expected_has_inside: false,
+                },
+                TestCase {
+                    name: "normal_start",
+                    activation: ScheduledActivation::new(
+                        new_time(12, 0, 0),
+                        TimeDelta::seconds(10),
+                    )?,
+                    time: new_datetime(12, 0, 0),
+                    expected_has_inside: true,
+                },
+                TestCase {
+                    name: "normal_middle",
+                    activation: ScheduledActivation::new(
+                        new_time(12, 0, 0),
+                        TimeDelta::seconds(10),
+                    )?,
+                    time: new_datetime(12, 0, 5),
+                    expected_has_inside: true,
+                },
+                TestCase {
+                    name: "normal_end",
+                    activation: ScheduledActivation::new(
+                        new_time(12, 0, 0),
+                        TimeDelta::seconds(10),
+                    )?,
+                    time: new_datetime(12, 0, 10),
+                    expected_has_inside: true,
+                },
+                TestCase {
+                    name: "normal_outside",
+                    activation: ScheduledActivation::new(
+                        new_time(12, 0, 0),
+                        TimeDelta::seconds(10),
+                    )?,
+                    time: new_datetime(18, 0, 0),
+                    expected_has_inside: false,
+                },
+                TestCase {
+                    name: "always_turned_on",
+                    activation: ScheduledActivation::new(
+                        new_time(0, 0, 0),
+                        TimeDelta::seconds(24 * 3600),
+                    )?,
+                    time: new_datetime(1, 0, 0),
+                    expected_has_inside: true,
+                },
+            ];
+
+            for test_case in &test_cases {
+                println!("test case: {}", test_case.name);
+                assert_eq!(
+                    test_case.activation.has_inside(&test_case.time),
+                    test_case.expected_has_inside
+                );
+            }
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_has_inside_solar() -> Result<()> {
+            let location = Location::new(51.5074, -0.1278)?;
+            let timezone = Timezone::new("Europe/London")?;
+            let activation = ScheduledActivation::new(
+                ActivationTime::new_solar(SolarEvent::Sunrise, TimeDelta::minutes(30), location)?,
+                TimeDelta::seconds(60),
+            )?
+            .with_timezone(timezone);
+
+            let summer = NaiveDate::from_ymd_opt(2024, 6, 21).unwrap();
+            let summer_sunrise =
+                timezone.local(&solar::calculate(SolarEvent::Sunrise, &summer, &location));
+            assert!(activation.has_inside(&(summer_sunrise + TimeDelta::seconds(30 * 60 + 30))));
+            assert!(!activation.has_inside(&summer_sunrise));
+
+            let winter = NaiveDate::from_ymd_opt(2024, 12, 21).unwrap();
+            let winter_sunrise =
+                timezone.local(&solar::calculate(SolarEvent::Sunrise, &winter, &location));
+            assert!(activation.has_inside(&(winter_sunrise + TimeDelta::seconds(30 * 60 + 30))));
+            assert!(!activation.has_inside(
+                &(winter.and_time(summer_sunrise.time()) + TimeDelta::seconds(30 * 60 + 30))
+            ));
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_overlaps_solar() -> Result<()> {
+            let location = Location::new(51.5074, -0.1278)?;
+            let sunrise = ScheduledActivation::new(
+                ActivationTime::new_solar(SolarEvent::Sunrise, TimeDelta::zero(), location)?,
+                TimeDelta::seconds(60 * 60),
+            )?;
+            let after_sunrise = ScheduledActivation::new(
+                ActivationTime::new_solar(SolarEvent::Sunrise, TimeDelta::minutes(30), location)?,
+                TimeDelta::seconds(10),
+            )?;
+            let sunset = ScheduledActivation::new(
+                ActivationTime::new_solar(SolarEvent::Sunset, TimeDelta::zero(), location)?,
+                TimeDelta::seconds(60 * 60),
+            )?;
+
+            assert!(sunrise.overlaps(&after_sunrise));
+            assert!(after_sunrise.overlaps(&sunrise));
+            assert!(!sunrise.overlaps(&sunset));
+            assert!(!sunset.overlaps(&sunrise));
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_has_inside_restricted_days() -> Result<()> {
+            struct TestCase<'a> {
+                name: &'a str,
+                activation: ScheduledActivation,
+                time: NaiveDateTime,
+                expected_has_inside: bool,
+            }
+
+            let mondays = ActivationDays::new(Weekdays::new(&[Weekday::Mon])?, None, None);
+            let winter = ActivationDays::new(
+                Weekdays::all(),
+                Some(DateRange::new(
+                    DayOfYear::new(11, 1)?,
+                    DayOfYear::new(3, 31)?,
+                )?),
+                None,
+            );
+            let every_three_days = ActivationDays::new(
+                Weekdays::all(),
+                None,
+                Some(EveryNDays::new(3, new_date(2024, 6, 1))?),
+            );
+
+            let test_cases = vec![
+                TestCase {
+                    name: "weekday_included",
+                    activation: ScheduledActivation::new(
+                        new_time(12, 0, 0),
+                        TimeDelta::seconds(10),
+                    )?
+                    .with_days(mondays),
+                    time: new_date(2024, 6, 17).and_time(new_time(12, 0, 5)),
+                    expected_has_inside: true,
+                },
+                TestCase {
+                    name: "weekday_excluded",
+                    activation: ScheduledActivation::new(
+                        new_time(12, 0, 0),
+                        TimeDelta::seconds(10),
+                    )?
+                    .with_days(mondays),
+                    time: new_date(2024, 6, 18).and_time(new_time(12, 0, 5)),
+                    expected_has_inside: false,
+                },
+                TestCase {
+                    name: "weekday_continues_after_midnight",
+                    activation: ScheduledActivation::new(
+                        new_time(23, 0, 0),
+                        TimeDelta::seconds(2 * 60 * 60),
+                    )?
+                    .with_days(mondays),
+                    time: new_date(2024, 6, 18).and_time(new_time(0, 30, 0)),
+                    expected_has_inside: true,
+                },
+                TestCase {
+                    name: "weekday_doesnt_start_on_excluded_day",
+                    activation: ScheduledActivation::new(
+                        new_time(23, 0, 0),
+                        TimeDelta::seconds(2 * 60 * 60),
+                    )?
+                    .with_days(mondays),
+                    time: new_date(2024, 6, 18).and_time(new_time(23, 30, 0)),
+                    expected_has_inside: false,
+                },
+                TestCase {
+                    name: "date_range_wrapping_included",
+                    activation: ScheduledActivation::new(
+                        new_time(12, 0, 0),
+                        TimeDelta::seconds(10),
+                    )?
+                    .with_days(winter),
+                    time: new_date(2024, 1, 15).and_time(new_time(12, 0, 5)),
+                    expected_has_inside: true,
+                },
+                TestCase {
+                    name: "date_range_wrapping_excluded",
+                    activation: ScheduledActivation::new(
+                        new_time(12, 0, 0),
+                        TimeDelta::seconds(10),
+                    )?
+                    .with_days(winter),
+                    time: new_date(2024, 6, 15).and_time(new_time(12, 0, 5)),
+                    expected_has_inside: false,
+                },
+                TestCase {
+                    name: "every_three_days_included",
+                    activation: ScheduledActivation::new(
+                        new_time(12, 0, 0),
+                        TimeDelta::seconds(10),
+                    )?
+                    .with_days(every_three_days),
+                    time: new_date(2024, 6, 7).and_time(new_time(12, 0, 5)),
+                    expected_has_inside: true,
+                },
+                TestCase {
+                    name: "every_three_days_excluded",
+                    activation: ScheduledActivation::new(
+                        new_time(12, 0, 0),
+                        TimeDelta::seconds(10),
+                    )?
+                    .with_days(every_three_days),
+                    time: new_date(2024, 6, 8).and_time(new_time(12, 0, 5)),
+                    expected_has_inside: false,
+                },
+                TestCase {
+                    name: "every_three_days_before_starting",
+                    activation: ScheduledActivation::new(
+                        new_time(12, 0, 0),
+                        TimeDelta::seconds(10),
+                    )?
+                    .with_days(every_three_days),
+                    time: new_date(2024, 5, 29).and_time(new_time(12, 0, 5)),
+                    expected_has_inside: true,
+                },
+            ];
+
+            for test_case in &test_cases {
+                println!("test case: {}", test_case.name);
+                assert_eq!(
+                    test_case.activation.has_inside(&test_case.time),
+                    test_case.expected_has_inside
+                );
+            }
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_overlaps_restricted_days() -> Result<()> {
+            let mondays = ActivationDays::new(Weekdays::new(&[Weekday::Mon])?, None, None);
+            let tuesdays = ActivationDays::new(Weekdays::new(&[Weekday::Tue])?, None, None);
+            let every_other_day = ActivationDays::new(
+                Weekdays::all(),
+                None,
+                Some(EveryNDays::new(2, new_date(2024, 1, 1))?),
+            );
+
+            let monday = ScheduledActivation::new(new_time(12, 0, 0), TimeDelta::seconds(10))?
+                .with_days(mondays);
+            let tuesday = ScheduledActivation::new(new_time(12, 0, 0), TimeDelta::seconds(10))?
+                .with_days(tuesdays);
+            let monday_night =
+                ScheduledActivation::new(new_time(23, 0, 0), TimeDelta::seconds(14 * 60 * 60))?
+                    .with_days(mondays);
+            let every_other_day =
+                ScheduledActivation::new(new_time(12, 0, 0), TimeDelta::seconds(10))?
+                    .with_days(every_other_day);
+
+            assert!(!monday.overlaps(&tuesday));
+            assert!(!tuesday.overlaps(&monday));
+            assert!(monday_night.overlaps(&tuesday));
+            assert!(tuesday.overlaps(&monday_night));
+            assert!(every_other_day.overlaps(&monday));
+            assert!(monday.overlaps(&every_other_day));
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_has_inside_ramp() -> Result<()> {
+            struct TestCase<'a> {
+                name: &'a str,
+                time: NaiveDateTime,
+                expected_has_inside: bool,
+            }
+
+            let activation =
+                ScheduledActivation::new(new_time(8, 0, 0), TimeDelta::seconds(10 * 60 * 60))?
+                    .with_ramp(Ramp::new(
+                        new_date(2025, 10, 1),
+                        new_date(2025, 10, 31),
+                        new_time(7, 0, 0),
+                        TimeDelta::seconds(12 * 60 * 60),
+                    )?)?;
+
+            let test_cases = vec![
+                TestCase {
+                    name: "before_start",
+                    time: new_date(2025, 9, 1).and_time(new_time(7, 40, 0)),
+                    expected_has_inside: false,
+                },
+                TestCase {
+                    name: "before_inside",
+                    time: new_date(2025, 9, 1).and_time(new_time(8, 10, 0)),
+                    expected_has_inside: true,
+                },
+                TestCase {
+                    name: "before_end",
+                    time: new_date(2025, 9, 1).and_time(new_time(18, 10, 0)),
+                    expected_has_inside: false,
+                },
+                TestCase {
+                    name: "halfway_before_start",
+                    time: new_date(2025, 10, 16).and_time(new_time(7, 20, 0)),
+                    expected_has_inside: false,
+                },
+                TestCase {
+                    name: "halfway_after_start",
+                    time: new_date(2025, 10, 16).and_time(new_time(7, 40, 0)),
+                    expected_has_inside: true,
+                },
+                TestCase {
+                    name: "halfway_before_end",
+                    time: new_date(2025, 10, 16).and_time(new_time(18, 20, 0)),
+                    expected_has_inside: true,
+                },
+                TestCase {
+                    name: "halfway_after_end",
+                    time: new_date(2025, 10, 16).and_time(new_time(18, 40, 0)),
+                    expected_has_inside: false,
+                },
+                TestCase {
+                    name: "after_start",
+                    time: new_date(2025, 11, 15).and_time(new_time(7, 10, 0)),
+                    expected_has_inside: true,
+                },
+                TestCase {
+                    name: "after_end",
+                    time: new_date(2025, 11, 15).and_time(new_time(18, 50, 0)),
+                    expected_has_inside: true,
+                },
+            ];
+
+            for test_case in &test_cases {
+                println!("test case: {}", test_case.name);
+                assert_eq!(
+                    activation.has_inside(&test_case.time),
+                    test_case.expected_has_inside
+                );
+            }
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_overlaps_ramp() -> Result<()> {
+            let ramp =
+                ScheduledActivation::new(new_time(8, 0, 0), TimeDelta::seconds(10 * 60 * 60))?
+                    .with_ramp(Ramp::new(
+                        new_date(2030, 10, 1),
+                        new_date(2030, 10, 31),
+                        new_time(7, 0, 0),
+                        TimeDelta::seconds(12 * 60 * 60),
+                    )?)?;
+            let early = ScheduledActivation::new(new_time(7, 0, 0), TimeDelta::seconds(30 * 60))?;
+            let late = ScheduledActivation::new(new_time(6, 0, 0), TimeDelta::seconds(30 * 60))?;
+
+            assert!(ramp.overlaps(&early));
+            assert!(early.overlaps(&ramp));
+            assert!(!ramp.overlaps(&late));
+            assert!(!late.overlaps(&ramp));
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_new_ramp() -> Result<()> {
+            assert!(Ramp::new(
+                new_date(2025, 10, 1),
+                new_date(2025, 10, 31),
+                new_time(7, 0, 0),
+                TimeDelta::seconds(60)
+            )
+            .is_ok());
+            assert!(Ramp::new(
+                new_date(2025, 10, 1),
+                new_date(2025, 10, 1),
+                new_time(7, 0, 0),
+                TimeDelta::seconds(60)
+            )
+            .is_err());
+            assert!(Ramp::new(
+                new_date(2025, 10, 1),
+                new_date(2025, 9, 1),
+                new_time(7, 0, 0),
+                TimeDelta::seconds(60)
+            )
+            .is_err());
+            assert!(Ramp::new(
+                new_date(2025, 10, 1),
+                new_date(2025, 10, 31),
+                new_time(7, 0, 0),
+                TimeDelta::seconds(0)
+            )
+            .is_err());
+            Ok(())
+        }
+
+        #[test]
+        fn test_brightness_at() -> Result<()> {
+            struct TestCase<'a> {
+                name: &'a str,
+                time: NaiveDateTime,
+                expected_brightness: Option<Brightness>,
+            }
+
+            let activation =
+                ScheduledActivation::new(new_time(8, 0, 0), TimeDelta::seconds(10 * 60 * 60))?
+                    .with_brightness(Brightness::new(0.8)?)
+                    .with_fade(TimeDelta::seconds(60 * 60), TimeDelta::seconds(2 * 60 * 60))?;
+
+            let test_cases = vec![
+                TestCase {
+                    name: "before",
+                    time: new_datetime(7, 59, 59),
+                    expected_brightness: None,
+                },
+                TestCase {
+                    name: "start",
+                    time: new_datetime(8, 0, 0),
+                    expected_brightness: Some(Brightness::off()),
+                },
+                TestCase {
+                    name: "fading_in",
+                    time: new_datetime(8, 30, 0),
+                    expected_brightness: Some(Brightness::new(0.4)?),
+                },
+                TestCase {
+                    name: "faded_in",
+                    time: new_datetime(12, 0, 0),
+                    expected_brightness: Some(Brightness::new(0.8)?),
+                },
+                TestCase {
+                    name: "fading_out",
+                    time: new_datetime(17, 0, 0),
+                    expected_brightness: Some(Brightness::new(0.4)?),
+                },
+                TestCase {
+                    name: "end",
+                    time: new_datetime(18, 0, 0),
+                    expected_brightness: Some(Brightness::off()),
+                },
+                TestCase {
+                    name: "after",
+                    time: new_datetime(18, 0, 1),
+                    expected_brightness: None,
+                },
+            ];
+
+            for test_case in &test_cases {
+                println!("test case: {}", test_case.name);
+                let result = activation.brightness_at(&test_case.time);
+                match (result, test_case.expected_brightness) {
+                    (Some(result), Some(expected)) => {
+                        assert!((result.fraction() - expected.fraction()).abs() < 0.001)
+                    }
+                    (result, expected) => assert_eq!(result, expected),
+                }
+            }
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_cycle() -> Result<()> {
+            struct TestCase<'a> {
+                name: &'a str,
+                cycle: Cycle,
+                expected_activations: Vec<ScheduledActivation>,
+            }
+
+            let test_cases = vec![
+                TestCase {
+                    name: "window",
+                    cycle: Cycle::new(TimeDelta::seconds(10 * 60), TimeDelta::seconds(50 * 60))?
+                        .with_window(new_time(8, 0, 0), new_time(11, 0, 0))?,
+                    expected_activations: vec![
+                        ScheduledActivation::new(new_time(8, 0, 0), TimeDelta::seconds(10 * 60))?,
+                        ScheduledActivation::new(new_time(9, 0, 0), TimeDelta::seconds(10 * 60))?,
+                        ScheduledActivation::new(new_time(10, 0, 0), TimeDelta::seconds(10 * 60))?,
+                    ],
+                },
+                TestCase {
+                    name: "window_over_midnight",
+                    cycle: Cycle::new(TimeDelta::seconds(10 * 60), TimeDelta::seconds(50 * 60))?
+                        .with_window(new_time(23, 0, 0), new_time(1, 0, 0))?,
+                    expected_activations: vec![
+                        ScheduledActivation::new(new_time(23, 0, 0), TimeDelta::seconds(10 * 60))?,
+                        ScheduledActivation::new(new_time(0, 0, 0), TimeDelta::seconds(10 * 60))?,
+                    ],
+                },
+                TestCase {
+                    name: "last_activation_is_cut_short",
+                    cycle: Cycle::new(TimeDelta::seconds(30 * 60), TimeDelta::seconds(30 * 60))?
+                        .with_window(new_time(8, 0, 0), new_time(9, 15, 0))?,
+                    expected_activations: vec![
+                        ScheduledActivation::new(new_time(8, 0, 0), TimeDelta::seconds(30 * 60))?,
+                        ScheduledActivation::new(
+                            new_time(9, 0, 0),
+                            TimeDelta::seconds(15 * 60 - 1),
+                        )?,
+                    ],
+                },
+            ];
+
+            for test_case in &test_cases {
+                println!("test case: {}", test_case.name);
+                assert_eq!(test_case.cycle.expand()?, test_case.expected_activations);
+            }
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_cycle_all_day() -> Result<()> {
+            let activations =
+                Cycle::new(TimeDelta::seconds(10 * 60), TimeDelta::seconds(45 * 60))?.expand()?;
+            assert_eq!(activations.len(), 27);
+
+            let activations = ScheduledActivations::new(&activations)?;
+            assert!(activations.has_inside(&new_datetime(0, 5, 0)));
+            assert!(!activations.has_inside(&new_datetime(0, 15, 0)));
+            assert!(activations.has_inside(&new_datetime(23, 50, 0)));
+            assert!(!activations.has_inside(&new_datetime(23, 40, 0)));
+
+            assert!(Cycle::new(TimeDelta::seconds(0), TimeDelta::seconds(10)).is_err());
+            assert!(Cycle::new(TimeDelta::seconds(10), TimeDelta::seconds(0)).is_err());
+            assert!(Cycle::new(TimeDelta::seconds(10), TimeDelta::seconds(10))?
+                .with_window(new_time(8, 0, 0), new_time(8, 0, 0))
+                .is_err());
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_jitter() -> Result<()> {
+            let activation =
+                ScheduledActivation::new(new_time(12, 0, 0), TimeDelta::seconds(10 * 60))?
+                    .with_jitter(TimeDelta::seconds(30 * 60))?
+                    .with_seed(1234);
+
+            let mut starts = HashSet::new();
+            for date in new_date(2024, 1, 1).iter_days().take(100) {
+                let (start, end) = activation.occurrence_on(&date).unwrap();
+                assert_eq!(end - start, TimeDelta::minutes(10));
+                assert!(
+                    (start - date.and_time(new_time(12, 0, 0))).abs() <= TimeDelta::minutes(30)
+                );
+                assert_eq!(activation.occurrence_on(&date), Some((start, end)));
+                starts.insert(start.time());
+            }
+            assert!(starts.len() > 90, "the start times should vary");
+
+            let other_seed = activation.with_seed(4321);
+            let date = new_date(2024, 1, 1);
+            assert_ne!(
+                activation.occurrence_on(&date),
+                other_seed.occurrence_on(&date)
+            );
+
+            assert!(activation
+                .with_jitter(TimeDelta::seconds(12 * 60 * 60 + 1))
+                .is_err());
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_probability() -> Result<()> {
+            let activation =
+                ScheduledActivation::new(new_time(12, 0, 0), TimeDelta::seconds(10 * 60))?
+                    .with_probability(Probability::new(0.3)?)
+                    .with_seed(1234);
+
+            let happened = new_date(2024, 1, 1)
+                .iter_days()
+                .take(1000)
+                .filter(|date| activation.occurrence_on(date).is_some())
+                .count();
+            assert!((250..350).contains(&happened), "happened {happened} times");
+
+            assert!(Probability::new(0.0).is_err());
+            assert!(Probability::new(1.1).is_err());
+            assert!(Probability::new(1.0).is_ok());
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_overlaps_jitter() -> Result<()> {
+            let a = ScheduledActivation::new(new_time(12, 0, 0), TimeDelta::seconds(10 * 60))?;
+            let b = ScheduledActivation::new(new_time(12, 30, 0), TimeDelta::seconds(10 * 60))?;
+            assert!(!a.overlaps(&b));
+
+            let b = b.with_probability(Probability::new(0.5)?);
+            assert!(!a.overlaps(&b));
+
+            let b = b.with_jitter(TimeDelta::seconds(5 * 60))?;
+            assert!(!a.overlaps(&b));
+            assert!(!b.overlaps(&a));
+
+            let b = b.with_jitter(TimeDelta::seconds(20 * 60))?;
+            assert!(a.overlaps(&b));
+            assert!(b.overlaps(&a));
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_with_fade() -> Result<()> {
+            let activation = ScheduledActivation::new(new_time(8, 0, 0), TimeDelta::seconds(60))?;
+            assert!(activation
+                .with_fade(TimeDelta::seconds(30), TimeDelta::seconds(30))
+                .is_ok());
+            assert!(activation
+                .with_fade(TimeDelta::seconds(60), TimeDelta::seconds(0))
+                .is_ok());
+            assert!(activation
+                .with_fade(TimeDelta::seconds(30), TimeDelta::seconds(31))
+                .is_err());
+
+            // the ramp can make the activation shorter than it usually is
+            let ramp = Ramp::new(
+                new_date(2025, 10, 1),
+                new_date(2025, 10, 31),
+                new_time(8, 0, 0),
+                TimeDelta::seconds(40),
+            )?;
+            assert!(activation
+                .with_ramp(ramp)?
+                .with_fade(TimeDelta::seconds(30), TimeDelta::seconds(30))
+                .is_err());
+            assert!(activation
+                .with_fade(TimeDelta::seconds(30), TimeDelta::seconds(30))?
+                .with_ramp(ramp)
+                .is_err());
+            Ok(())
+        }
+
+        #[test]
+        fn test_overlaps() -> Result<()> {
+            struct TestCase<'a> {
+                name: &'a str,
+                a: ScheduledActivation,
+                b: ScheduledActivation,
+                expected_overlaps: bool,
+            }
+
+            let test_cases = vec![
+                TestCase {
+                    name: "identical",
+                    a: ScheduledActivation::new(new_time(14, 0, 0), TimeDelta::seconds(10))?,
+                    b: ScheduledActivation::new(new_time(14, 0, 0), TimeDelta::seconds(10))?,
+                    expected_overlaps: true,
+                },
+                TestCase {
+                    name: "inside",
+                    a: ScheduledActivation::new(new_time(14, 0, 0), TimeDelta::seconds(20))?,
+                    b: ScheduledActivation::new(new_time(14, 0, 5), TimeDelta::seconds(10))?,
+                    expected_overlaps: true,
+                },
+                TestCase {
+                    name: "overlap",
+                    a: ScheduledActivation::new(new_time(14, 0, 0), TimeDelta::seconds(10))?,
+                    b: ScheduledActivation::new(new_time(14, 0, 5), TimeDelta::seconds(10))?,
+                    expected_overlaps: true,
+                },
+                TestCase {
+                    name: "outside",
+                    a: ScheduledActivation::new(new_time(14, 0, 0), TimeDelta::seconds(10))?,
+                    b: ScheduledActivation::new(new_time(18, 0, 0), TimeDelta::seconds(10))?,
+                    expected_overlaps: false,
+                },
+                TestCase {
+                    name: "midnight_inside",
+                    a: ScheduledActivation::new(new_time(23, 59, 50), TimeDelta::seconds(20))?,
+                    b: ScheduledActivation::new(new_time(23, 59, 55), TimeDelta::seconds(10))?,
+                    expected_overlaps: true,
+                },
+                TestCase {
+                    name: "midnight_inside_before",
+                    a: ScheduledActivation::new(new_time(23, 59, 50), TimeDelta::seconds(20))?,
+                    b: ScheduledActivation::new(new_time(23, 59, 55), TimeDelta::seconds(1))?,
+                    expected_overlaps: true,
+                },
+                TestCase {
+                    name: "midnight_inside_after",
+                    a: ScheduledActivation::new(new_time(23, 59, 50), TimeDelta::seconds(20))?,
+                    b: ScheduledActivation::new(new_time(00, 0, 5), TimeDelta::seconds(1))?,
+                    expected_overlaps: true,
+                },
+                TestCase {
+                    name: "midnight_outside",
+                    a: ScheduledActivation::new(new_time(23, 59, 50), TimeDelta::seconds(20))?,
+                    b: ScheduledActivation::new(new_time(18, 0, 0), TimeDelta::seconds(10))?,
+                    expected_overlaps: false,
+                },
+            ];
+
+            for test_case in &test_cases {
+                println!("test case: {}", test_case.name);
+
+                assert_eq!(
+                    test_case.a.overlaps(&test_case.b),
+                    test_case.expected_overlaps
+                );
+
+                assert_eq!(
+                    test_case.b.overlaps(&test_case.a),
+                    test_case.expected_overlaps
+                );
+            }
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_repeat() -> Result<()> {
+            struct TestCase<'a> {
+                name: &'a str,
+                activation: ScheduledActivation,
+                start_every: u32,
+                times: u32,
+                result: Result<Vec<ScheduledActivation>>,
+            }
+
+            let test_cases = vec![
+                TestCase {
+                name: "zero_times",
+                activation: ScheduledActivation::new(new_time(14, 0, 0), TimeDelta::seconds(10))?,
+                start_every: 15,
+                times: 0,
+                result: Err(anyhow!("repeating less than two times makes no sense, just use the existing activation")),
+            },
+                TestCase {
+                name: "one_time",
+                activation: ScheduledActivation::new(new_time(14, 0, 0), TimeDelta::seconds(10))?,
+                start_every: 15,
+                times: 1,
+                result: Err(anyhow!("repeating less than two times makes no sense, just use the existing activation")),
+            },
+                TestCase {
+                name: "just_as_often",
+                activation: ScheduledActivation::new(new_time(14, 0, 0), TimeDelta::seconds(10))?,
+                start_every: 10,
+                times: 2,
+                result: Err(anyhow!("repeating more often than or just as often as the activation lasts makes no sense, the results will overlap")),
+            },
+                TestCase {
+                name: "more_often",
+                activation: ScheduledActivation::new(new_time(14, 0, 0), TimeDelta::seconds(10))?,
+                start_every: 9,
+                times: 2,
+                result: Err(anyhow!("repeating more often than or just as often as the activation lasts makes no sense, the results will overlap")),
+            },
+                TestCase {
+                name: "simple",
+                activation: ScheduledActivation::new(new_time(14, 0, 0), TimeDelta::seconds(10))?,
+                start_every: 15,
+                times: 2,
+                result: Ok(vec![
+                    ScheduledActivation::new(new_time(14, 0, 0), TimeDelta::seconds(10))?,
+                    ScheduledActivation::new(new_time(14, 0, 15), TimeDelta::seconds(10))?,
+                ]),
+            },
+            TestCase {
+                name: "start_every_longer_than_a_day",
+                activation: ScheduledActivation::new(new_time(14, 0, 0), TimeDelta::seconds(10))?,
+                start_every: 25 * 60 * 60,
+                times: 2,
+                result: Err(anyhow!("there is no way we meant to jump by more than a whole day or a whole day when repeating")),
+            },
+            TestCase {
+                name: "start_every_equal_to_one_day",
+                activation: ScheduledActivation::new(new_time(14, 0, 0), TimeDelta::seconds(10))?,
+                start_every: 24 * 60 * 60,
+                times: 2,
+                result: Err(anyhow!("there is no way we meant to jump by more than a whole day or a whole day when repeating")),
+            },
+            TestCase {
+                name: "start_jumps_over_midnight",
+                activation: ScheduledActivation::new(new_time(23, 0, 0), TimeDelta::seconds(60))?,
+                start_every: 60 * 60,
+                times: 2,
+                result: Err(anyhow!("I find it highly suspicious that we jumped over midnight when repeating, I suspect we didn't want this to happen")),
+            },
+            TestCase {
+                name: "ramp_jumps_over_midnight",
+                activation: ScheduledActivation::new(new_time(14, 0, 0), TimeDelta::seconds(60))?
+                    .with_ramp(Ramp::new(
+                        new_date(2025, 10, 1),
+                        new_date(2025, 10, 31),
+                        new_time(23, 0, 0),
+                        TimeDelta::seconds(60),
+                    )?)?,
+                start_every: 2 * 60 * 60,
+                times: 2,
+                result: Err(anyhow!("I find it highly suspicious that we jumped over midnight when repeating, I suspect we didn't want this to happen")),
+            },
+            ];
+
+            for test_case in &test_cases {
+                println!("test case: {}", test_case.name);
+
+                let result = test_case.activation.repeat(
+                    TimeDelta::seconds(test_case.start_every.into()),
+                    test_case.times,
+                );
+                match &test_case.result {
+                    Ok(a) => match result {
+                        Ok(b) => assert_eq!(a, &b),
+                        Err(err_b) => panic!("got an error when a value was expected: {:?}", err_b),
+                    },
+                    Err(err_a) => match result {
+                        Ok(b) => panic!("got a value when an error was expected: {:?}", b),
+                        Err(err_b) => assert_eq!(err_a.to_string(), err_b.to_string()),
+                    },
+                }
+            }
+
+            Ok(())
+        }
+    }
+
+    mod scheduled_activations {
+        use super::*;
+        use anyhow::Error;
+        use core::panic;
+
+        #[test]
+        fn test_construct() -> Result<()> {
+            struct TestCase<'a> {
+                name: &'a str,
+                activations: Vec<ScheduledActivation>,
+                expected_error: Option<Error>,
+            }
+
+            let test_cases = vec![
+                TestCase {
+                    name: "empty",
+                    activations: vec![],
+                    expected_error: None,
+                },
+                TestCase {
+                    name: "overlap",
+                    activations: vec![
+                        ScheduledActivation::new(new_time(12, 0, 0), TimeDelta::seconds(10))?,
+                        ScheduledActivation::new(new_time(12, 0, 5), TimeDelta::seconds(10))?,
+                    ],
+                    expected_error: Some(anyhow!("activations can't overlap")),
+                },
+                TestCase {
+                    name: "ok",
+                    activations: vec![
+                        ScheduledActivation::new(new_time(12, 0, 0), TimeDelta::seconds(10))?,
+                        ScheduledActivation::new(new_time(18, 0, 0), TimeDelta::seconds(10))?,
+                    ],
+                    expected_error: None,
+                },
+            ];
+
+            for test_case in &test_cases {
+                println!("test case: {}", test_case.name);
+
+                let result = ScheduledActivations::new(&test_case.activations);
+                match &test_case.expected_error {
+                    Some(expected_err) => match result {
+                        Ok(_) => {
+                            panic!("no error encountered even though an error was expected")
+                        }
+                        Err(err) => {
+                            assert_eq!(err.to_string(), expected_err.to_string());
+                        }
+                    },
+                    None => {
+                        match result {
+                            Ok(_) => {
+                                // ok
+                            }
+                            Err(_) => {
+                                panic!("error encountered even though no error was expected")
+                            }
+                        }
+                    }
+                }
+            }
+
+            Ok(())
+        }
+    }
+
+    mod controlled_output {
+        use super::*;
+        use crate::adapters::MockOutputPin;
+
+        #[test]
+        fn test_target_state() -> Result<()> {
             struct TestCase<'a> {
                 name: &'a str,
-                activation: ScheduledActivation,
-                time: NaiveTime,
-                expected_has_inside: bool,
+                activations: Vec<ScheduledActivation>,
+                overrides: Vec<Override>,
+                expected_state: OutputState,
+            }
+
+            let time = new_datetime(12, 00, 00);
+            let test_cases = vec![
+                TestCase {
+                    name: "empty",
+                    activations: vec![],
+                    overrides: vec![],
+                    expected_state: OutputState::Off,
+                },
+                TestCase {
+                    name: "no_override",
+                    activations: vec![ScheduledActivation::new(
+                        new_time(11, 59, 55),
+                        TimeDelta::seconds(10),
+                    )?],
+                    overrides: vec![],
+                    expected_state: OutputState::On,
+                },
+                TestCase {
+                    name: "override_off",
+                    activations: vec![ScheduledActivation::new(
+                        new_time(11, 59, 55),
+                        TimeDelta::seconds(10),
+                    )?],
+                    overrides: vec![Override::new(
+                        OverrideId::new(1),
+                        OutputState::Off,
+                        new_datetime(11, 59, 55).and_utc(),
+                        new_datetime(12, 0, 5).and_utc(),
+                    )],
+                    expected_state: OutputState::Off,
+                },
+                TestCase {
+                    name: "override_on",
+                    activations: vec![ScheduledActivation::new(
+                        new_time(18, 00, 00),
+                        TimeDelta::seconds(10),
+                    )?],
+                    overrides: vec![Override::new(
+                        OverrideId::new(1),
+                        OutputState::On,
+                        new_datetime(11, 59, 55).and_utc(),
+                        new_datetime(12, 0, 5).and_utc(),
+                    )],
+                    expected_state: OutputState::On,
+                },
+                TestCase {
+                    name: "dimmed",
+                    activations: vec![ScheduledActivation::new(
+                        new_time(11, 59, 55),
+                        TimeDelta::seconds(10),
+                    )?
+                    .with_brightness(Brightness::new(0.5)?)],
+                    overrides: vec![],
+                    expected_state: OutputState::Dimmed(Brightness::new(0.5)?),
+                },
+                TestCase {
+                    name: "override_dimmed",
+                    activations: vec![ScheduledActivation::new(
+                        new_time(11, 59, 55),
+                        TimeDelta::seconds(10),
+                    )?],
+                    overrides: vec![Override::new(
+                        OverrideId::new(1),
+                        OutputState::Dimmed(Brightness::new(0.3)?),
+                        new_datetime(11, 59, 55).and_utc(),
+                        new_datetime(12, 0, 5).and_utc(),
+                    )],
+                    expected_state: OutputState::Dimmed(Brightness::new(0.3)?),
+                },
+            ];
+
+            for test_case in &test_cases {
+                println!("test case: {}", test_case.name);
+
+                let pin_number = PinNumber::new(1)?;
+                let activations = ScheduledActivations::new(&test_case.activations)?;
+                let definition =
+                    OutputDefinition::new(OutputName::new("output")?, pin_number, activations);
+                let mut output = ControlledOutput {
+                    definition,
+                    pin: MockOutputPin::new(pin_number, DutyCycle::off()),
+                    overrides: test_case.overrides.clone(),
+                    in_effect: None,
+                    blocked_by: None,
+                    runtime: Runtime::default(),
+                    deferred: None,
+                    regulating: false,
+                    running_dry: false,
+                    top_off: TopOffState::default(),
+                    pid: None,
+                };
+
+                let result = output.target_state(&time, &time.and_utc(), &Readings::new(), None);
+                assert_eq!(result, test_case.expected_state);
+            }
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_cleanup_overrides() -> Result<()> {
+            struct TestCase<'a> {
+                name: &'a str,
+                overrides: Vec<Override>,
+                expected_overrides: Vec<Override>,
+            }
+
+            let time = new_datetime(12, 00, 00).and_utc();
+            let new_override = |id: u64, start: NaiveDateTime| {
+                Override::new(
+                    OverrideId::new(id),
+                    OutputState::On,
+                    start.and_utc(),
+                    (start + TimeDelta::seconds(10)).and_utc(),
+                )
+            };
+            let test_cases = vec![
+                TestCase {
+                    name: "future_override",
+                    overrides: vec![new_override(1, new_datetime(18, 0, 0))],
+                    expected_overrides: vec![new_override(1, new_datetime(18, 0, 0))],
+                },
+                TestCase {
+                    name: "past_override",
+                    overrides: vec![
+                        new_override(1, new_datetime(18, 0, 0)),
+                        new_override(2, new_datetime(6, 0, 0)),
+                    ],
+                    expected_overrides: vec![new_override(1, new_datetime(18, 0, 0))],
+                },
+                TestCase {
+                    name: "current_override",
+                    overrides: vec![
+                        new_override(1, new_datetime(18, 0, 0)),
+                        new_override(2, new_datetime(11, 59, 55)),
+                    ],
+                    expected_overrides: vec![
+                        new_override(1, new_datetime(18, 0, 0)),
+                        new_override(2, new_datetime(11, 59, 55)),
+                    ],
+                },
+                TestCase {
+                    name: "override_ending_now",
+                    overrides: vec![new_override(1, new_datetime(11, 59, 50))],
+                    expected_overrides: vec![new_override(1, new_datetime(11, 59, 50))],
+                },
+            ];
+
+            for test_case in &test_cases {
+                println!("test case: {}", test_case.name);
+
+                let pin_number = PinNumber::new(1)?;
+                let activations = ScheduledActivations::new(&[])?;
+                let definition =
+                    OutputDefinition::new(OutputName::new("output")?, pin_number, activations);
+                let mut output = ControlledOutput {
+                    definition,
+                    pin: MockOutputPin::new(pin_number, DutyCycle::off()),
+                    overrides: test_case.overrides.clone(),
+                    in_effect: None,
+                    blocked_by: None,
+                    runtime: Runtime::default(),
+                    deferred: None,
+                    regulating: false,
+                    running_dry: false,
+                    top_off: TopOffState::default(),
+                    pid: None,
+                };
+
+                output.cleanup_overrides(&time);
+                assert_eq!(output.overrides, test_case.expected_overrides);
+            }
+
+            Ok(())
+        }
+    }
+
+    mod controller {
+        use super::*;
+        use crate::adapters::{MockGPIO, MockOutputPin};
+        use crate::domain::pid::{Gains, PidSettings};
+        use crate::domain::regulation::{Direction, Setpoint};
+        use crate::domain::PwmKind;
+        use chrono::TimeZone;
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        #[test]
+        fn test_interlocks_order() -> Result<()> {
+            let interlocks = [
+                Interlock::new(
+                    OutputName::new("a")?,
+                    InterlockKind::Excludes,
+                    OutputName::new("b")?,
+                )?,
+                Interlock::new(
+                    OutputName::new("b")?,
+                    InterlockKind::Excludes,
+                    OutputName::new("c")?,
+                )?,
+                Interlock::new(
+                    OutputName::new("d")?,
+                    InterlockKind::Excludes,
+                    OutputName::new("e")?,
+                )?,
+                Interlock::new(
+                    OutputName::new("e")?,
+                    InterlockKind::Excludes,
+                    OutputName::new("d")?,
+                )?,
+            ];
+            let activation = ScheduledActivation::new(new_time(12, 0, 0), TimeDelta::hours(1))?;
+            let now = Local.from_local_datetime(&new_datetime(12, 5, 0)).unwrap();
+
+            for names in [["a", "b", "c", "d", "e"], ["e", "d", "c", "b", "a"]] {
+                println!("order: {names:?}");
+
+                let definitions = names
+                    .iter()
+                    .enumerate()
+                    .map(|(i, name)| new_output(name, i as u8 + 1, &[activation]))
+                    .collect::<Result<Vec<_>>>()?;
+                let outputs = OutputDefinitions::new(&definitions)?.with_interlocks(&interlocks)?;
+                let mut controller =
+                    Controller::new(&outputs, MockGPIO::new(), fixed_clock(now.to_utc()))?;
+                controller.update_outputs_for_time(now.to_utc());
+
+                // c turns b off so a can be on, d and e exclude each other so neither can be on
+                let mut result = states(&controller);
+                result.sort_by_key(|v| v.0);
+                assert_eq!(
+                    result,
+                    vec![
+                        ("a", OutputState::On, false),
+                        ("b", OutputState::Off, true),
+                        ("c", OutputState::On, false),
+                        ("d", OutputState::Off, true),
+                        ("e", OutputState::Off, true),
+                    ]
+                );
+            }
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_interlocks() -> Result<()> {
+            let outputs = OutputDefinitions::new(&[
+                new_output(
+                    "misting",
+                    1,
+                    &[ScheduledActivation::new(
+                        new_time(12, 0, 0),
+                        TimeDelta::seconds(600),
+                    )?],
+                )?,
+                new_output(
+                    "fan",
+                    2,
+                    &[ScheduledActivation::new(
+                        new_time(11, 0, 0),
+                        TimeDelta::seconds(7200),
+                    )?],
+                )?,
+                new_output(
+                    "heater",
+                    3,
+                    &[ScheduledActivation::new(
+                        new_time(12, 0, 0),
+                        TimeDelta::seconds(600),
+                    )?],
+                )?,
+                new_output("pump", 4, &[])?,
+            ])?
+            .with_interlocks(&[
+                Interlock::new(
+                    OutputName::new("misting")?,
+                    InterlockKind::Excludes,
+                    OutputName::new("fan")?,
+                )?,
+                Interlock::new(
+                    OutputName::new("heater")?,
+                    InterlockKind::Requires,
+                    OutputName::new("pump")?,
+                )?,
+            ])?;
+
+            let now = Local.from_local_datetime(&new_datetime(12, 5, 0)).unwrap();
+            let mut controller =
+                Controller::new(&outputs, MockGPIO::new(), fixed_clock(now.to_utc()))?;
+
+            controller.update_outputs_for_time(now.to_utc());
+            assert_eq!(
+                states(&controller),
+                vec![
+                    ("misting", OutputState::Off, true),
+                    ("fan", OutputState::On, false),
+                    ("heater", OutputState::Off, true),
+                    ("pump", OutputState::Off, false),
+                ]
+            );
+
+            let err = controller
+                .add_override(
+                    OutputName::new("misting")?,
+                    NewOverride::new(OutputState::On, now.to_utc(), TimeDelta::seconds(60))?,
+                )
+                .unwrap_err();
+            assert!(err.downcast_ref::<BlockedByInterlock>().is_some());
+
+            controller.add_override(
+                OutputName::new("pump")?,
+                NewOverride::new(OutputState::On, now.to_utc(), TimeDelta::seconds(60))?,
+            )?;
+            controller.update_outputs_for_time(now.to_utc());
+            assert_eq!(
+                states(&controller),
+                vec![
+                    ("misting", OutputState::Off, true),
+                    ("fan", OutputState::On, false),
+                    ("heater", OutputState::On, false),
+                    ("pump", OutputState::On, false),
+                ]
+            );
+
+            controller.add_override(
+                OutputName::new("fan")?,
+                NewOverride::new(OutputState::Off, now.to_utc(), TimeDelta::seconds(60))?,
+            )?;
+            controller.update_outputs_for_time(now.to_utc());
+            assert_eq!(states(&controller)[0], ("misting", OutputState::On, false));
+
+            // the fan is going to be on by then
+            let err = controller
+                .add_override(
+                    OutputName::new("misting")?,
+                    NewOverride::new(
+                        OutputState::On,
+                        local(&new_datetime(12, 10, 0)),
+                        TimeDelta::seconds(60),
+                    )?,
+                )
+                .unwrap_err();
+            assert!(err.downcast_ref::<BlockedByInterlock>().is_some());
+
+            controller.add_override(
+                OutputName::new("misting")?,
+                NewOverride::new(
+                    OutputState::On,
+                    local(&new_datetime(13, 10, 0)),
+                    TimeDelta::seconds(60),
+                )?,
+            )?;
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_limits() -> Result<()> {
+            struct Step<'a> {
+                name: &'a str,
+                time: NaiveDateTime,
+                expected_state: OutputState,
+                expected_limit_exceeded: Option<Limit>,
+            }
+
+            let outputs = OutputDefinitions::new(&[new_output(
+                "misting",
+                1,
+                &[
+                    ScheduledActivation::new(new_time(12, 0, 0), TimeDelta::seconds(60 * 60))?,
+                    ScheduledActivation::new(new_time(14, 0, 0), TimeDelta::seconds(10 * 60))?,
+                ],
+            )?
+            .with_max_on_time(10 * 60)?
+            .with_daily_budget(15 * 60)?])?;
+
+            let steps = vec![
+                Step {
+                    name: "turned_on",
+                    time: new_datetime(12, 0, 0),
+                    expected_state: OutputState::On,
+                    expected_limit_exceeded: None,
+                },
+                Step {
+                    name: "still_on",
+                    time: new_datetime(12, 5, 0),
+                    expected_state: OutputState::On,
+                    expected_limit_exceeded: None,
+                },
+                Step {
+                    name: "on_for_too_long",
+                    time: new_datetime(12, 10, 0),
+                    expected_state: OutputState::Off,
+                    expected_limit_exceeded: Some(Limit::MaxOnTime),
+                },
+                Step {
+                    name: "kept_off_until_activation_ends",
+                    time: new_datetime(12, 30, 0),
+                    expected_state: OutputState::Off,
+                    expected_limit_exceeded: Some(Limit::MaxOnTime),
+                },
+                Step {
+                    name: "activation_ended",
+                    time: new_datetime(13, 30, 0),
+                    expected_state: OutputState::Off,
+                    expected_limit_exceeded: None,
+                },
+                Step {
+                    name: "turned_on_again",
+                    time: new_datetime(14, 0, 0),
+                    expected_state: OutputState::On,
+                    expected_limit_exceeded: None,
+                },
+                Step {
+                    name: "still_on_again",
+                    time: new_datetime(14, 4, 0),
+                    expected_state: OutputState::On,
+                    expected_limit_exceeded: None,
+                },
+                Step {
+                    name: "out_of_budget",
+                    time: new_datetime(14, 5, 0),
+                    expected_state: OutputState::Off,
+                    expected_limit_exceeded: Some(Limit::DailyBudget),
+                },
+                Step {
+                    name: "new_budget_next_day",
+                    time: new_datetime(14, 0, 0) + TimeDelta::days(1),
+                    expected_state: OutputState::On,
+                    expected_limit_exceeded: None,
+                },
+            ];
+
+            let now = Local.from_local_datetime(&steps[0].time).unwrap();
+            let mut controller =
+                Controller::new(&outputs, MockGPIO::new(), fixed_clock(now.to_utc()))?;
+
+            for step in &steps {
+                println!("step: {}", step.name);
+
+                controller.update_outputs_for_time(
+                    Local.from_local_datetime(&step.time).unwrap().to_utc(),
+                );
+                let status = controller.status().remove(0);
+                assert_eq!(status.state, step.expected_state);
+                assert_eq!(status.limit_exceeded, step.expected_limit_exceeded);
+            }
+
+            let err = controller.add_override(
+                OutputName::new("misting")?,
+                NewOverride::new(
+                    OutputState::On,
+                    local(&new_datetime(16, 0, 0)),
+                    TimeDelta::seconds(10 * 60 * 60),
+                )?,
+            );
+            assert!(err.is_err());
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_min_on_and_off_time() -> Result<()> {
+            struct Step<'a> {
+                name: &'a str,
+                time: NaiveDateTime,
+                expected_state: OutputState,
+                expected_deferred: Option<DeferredTransition>,
+            }
+
+            let outputs = OutputDefinitions::new(&[new_output(
+                "pump",
+                1,
+                &[
+                    ScheduledActivation::new(new_time(12, 0, 0), TimeDelta::seconds(10))?,
+                    ScheduledActivation::new(new_time(12, 1, 30), TimeDelta::seconds(10))?,
+                ],
+            )?
+            .with_min_on_time(60)?
+            .with_min_off_time(120)?])?;
+
+            let steps = vec![
+                Step {
+                    name: "off",
+                    time: new_datetime(11, 0, 0),
+                    expected_state: OutputState::Off,
+                    expected_deferred: None,
+                },
+                Step {
+                    name: "on",
+                    time: new_datetime(12, 0, 0),
+                    expected_state: OutputState::On,
+                    expected_deferred: None,
+                },
+                Step {
+                    name: "kept_on",
+                    time: new_datetime(12, 0, 20),
+                    expected_state: OutputState::On,
+                    expected_deferred: Some(DeferredTransition {
+                        state: OutputState::Off,
+                        until: Local
+                            .from_local_datetime(&new_datetime(12, 1, 0))
+                            .unwrap()
+                            .to_utc(),
+                    }),
+                },
+                Step {
+                    name: "off_again",
+                    time: new_datetime(12, 1, 0),
+                    expected_state: OutputState::Off,
+                    expected_deferred: None,
+                },
+                Step {
+                    name: "kept_off",
+                    time: new_datetime(12, 1, 30),
+                    expected_state: OutputState::Off,
+                    expected_deferred: Some(DeferredTransition {
+                        state: OutputState::On,
+                        until: Local
+                            .from_local_datetime(&new_datetime(12, 3, 0))
+                            .unwrap()
+                            .to_utc(),
+                    }),
+                },
+                Step {
+                    name: "activation_ended_while_deferred",
+                    time: new_datetime(12, 2, 0),
+                    expected_state: OutputState::Off,
+                    expected_deferred: None,
+                },
+            ];
+
+            let now = Local.from_local_datetime(&steps[0].time).unwrap();
+            let mut controller =
+                Controller::new(&outputs, MockGPIO::new(), fixed_clock(now.to_utc()))?;
+
+            for step in &steps {
+                println!("step: {}", step.name);
+
+                controller.update_outputs_for_time(
+                    Local.from_local_datetime(&step.time).unwrap().to_utc(),
+                );
+                let status = controller.status().remove(0);
+                assert_eq!(status.state, step.expected_state);
+                assert_eq!(status.deferred, step.expected_deferred);
+            }
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_regulation() -> Result<()> {
+            struct Step<'a> {
+                name: &'a str,
+                reading: Option<f32>,
+                time: NaiveDateTime,
+                expected_state: OutputState,
             }
 
-            let test_cases = vec![
-                TestCase {
-                    name: "midnight_start",
-                    activation: ScheduledActivation::new(new_time(23, 59, 55), 10)?,
-                    time: new_time(23, 59, 55),
-                    expected_has_inside: true,
-                },
-                TestCase {
-                    name: "midnight_middle_before",
-                    activation: ScheduledActivation::new(new_time(23, 59, 55), 10)?,
-                    time: new_time(23, 59, 59),
-                    expected_has_inside: true,
-                },
-                TestCase {
-                    name: "midnight_middle_after",
-                    activation: ScheduledActivation::new(new_time(23, 59, 55), 10)?,
-                    time: new_time(00, 00, 00),
-                    expected_has_inside: true,
-                },
-                TestCase {
-                    name: "midnight_end",
-                    activation: ScheduledActivation::new(new_time(23, 59, 55), 10)?,
-                    time: new_time(00, 00, 5),
-                    expected_has_inside: true,
-                },
-                TestCase {
-                    name: "midnight_outside",
-                    activation: ScheduledActivation::new(new_time(23, 59, 55), 10)?,
-                    time: new_time(12, 00, 00),
-                    expected_has_inside: false,
+            let sensor = SensorName::new("sensor")?;
+            let outputs = OutputDefinitions::new(&[new_output("mister", 1, &[])?
+                .with_regulation(Regulation::new(
+                    sensor.clone(),
+                    Quantity::Humidity,
+                    Direction::Raise,
+                    Setpoint::new(75.0, 10.0)?,
+                ))])?;
+
+            let steps = vec![
+                Step {
+                    name: "no_readings",
+                    reading: None,
+                    time: new_datetime(12, 0, 0),
+                    expected_state: OutputState::Off,
                 },
-                TestCase {
-                    name: "normal_start",
-                    activation: ScheduledActivation::new(new_time(12, 0, 0), 10)?,
-                    time: new_time(12, 0, 0),
-                    expected_has_inside: true,
+                Step {
+                    name: "too_dry",
+                    reading: Some(70.0),
+                    time: new_datetime(12, 0, 0),
+                    expected_state: OutputState::On,
                 },
-                TestCase {
-                    name: "normal_middle",
-                    activation: ScheduledActivation::new(new_time(12, 0, 0), 10)?,
-                    time: new_time(12, 0, 5),
-                    expected_has_inside: true,
+                Step {
+                    name: "within_hysteresis",
+                    reading: Some(80.0),
+                    time: new_datetime(12, 0, 0),
+                    expected_state: OutputState::On,
                 },
-                TestCase {
-                    name: "normal_end",
-                    activation: ScheduledActivation::new(new_time(12, 0, 0), 10)?,
-                    time: new_time(12, 0, 10),
-                    expected_has_inside: true,
+                Step {
+                    name: "humid_enough",
+                    reading: Some(86.0),
+                    time: new_datetime(12, 0, 0),
+                    expected_state: OutputState::Off,
                 },
-                TestCase {
-                    name: "normal_outside",
-                    activation: ScheduledActivation::new(new_time(12, 0, 0), 10)?,
-                    time: new_time(18, 0, 0),
-                    expected_has_inside: false,
+                Step {
+                    name: "too_dry_again",
+                    reading: Some(70.0),
+                    time: new_datetime(12, 0, 0),
+                    expected_state: OutputState::On,
                 },
-                TestCase {
-                    name: "always_turned_on",
-                    activation: ScheduledActivation::new(new_time(0, 0, 0), 24 * 3600)?,
-                    time: new_time(1, 0, 0),
-                    expected_has_inside: true,
+                Step {
+                    name: "readings_are_too_old",
+                    reading: None,
+                    time: new_datetime(12, 30, 0),
+                    expected_state: OutputState::Off,
                 },
             ];
 
-            for test_case in &test_cases {
-                println!("test case: {}", test_case.name);
-                assert_eq!(
-                    test_case.activation.has_inside(&test_case.time),
-                    test_case.expected_has_inside
+            let now = Local.from_local_datetime(&steps[0].time).unwrap();
+            let mut controller =
+                Controller::new(&outputs, MockGPIO::new(), fixed_clock(now.to_utc()))?;
+
+            for step in &steps {
+                println!("step: {}", step.name);
+
+                if let Some(reading) = step.reading {
+                    controller.report_reading(&sensor, Quantity::Humidity, reading);
+                }
+                controller.update_outputs_for_time(
+                    Local.from_local_datetime(&step.time).unwrap().to_utc(),
                 );
+                assert_eq!(controller.status().remove(0).state, step.expected_state);
             }
 
             Ok(())
         }
 
         #[test]
-        fn test_overlaps() -> Result<()> {
-            struct TestCase<'a> {
+        fn test_dry_run_protection() -> Result<()> {
+            struct Step<'a> {
                 name: &'a str,
-                a: ScheduledActivation,
-                b: ScheduledActivation,
-                expected_overlaps: bool,
+                level: Option<f32>,
+                time: NaiveDateTime,
+                expected_state: OutputState,
             }
 
-            let test_cases = vec![
-                TestCase {
-                    name: "identical",
-                    a: ScheduledActivation::new(new_time(14, 0, 0), 10)?,
-                    b: ScheduledActivation::new(new_time(14, 0, 0), 10)?,
-                    expected_overlaps: true,
-                },
-                TestCase {
-                    name: "inside",
-                    a: ScheduledActivation::new(new_time(14, 0, 0), 20)?,
-                    b: ScheduledActivation::new(new_time(14, 0, 5), 10)?,
-                    expected_overlaps: true,
-                },
-                TestCase {
-                    name: "overlap",
-                    a: ScheduledActivation::new(new_time(14, 0, 0), 10)?,
-                    b: ScheduledActivation::new(new_time(14, 0, 5), 10)?,
-                    expected_overlaps: true,
+            let sensor = SensorName::new("sensor")?;
+            let outputs = OutputDefinitions::new(&[new_output(
+                "pump",
+                1,
+                &[ScheduledActivation::new(
+                    new_time(12, 0, 0),
+                    TimeDelta::seconds(3600),
+                )?],
+            )?
+            .with_min_on_time(600)?
+            .with_dry_run_protection(DryRunProtection::new(sensor.clone(), 20.0, 5.0)?)])?;
+
+            let steps = vec![
+                Step {
+                    name: "no_readings",
+                    level: None,
+                    time: new_datetime(12, 0, 0),
+                    expected_state: OutputState::Off,
                 },
-                TestCase {
-                    name: "outside",
-                    a: ScheduledActivation::new(new_time(14, 0, 0), 10)?,
-                    b: ScheduledActivation::new(new_time(18, 0, 0), 10)?,
-                    expected_overlaps: false,
+                Step {
+                    name: "full",
+                    level: Some(80.0),
+                    time: new_datetime(12, 0, 10),
+                    expected_state: OutputState::On,
                 },
-                TestCase {
-                    name: "midnight_inside",
-                    a: ScheduledActivation::new(new_time(23, 59, 50), 20)?,
-                    b: ScheduledActivation::new(new_time(23, 59, 55), 10)?,
-                    expected_overlaps: true,
+                Step {
+                    name: "empty_despite_min_on_time",
+                    level: Some(10.0),
+                    time: new_datetime(12, 0, 20),
+                    expected_state: OutputState::Off,
                 },
-                TestCase {
-                    name: "midnight_inside_before",
-                    a: ScheduledActivation::new(new_time(23, 59, 50), 20)?,
-                    b: ScheduledActivation::new(new_time(23, 59, 55), 1)?,
-                    expected_overlaps: true,
+                Step {
+                    name: "within_hysteresis",
+                    level: Some(22.0),
+                    time: new_datetime(12, 0, 30),
+                    expected_state: OutputState::Off,
                 },
-                TestCase {
-                    name: "midnight_inside_after",
-                    a: ScheduledActivation::new(new_time(23, 59, 50), 20)?,
-                    b: ScheduledActivation::new(new_time(00, 0, 5), 1)?,
-                    expected_overlaps: true,
+                Step {
+                    name: "refilled",
+                    level: Some(30.0),
+                    time: new_datetime(12, 0, 40),
+                    expected_state: OutputState::On,
                 },
-                TestCase {
-                    name: "midnight_outside",
-                    a: ScheduledActivation::new(new_time(23, 59, 50), 20)?,
-                    b: ScheduledActivation::new(new_time(18, 0, 0), 10)?,
-                    expected_overlaps: false,
+                Step {
+                    name: "readings_are_too_old",
+                    level: None,
+                    time: new_datetime(12, 30, 0),
+                    expected_state: OutputState::Off,
                 },
             ];
 
-            for test_case in &test_cases {
-                println!("test case: {}", test_case.name);
+            let now = Local.from_local_datetime(&steps[0].time).unwrap();
+            let mut controller =
+                Controller::new(&outputs, MockGPIO::new(), fixed_clock(now.to_utc()))?;
+
+            for step in &steps {
+                println!("step: {}", step.name);
+
+                if let Some(level) = step.level {
+                    controller.report_reading(&sensor, Quantity::WaterLevel, level);
+                }
+                controller.update_outputs_for_time(
+                    Local.from_local_datetime(&step.time).unwrap().to_utc(),
+                );
+
+                let status = controller.status().remove(0);
+                assert_eq!(status.state, step.expected_state);
+                assert_eq!(status.running_dry, step.expected_state == OutputState::Off);
+            }
+
+            let err = controller
+                .add_override(
+                    OutputName::new("pump")?,
+                    NewOverride::new(OutputState::On, now.to_utc(), TimeDelta::seconds(60))?,
+                )
+                .unwrap_err();
+            assert!(err.downcast_ref::<RunningDry>().is_some());
+
+            controller.add_override(
+                OutputName::new("pump")?,
+                NewOverride::new(OutputState::Off, now.to_utc(), TimeDelta::seconds(60))?,
+            )?;
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_top_off() -> Result<()> {
+            let sensor = SensorName::new("sensor")?;
+            let outputs = OutputDefinitions::new(&[new_output("refill pump", 1, &[])?
+                .with_top_off(TopOff::new(sensor.clone(), 40.0, 60.0, 120)?)])?;
+
+            let now = Local.from_local_datetime(&new_datetime(12, 0, 0)).unwrap();
+            let mut controller =
+                Controller::new(&outputs, MockGPIO::new(), fixed_clock(now.to_utc()))?;
+
+            controller.report_reading(&sensor, Quantity::WaterLevel, 35.0);
+            controller.update_outputs_for_time(now.to_utc());
+            assert_eq!(controller.status().remove(0).state, OutputState::On);
+
+            controller.report_reading(&sensor, Quantity::WaterLevel, 45.0);
+            controller.update_outputs_for_time((now + TimeDelta::minutes(3)).to_utc());
+            let status = controller.status().remove(0);
+            assert_eq!(status.state, OutputState::Off);
+            assert_eq!(status.top_off_failed, Some(TopOffFailure::MaxFillTime));
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_top_off_held_off() -> Result<()> {
+            let sensor = SensorName::new("sensor")?;
+            let outputs = OutputDefinitions::new(&[
+                new_output(
+                    "heater",
+                    1,
+                    &[ScheduledActivation::new(
+                        new_time(12, 0, 0),
+                        TimeDelta::minutes(2),
+                    )?],
+                )?,
+                new_output("refill pump", 2, &[])?.with_top_off(TopOff::new(
+                    sensor.clone(),
+                    40.0,
+                    60.0,
+                    60,
+                )?),
+            ])?
+            .with_interlocks(&[Interlock::new(
+                OutputName::new("refill pump")?,
+                InterlockKind::Excludes,
+                OutputName::new("heater")?,
+            )?])?;
+
+            let now = Local.from_local_datetime(&new_datetime(12, 0, 0)).unwrap();
+            let mut controller =
+                Controller::new(&outputs, MockGPIO::new(), fixed_clock(now.to_utc()))?;
+            controller.report_reading(&sensor, Quantity::WaterLevel, 35.0);
+
+            // the pump doesn't run while the heater is on so that time doesn't count
+            for (time, expected_state, expected_failure) in [
+                (new_datetime(12, 0, 0), OutputState::Off, None),
+                (new_datetime(12, 1, 30), OutputState::Off, None),
+                (new_datetime(12, 2, 30), OutputState::On, None),
+                (new_datetime(12, 3, 0), OutputState::On, None),
+                (
+                    new_datetime(12, 4, 0),
+                    OutputState::Off,
+                    Some(TopOffFailure::MaxFillTime),
+                ),
+            ] {
+                controller
+                    .update_outputs_for_time(Local.from_local_datetime(&time).unwrap().to_utc());
+                let status = controller.status().remove(1);
+                assert_eq!(status.state, expected_state, "{time}");
+                assert_eq!(status.top_off_failed, expected_failure, "{time}");
+            }
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_future_overrides() -> Result<()> {
+            let outputs = OutputDefinitions::new(&[new_output("pump", 1, &[])?])?;
+
+            let now = Local.from_local_datetime(&new_datetime(12, 0, 0)).unwrap();
+            let mut controller =
+                Controller::new(&outputs, MockGPIO::new(), fixed_clock(now.to_utc()))?;
+            controller.add_override(
+                OutputName::new("pump")?,
+                NewOverride::new(
+                    OutputState::On,
+                    local(&new_datetime(14, 0, 0)),
+                    TimeDelta::seconds(30 * 60),
+                )?,
+            )?;
+            controller.add_override(
+                OutputName::new("pump")?,
+                NewOverride::new(
+                    OutputState::On,
+                    local(&(new_datetime(12, 0, 0) + TimeDelta::days(3))),
+                    TimeDelta::seconds(60),
+                )?,
+            )?;
+
+            for (time, expected_state, expected_overrides) in [
+                (new_datetime(12, 0, 0), OutputState::Off, 2),
+                (new_datetime(14, 0, 5), OutputState::On, 2),
+                (new_datetime(14, 31, 0), OutputState::Off, 1),
+                (
+                    new_datetime(12, 0, 30) + TimeDelta::days(1),
+                    OutputState::Off,
+                    1,
+                ),
+                (
+                    new_datetime(12, 0, 30) + TimeDelta::days(3),
+                    OutputState::On,
+                    1,
+                ),
+                (
+                    new_datetime(12, 1, 30) + TimeDelta::days(3),
+                    OutputState::Off,
+                    0,
+                ),
+            ] {
+                controller
+                    .update_outputs_for_time(Local.from_local_datetime(&time).unwrap().to_utc());
+                assert_eq!(controller.status().remove(0).state, expected_state);
+                assert_eq!(controller.outputs[0].overrides.len(), expected_overrides);
+            }
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_skipped_overrides() -> Result<()> {
+            let outputs = OutputDefinitions::new(&[new_output("pump", 1, &[])?])?;
+
+            let now = Local.from_local_datetime(&new_datetime(12, 0, 0)).unwrap();
+            let mut controller =
+                Controller::new(&outputs, MockGPIO::new(), fixed_clock(now.to_utc()))?;
+            controller.add_override(
+                OutputName::new("pump")?,
+                NewOverride::new(
+                    OutputState::On,
+                    local(&new_datetime(12, 30, 0)),
+                    TimeDelta::seconds(60),
+                )?,
+            )?;
+            controller.update_outputs_for_time(now.to_utc());
+            assert_eq!(controller.outputs[0].overrides.len(), 1);
+
+            // the whole override happens between two updates
+            let later = Local.from_local_datetime(&new_datetime(13, 0, 0)).unwrap();
+            controller.update_outputs_for_time(later.to_utc());
+            assert_eq!(controller.status().remove(0).state, OutputState::Off);
+            assert!(controller.outputs[0].overrides.is_empty());
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_restore_overrides() -> Result<()> {
+            let outputs = OutputDefinitions::new(&[
+                new_output("lights", 1, &[])?,
+                new_output("fan", 2, &[])?,
+            ])?;
+
+            let now = Local.from_local_datetime(&new_datetime(23, 0, 0)).unwrap();
+            let mut controller =
+                Controller::new(&outputs, MockGPIO::new(), fixed_clock(now.to_utc()))?;
+            let id = controller.add_override(
+                OutputName::new("lights")?,
+                NewOverride::new(
+                    OutputState::Off,
+                    now.to_utc(),
+                    TimeDelta::seconds(2 * 60 * 60),
+                )?,
+            )?;
+            controller.update_outputs_for_time(now.to_utc());
+
+            let saved = controller.overrides();
+            assert_eq!(
+                saved,
+                vec![OverrideStatus::new(
+                    id,
+                    OutputName::new("lights")?,
+                    OutputState::Off,
+                    now.to_utc(),
+                    now.to_utc() + TimeDelta::hours(2),
+                )?]
+            );
+
+            let later = now + TimeDelta::hours(1);
+            let mut restored =
+                Controller::new(&outputs, MockGPIO::new(), fixed_clock(later.to_utc()))?;
+            restored.restore_overrides(&[
+                saved[0].clone(),
+                OverrideStatus::new(
+                    OverrideId::new(5),
+                    OutputName::new("fan")?,
+                    OutputState::On,
+                    local(&new_datetime(22, 0, 0)),
+                    local(&new_datetime(22, 30, 0)),
+                )?,
+                OverrideStatus::new(
+                    OverrideId::new(7),
+                    OutputName::new("pump")?,
+                    OutputState::On,
+                    local(&new_datetime(23, 0, 0)),
+                    local(&(new_datetime(23, 0, 0) + TimeDelta::hours(2))),
+                )?,
+            ]);
+            restored.update_outputs_for_time(later.to_utc());
+
+            assert_eq!(
+                restored.overrides(),
+                vec![OverrideStatus::new(
+                    id,
+                    OutputName::new("lights")?,
+                    OutputState::Off,
+                    now.to_utc(),
+                    now.to_utc() + TimeDelta::hours(2),
+                )?]
+            );
+
+            // identifiers of the dropped overrides aren't reused either
+            assert_eq!(
+                restored.add_override(
+                    OutputName::new("fan")?,
+                    NewOverride::new(OutputState::On, later.to_utc(), TimeDelta::seconds(60))?
+                )?,
+                OverrideId::new(8)
+            );
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_remove_override() -> Result<()> {
+            let outputs = OutputDefinitions::new(&[
+                new_output("lights", 1, &[])?,
+                new_output("fan", 2, &[])?,
+            ])?;
+
+            let now = Local.from_local_datetime(&new_datetime(12, 0, 0)).unwrap();
+            let mut controller =
+                Controller::new(&outputs, MockGPIO::new(), fixed_clock(now.to_utc()))?;
+            let lights = OutputName::new("lights")?;
+            let first = controller.add_override(
+                lights.clone(),
+                NewOverride::new(OutputState::On, now.to_utc(), TimeDelta::seconds(60))?,
+            )?;
+            let second = controller.add_override(
+                lights.clone(),
+                NewOverride::new(
+                    OutputState::Off,
+                    now.to_utc() + TimeDelta::hours(1),
+                    TimeDelta::seconds(60),
+                )?,
+            )?;
+            assert_ne!(first, second);
+
+            let ids = |controller: &Controller<_, _>| -> Result<Vec<OverrideId>> {
+                Ok(controller
+                    .output_overrides(&OutputName::new("lights")?)?
+                    .iter()
+                    .map(|v| v.id())
+                    .collect())
+            };
+            assert_eq!(ids(&controller)?, vec![first, second]);
+            assert!(controller
+                .output_overrides(&OutputName::new("fan")?)?
+                .is_empty());
+
+            controller.remove_override(lights.clone(), first)?;
+            assert_eq!(ids(&controller)?, vec![second]);
+
+            let err = controller
+                .remove_override(lights.clone(), first)
+                .unwrap_err();
+            assert!(err.downcast_ref::<UnknownOverride>().is_some());
+            assert!(controller
+                .remove_override(OutputName::new("fan")?, second)
+                .is_err());
+            assert!(controller
+                .output_overrides(&OutputName::new("pump")?)
+                .is_err());
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_override_priorities() -> Result<()> {
+            let outputs = OutputDefinitions::new(&[new_output("misting", 1, &[])?])?;
+
+            let now = Local.from_local_datetime(&new_datetime(12, 0, 0)).unwrap();
+            let mut controller =
+                Controller::new(&outputs, MockGPIO::new(), fixed_clock(now.to_utc()))?;
+            let misting = OutputName::new("misting")?;
+            let routine = controller.add_override(
+                misting.clone(),
+                NewOverride::new(OutputState::On, now.to_utc(), TimeDelta::seconds(10 * 60))?,
+            )?;
+
+            let err = controller
+                .add_override(
+                    misting.clone(),
+                    NewOverride::new(
+                        OutputState::Off,
+                        local(&new_datetime(12, 5, 0)),
+                        TimeDelta::seconds(10 * 60),
+                    )?,
+                )
+                .unwrap_err();
+            let err = err.downcast_ref::<ConflictingOverride>().unwrap();
+            assert_eq!(err.id(), routine);
+
+            // setting the same state isn't a conflict
+            let repeated = controller.add_override(
+                misting.clone(),
+                NewOverride::new(OutputState::On, now.to_utc(), TimeDelta::seconds(5 * 60))?,
+            )?;
+
+            // neither is a different state that doesn't overlap
+            controller.add_override(
+                misting.clone(),
+                NewOverride::new(
+                    OutputState::Off,
+                    local(&new_datetime(12, 30, 0)),
+                    TimeDelta::seconds(60),
+                )?,
+            )?;
+
+            let emergency = controller.add_override(
+                misting.clone(),
+                NewOverride::new(
+                    OutputState::Off,
+                    local(&new_datetime(12, 2, 0)),
+                    TimeDelta::seconds(5 * 60),
+                )?
+                .with_priority(OverridePriority::new(10)),
+            )?;
+
+            for (time, expected_state, expected_override) in [
+                (new_datetime(12, 1, 0), OutputState::On, Some(repeated)),
+                (new_datetime(12, 3, 0), OutputState::Off, Some(emergency)),
+                (new_datetime(12, 8, 0), OutputState::On, Some(routine)),
+                (new_datetime(12, 11, 0), OutputState::Off, None),
+            ] {
+                controller
+                    .update_outputs_for_time(Local.from_local_datetime(&time).unwrap().to_utc());
+                let status = controller.status().remove(0);
+                assert_eq!(status.state, expected_state);
+                assert_eq!(status.override_in_effect.map(|v| v.id()), expected_override);
+                assert_eq!(controller.override_in_effect(&misting)?, expected_override);
+            }
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_sub_second_pulses() -> Result<()> {
+            let outputs = OutputDefinitions::new(&[new_output(
+                "misting",
+                1,
+                &[ScheduledActivation::new(
+                    new_time(12, 0, 0),
+                    TimeDelta::milliseconds(500),
+                )?],
+            )?])?;
+
+            let at = |millis: i64| {
+                Local
+                    .from_local_datetime(
+                        &(new_datetime(12, 0, 0) + TimeDelta::milliseconds(millis)),
+                    )
+                    .unwrap()
+                    .to_utc()
+            };
+
+            let controller = Controller::new(&outputs, MockGPIO::new(), fixed_clock(at(-100)))?;
+            assert_eq!(controller.next_change(), Some(TimeDelta::milliseconds(100)));
+
+            let mut controller = Controller::new(&outputs, MockGPIO::new(), fixed_clock(at(200)))?;
+            assert_eq!(controller.next_change(), Some(TimeDelta::milliseconds(301)));
+
+            controller.add_override(
+                OutputName::new("misting")?,
+                NewOverride::new(
+                    OutputState::On,
+                    local(&(new_datetime(12, 0, 0) + TimeDelta::milliseconds(300))),
+                    TimeDelta::milliseconds(50),
+                )?,
+            )?;
+            assert_eq!(controller.next_change(), Some(TimeDelta::milliseconds(100)));
+
+            for (step, millis, expected_state) in [
+                ("before", -1, OutputState::Off),
+                ("start", 0, OutputState::On),
+                ("end", 500, OutputState::On),
+                ("after", 501, OutputState::Off),
+            ] {
+                println!("step: {}", step);
+                controller.update_outputs_for_time(at(millis));
+                assert_eq!(controller.status().remove(0).state, expected_state);
+            }
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_preview() -> Result<()> {
+            let outputs = OutputDefinitions::new(&[
+                new_output(
+                    "misting",
+                    1,
+                    &[ScheduledActivation::new(
+                        new_time(11, 30, 0),
+                        TimeDelta::minutes(10),
+                    )?],
+                )?,
+                new_output(
+                    "fan",
+                    2,
+                    &[ScheduledActivation::new(
+                        new_time(11, 0, 0),
+                        TimeDelta::hours(1),
+                    )?],
+                )?,
+            ])?
+            .with_interlocks(&[Interlock::new(
+                OutputName::new("misting")?,
+                InterlockKind::Excludes,
+                OutputName::new("fan")?,
+            )?])?;
+
+            let now = Local.from_local_datetime(&new_datetime(10, 0, 0)).unwrap();
+            let mut controller =
+                Controller::new(&outputs, MockGPIO::new(), fixed_clock(now.to_utc()))?;
+            controller.add_override(
+                OutputName::new("fan")?,
+                NewOverride::new(
+                    OutputState::Off,
+                    local(&new_datetime(11, 30, 0)),
+                    TimeDelta::minutes(5),
+                )?,
+            )?;
+
+            let transition = |time: NaiveDateTime, output: &str, state: OutputState| {
+                Ok::<_, anyhow::Error>(Transition {
+                    time,
+                    output: OutputName::new(output)?,
+                    state,
+                })
+            };
+            let after = |time: NaiveDateTime| time + TimeDelta::milliseconds(1);
+            assert_eq!(
+                controller.preview(TimeDelta::days(1))?,
+                vec![
+                    transition(new_datetime(10, 0, 0), "misting", OutputState::Off)?,
+                    transition(new_datetime(10, 0, 0), "fan", OutputState::Off)?,
+                    transition(new_datetime(11, 0, 0), "fan", OutputState::On)?,
+                    transition(new_datetime(11, 30, 0), "misting", OutputState::On)?,
+                    transition(new_datetime(11, 30, 0), "fan", OutputState::Off)?,
+                    transition(after(new_datetime(11, 35, 0)), "misting", OutputState::Off)?,
+                    transition(after(new_datetime(11, 35, 0)), "fan", OutputState::On)?,
+                    transition(after(new_datetime(12, 0, 0)), "fan", OutputState::Off)?,
+                ]
+            );
+
+            assert!(controller.preview(TimeDelta::days(8)).is_err());
+            assert!(controller.preview(TimeDelta::zero()).is_err());
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_active_low() -> Result<()> {
+            let activation = ScheduledActivation::new(new_time(12, 0, 0), TimeDelta::hours(1))?;
+            let outputs = OutputDefinitions::new(&[
+                new_output("pump", 1, &[activation])?
+                    .with_active_low()
+                    .with_safe_state(OutputState::On)?,
+                new_output("heater", 2, &[activation])?,
+                new_output(
+                    "lights",
+                    3,
+                    &[activation.with_brightness(Brightness::new(0.25)?)],
+                )?
+                .with_pwm(Pwm::new(PwmKind::Hardware, 500.0)?)
+                .with_active_low(),
+            ])?;
+
+            let now = Local.from_local_datetime(&new_datetime(12, 0, 0)).unwrap();
+            let mut controller =
+                Controller::new(&outputs, MockGPIO::new(), fixed_clock(now.to_utc()))?;
+
+            let pins = |controller: &Controller<MockOutputPin, FixedTimeProvider>| {
+                controller
+                    .outputs
+                    .iter()
+                    .map(|v| v.pin.duty_cycle().fraction())
+                    .collect::<Vec<_>>()
+            };
+
+            for (step, time, expected_states, expected_pins) in [
+                (
+                    "on",
+                    new_datetime(12, 30, 0),
+                    vec![
+                        OutputState::On,
+                        OutputState::On,
+                        OutputState::Dimmed(Brightness::new(0.25)?),
+                    ],
+                    vec![0.0, 1.0, 0.75],
+                ),
+                (
+                    "off",
+                    new_datetime(14, 0, 0),
+                    vec![OutputState::Off, OutputState::Off, OutputState::Off],
+                    vec![1.0, 0.0, 1.0],
+                ),
+            ] {
+                println!("step: {}", step);
+                controller
+                    .update_outputs_for_time(Local.from_local_datetime(&time).unwrap().to_utc());
+                let states: Vec<OutputState> =
+                    controller.status().into_iter().map(|v| v.state).collect();
+                assert_eq!(states, expected_states);
+                assert_eq!(pins(&controller), expected_pins);
+            }
+
+            controller.fail_safe();
+            assert_eq!(pins(&controller), vec![0.0, 0.0, 1.0]);
+
+            assert!(new_output("pump", 1, &[])?
+                .with_safe_state(OutputState::Dimmed(Brightness::new(0.5)?))
+                .is_err());
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_startup() -> Result<()> {
+            let outputs = OutputDefinitions::new(&[
+                new_output("pump", 1, &[])?.with_safe_state(OutputState::On)?,
+                new_output("heater", 2, &[])?.with_active_low(),
+                new_output("lights", 3, &[])?.with_pwm(Pwm::new(PwmKind::Hardware, 500.0)?),
+            ])?;
+
+            let now = Local.from_local_datetime(&new_datetime(12, 0, 0)).unwrap();
+            let mut controller =
+                Controller::new(&outputs, MockGPIO::new(), fixed_clock(now.to_utc()))?;
+
+            let pins = |controller: &Controller<MockOutputPin, FixedTimeProvider>| {
+                controller
+                    .outputs
+                    .iter()
+                    .map(|v| v.pin.duty_cycle().fraction())
+                    .collect::<Vec<_>>()
+            };
+
+            // the pins start in the safe states
+            assert_eq!(pins(&controller), vec![1.0, 1.0, 0.0]);
+
+            controller.update_outputs_for_time(now.to_utc());
+            assert_eq!(pins(&controller), vec![0.0, 1.0, 0.0]);
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_scenes() -> Result<()> {
+            let misting = OutputName::new("misting")?;
+            let fan = OutputName::new("fan")?;
+            let lights = OutputName::new("lights")?;
+            let feeding = SceneName::new("feeding")?;
+            let viewing = SceneName::new("viewing")?;
+
+            let outputs = OutputDefinitions::new(&[
+                new_output("misting", 1, &[])?,
+                new_output(
+                    "fan",
+                    2,
+                    &[ScheduledActivation::new(
+                        new_time(11, 0, 0),
+                        TimeDelta::hours(2),
+                    )?],
+                )?,
+                new_output("lights", 3, &[])?,
+            ])?
+            .with_interlocks(&[Interlock::new(
+                misting.clone(),
+                InterlockKind::Excludes,
+                fan.clone(),
+            )?])?
+            .with_scenes(&[
+                // misting can only be turned on once the fan is off
+                Scene::new(
+                    feeding.clone(),
+                    TimeDelta::minutes(20),
+                    &[
+                        (misting.clone(), OutputState::On),
+                        (fan.clone(), OutputState::Off),
+                    ],
+                )?,
+                Scene::new(
+                    viewing.clone(),
+                    TimeDelta::minutes(20),
+                    &[
+                        (fan.clone(), OutputState::Off),
+                        (lights.clone(), OutputState::On),
+                    ],
+                )?,
+            ])?;
+
+            let now = Local.from_local_datetime(&new_datetime(12, 0, 0)).unwrap();
+            let mut controller =
+                Controller::new(&outputs, MockGPIO::new(), fixed_clock(now.to_utc()))?;
+            let scenes = |controller: &Controller<MockOutputPin, FixedTimeProvider>| {
+                controller
+                    .overrides()
+                    .iter()
+                    .map(|v| (v.output().clone(), v.scene().cloned()))
+                    .collect::<Vec<_>>()
+            };
+
+            let ids = controller.apply_scene(&feeding)?;
+            assert_eq!(ids.len(), 2);
+            controller.update_outputs_for_time(now.to_utc());
+            assert_eq!(
+                controller
+                    .status()
+                    .into_iter()
+                    .map(|v| v.state)
+                    .collect::<Vec<_>>(),
+                vec![OutputState::On, OutputState::Off, OutputState::Off]
+            );
+
+            // applying the scene again starts it over
+            controller.apply_scene(&feeding)?;
+            assert_eq!(
+                scenes(&controller),
+                vec![
+                    (misting.clone(), Some(feeding.clone())),
+                    (fan.clone(), Some(feeding.clone())),
+                ]
+            );
+
+            // the previous run of the scene is kept if starting it over fails
+            let fan_on = controller.add_override(
+                fan.clone(),
+                NewOverride::new(OutputState::On, now.to_utc(), TimeDelta::hours(1))?
+                    .with_priority(OverridePriority::new(5)),
+            )?;
+            let err = controller.apply_scene(&feeding).unwrap_err();
+            assert!(err.downcast_ref::<BlockedByInterlock>().is_some());
+            assert_eq!(
+                scenes(&controller),
+                vec![
+                    (misting.clone(), Some(feeding.clone())),
+                    (fan.clone(), None),
+                    (fan.clone(), Some(feeding.clone())),
+                ]
+            );
+            controller.remove_override(fan.clone(), fan_on)?;
+
+            // the scene is rolled back if any of its overrides is rejected
+            controller.add_override(
+                lights.clone(),
+                NewOverride::new(
+                    OutputState::Off,
+                    local(&new_datetime(12, 0, 0)),
+                    TimeDelta::hours(1),
+                )?,
+            )?;
+            let err = controller.apply_scene(&viewing).unwrap_err();
+            assert!(err.downcast_ref::<ConflictingOverride>().is_some());
+            assert_eq!(
+                scenes(&controller),
+                vec![
+                    (misting.clone(), Some(feeding.clone())),
+                    (fan.clone(), Some(feeding.clone())),
+                    (lights.clone(), None),
+                ]
+            );
+
+            controller.cancel_scene(&feeding)?;
+            assert_eq!(scenes(&controller), vec![(lights.clone(), None)]);
+
+            assert!(controller.apply_scene(&SceneName::new("unknown")?).is_err());
+
+            // none of the outputs are dimmable
+            let dimmed = OutputState::Dimmed(Brightness::new(0.3)?);
+            assert!(controller
+                .add_override(
+                    lights.clone(),
+                    NewOverride::new(dimmed, local(&new_datetime(12, 0, 0)), TimeDelta::hours(1))?
+                        .with_priority(OverridePriority::new(100))
+                )
+                .is_err());
+            assert!(outputs
+                .clone()
+                .with_scenes(&[Scene::new(
+                    viewing.clone(),
+                    TimeDelta::minutes(20),
+                    &[(lights.clone(), dimmed)],
+                )?])
+                .is_err());
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_hold() -> Result<()> {
+            let outputs = OutputDefinitions::new(&[
+                new_output(
+                    "misting",
+                    1,
+                    &[ScheduledActivation::new(
+                        new_time(12, 30, 0),
+                        TimeDelta::minutes(10),
+                    )?],
+                )?,
+                new_output("fan", 2, &[])?.with_safe_state(OutputState::On)?,
+                new_output(
+                    "lights",
+                    3,
+                    &[ScheduledActivation::new(
+                        new_time(11, 0, 0),
+                        TimeDelta::hours(2),
+                    )?],
+                )?,
+            ])?;
+
+            let now = Local.from_local_datetime(&new_datetime(12, 0, 0)).unwrap();
+            let at = |hour, min| {
+                Local
+                    .from_local_datetime(&new_datetime(hour, min, 0))
+                    .unwrap()
+                    .to_utc()
+            };
+            let mut controller =
+                Controller::new(&outputs, MockGPIO::new(), fixed_clock(now.to_utc()))?;
+
+            assert!(Hold::new()
+                .with_state(OutputState::Dimmed(Brightness::new(0.5)?))
+                .is_err());
+            assert!(controller.hold(Hold::new().with_until(at(11, 0))).is_err());
+
+            // the outputs are held in their safe states even once an activation starts
+            controller.hold(Hold::new().with_until(at(12, 45)))?;
+            for time in [at(12, 0), at(12, 35)] {
+                controller.update_outputs_for_time(time);
+                assert_eq!(
+                    states(&controller),
+                    vec![
+                        ("misting", OutputState::Off, false),
+                        ("fan", OutputState::On, false),
+                        ("lights", OutputState::Off, false),
+                    ]
+                );
+            }
+
+            // the hold runs out
+            controller.update_outputs_for_time(at(12, 46));
+            assert_eq!(controller.current_hold(), None);
+            assert_eq!(
+                states(&controller),
+                vec![
+                    ("misting", OutputState::Off, false),
+                    ("fan", OutputState::Off, false),
+                    ("lights", OutputState::On, false),
+                ]
+            );
+
+            // the outputs are held in the chosen state until resumed
+            let hold = Hold::new().with_state(OutputState::On)?;
+            controller.hold(hold)?;
+            controller.update_outputs_for_time(at(14, 0));
+            assert_eq!(controller.current_hold(), Some(hold));
+            assert_eq!(
+                states(&controller),
+                vec![
+                    ("misting", OutputState::On, false),
+                    ("fan", OutputState::On, false),
+                    ("lights", OutputState::On, false),
+                ]
+            );
+
+            controller.resume();
+            controller.update_outputs_for_time(at(14, 1));
+            assert_eq!(controller.current_hold(), None);
+            assert_eq!(
+                states(&controller),
+                vec![
+                    ("misting", OutputState::Off, false),
+                    ("fan", OutputState::Off, false),
+                    ("lights", OutputState::Off, false),
+                ]
+            );
+
+            // a hold which ran out while the program wasn't running is dropped
+            controller.restore_hold(Hold::new().with_until(at(11, 0)));
+            assert_eq!(controller.current_hold(), None);
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_profiles() -> Result<()> {
+            let dry_season = ProfileName::new("dry_season")?;
+            let vacation = ProfileName::new("vacation")?;
+
+            let outputs = OutputDefinitions::new(&[
+                new_output(
+                    "lights",
+                    1,
+                    &[ScheduledActivation::new(
+                        new_time(8, 0, 0),
+                        TimeDelta::hours(12),
+                    )?],
+                )?
+                .with_profile(
+                    dry_season.clone(),
+                    ScheduledActivations::new(&[ScheduledActivation::new(
+                        new_time(10, 0, 0),
+                        TimeDelta::hours(8),
+                    )?])?,
+                )?,
+                new_output(
+                    "misting",
+                    2,
+                    &[ScheduledActivation::new(
+                        new_time(12, 0, 0),
+                        TimeDelta::minutes(10),
+                    )?],
+                )?
+                .with_profile(vacation.clone(), ScheduledActivations::new(&[])?)?,
+            ])?
+            .with_profiles(&[
+                Profile::new(dry_season.clone()).with_dates(DateRange::new(
+                    DayOfYear::new(6, 1)?,
+                    DayOfYear::new(9, 30)?,
+                )?),
+                Profile::new(vacation.clone()),
+            ])?;
+
+            let local =
+                |month, day, hour, min| new_date(2024, month, day).and_time(new_time(hour, min, 0));
+            let at = |month, day, hour, min| {
+                Local
+                    .from_local_datetime(&local(month, day, hour, min))
+                    .unwrap()
+                    .to_utc()
+            };
+            let mut controller =
+                Controller::new(&outputs, MockGPIO::new(), fixed_clock(at(5, 31, 12, 0)))?;
+            let lights = OutputName::new("lights")?;
+
+            // the profile switches automatically once its dates start
+            let transitions = controller.preview(TimeDelta::hours(36))?;
+            let end = TimeDelta::milliseconds(1);
+            assert_eq!(
+                transitions
+                    .iter()
+                    .filter(|v| v.output == lights)
+                    .map(|v| (v.time, v.state))
+                    .collect::<Vec<_>>(),
+                vec![
+                    (local(5, 31, 12, 0), OutputState::On),
+                    (local(5, 31, 20, 0) + end, OutputState::Off),
+                    (local(6, 1, 10, 0), OutputState::On),
+                    (local(6, 1, 18, 0) + end, OutputState::Off),
+                ]
+            );
+
+            controller.update_outputs_for_time(at(5, 31, 12, 0));
+            assert_eq!(controller.current_profile().name(), None);
+            assert_eq!(
+                states(&controller),
+                vec![
+                    ("lights", OutputState::On, false),
+                    ("misting", OutputState::On, false),
+                ]
+            );
+
+            // outputs without activations for the profile keep their usual ones
+            controller.switch_profile(Some(&vacation))?;
+            controller.update_outputs_for_time(at(5, 31, 12, 5));
+            assert_eq!(controller.current_profile().name(), Some(&vacation));
+            assert_eq!(
+                states(&controller),
+                vec![
+                    ("lights", OutputState::On, false),
+                    ("misting", OutputState::Off, false),
+                ]
+            );
+
+            controller.update_outputs_for_time(at(6, 1, 9, 0));
+            assert_eq!(controller.current_profile().name(), Some(&dry_season));
+            assert_eq!(
+                states(&controller),
+                vec![
+                    ("lights", OutputState::Off, false),
+                    ("misting", OutputState::Off, false),
+                ]
+            );
+
+            // a profile switched to by hand is kept until the dates say otherwise
+            controller.restore_profile(ActiveProfile::new(
+                Some(vacation.clone()),
+                new_date(2024, 6, 1),
+            ));
+            controller.update_outputs_for_time(at(6, 2, 12, 0));
+            assert_eq!(controller.current_profile().name(), Some(&vacation));
+            assert_eq!(
+                states(&controller),
+                vec![
+                    ("lights", OutputState::On, false),
+                    ("misting", OutputState::Off, false),
+                ]
+            );
 
-                assert_eq!(
-                    test_case.a.overlaps(&test_case.b),
-                    test_case.expected_overlaps
-                );
+            controller.update_outputs_for_time(at(10, 1, 12, 0));
+            assert_eq!(controller.current_profile().name(), None);
+            assert_eq!(
+                states(&controller),
+                vec![
+                    ("lights", OutputState::On, false),
+                    ("misting", OutputState::On, false),
+                ]
+            );
 
-                assert_eq!(
-                    test_case.b.overlaps(&test_case.a),
-                    test_case.expected_overlaps
-                );
-            }
+            let unknown = ProfileName::new("unknown")?;
+            assert!(controller.switch_profile(Some(&unknown)).is_err());
+            controller.restore_profile(ActiveProfile::new(Some(unknown), new_date(2024, 10, 1)));
+            assert_eq!(controller.current_profile().name(), None);
 
             Ok(())
         }
 
         #[test]
-        fn test_repeat() -> Result<()> {
-            struct TestCase<'a> {
-                name: &'a str,
-                activation: ScheduledActivation,
-                start_every: u32,
-                times: u32,
-                result: Result<Vec<ScheduledActivation>>,
+        fn test_overlapping_profiles() -> Result<()> {
+            let outputs = OutputDefinitions::new(&[new_output("lights", 1, &[])?])?;
+            let dates = |from: (u32, u32), until: (u32, u32)| -> Result<DateRange> {
+                DateRange::new(
+                    DayOfYear::new(from.0, from.1)?,
+                    DayOfYear::new(until.0, until.1)?,
+                )
+            };
+
+            for (a, b, expected_ok) in [
+                (dates((6, 1), (9, 30))?, dates((10, 1), (11, 30))?, true),
+                (dates((6, 1), (9, 30))?, dates((9, 30), (11, 30))?, false),
+                (dates((6, 1), (9, 30))?, dates((7, 1), (7, 31))?, false),
+                (dates((11, 1), (3, 31))?, dates((4, 1), (10, 31))?, true),
+                (dates((11, 1), (3, 31))?, dates((3, 1), (5, 31))?, false),
+                (dates((11, 1), (3, 31))?, dates((12, 1), (1, 31))?, false),
+            ] {
+                println!("dates: {a:?} {b:?}");
+                let result = outputs.clone().with_profiles(&[
+                    Profile::new(ProfileName::new("a")?).with_dates(a),
+                    Profile::new(ProfileName::new("b")?).with_dates(b),
+                ]);
+                assert_eq!(result.is_ok(), expected_ok);
             }
 
-            let test_cases = vec![
-                TestCase {
-                name: "zero_times",
-                activation: ScheduledActivation::new(new_time(14, 0, 0), 10)?,
-                start_every: 15,
-                times: 0,
-                result: Err(anyhow!("repeating less than two times makes no sense, just use the existing activation")),
-            },
-                TestCase {
-                name: "one_time",
-                activation: ScheduledActivation::new(new_time(14, 0, 0), 10)?,
-                start_every: 15,
-                times: 1,
-                result: Err(anyhow!("repeating less than two times makes no sense, just use the existing activation")),
-            },
-                TestCase {
-                name: "just_as_often",
-                activation: ScheduledActivation::new(new_time(14, 0, 0), 10)?,
-                start_every: 10,
-                times: 2,
-                result: Err(anyhow!("repeating more often than or just as often as for_seconds makes no sense, the results will overlap")),
-            },
-                TestCase {
-                name: "more_often",
-                activation: ScheduledActivation::new(new_time(14, 0, 0), 10)?,
-                start_every: 9,
-                times: 2,
-                result: Err(anyhow!("repeating more often than or just as often as for_seconds makes no sense, the results will overlap")),
-            },
-                TestCase {
-                name: "simple",
-                activation: ScheduledActivation::new(new_time(14, 0, 0), 10)?,
-                start_every: 15,
-                times: 2,
-                result: Ok(vec![
-                    ScheduledActivation::new(new_time(14, 0, 0), 10)?,
-                    ScheduledActivation::new(new_time(14, 0, 15), 10)?,
-                ]),
-            },
-            TestCase {
-                name: "start_every_longer_than_a_day",
-                activation: ScheduledActivation::new(new_time(14, 0, 0), 10)?,
-                start_every: 25 * 60 * 60,
-                times: 2,
-                result: Err(anyhow!("there is no way we meant to jump by more than a whole day or a whole day when repeating")),
-            },
-            TestCase {
-                name: "start_every_equal_to_one_day",
-                activation: ScheduledActivation::new(new_time(14, 0, 0), 10)?,
-                start_every: 24 * 60 * 60,
-                times: 2,
-                result: Err(anyhow!("there is no way we meant to jump by more than a whole day or a whole day when repeating")),
-            },
-            TestCase {
-                name: "start_jumps_over_midnight",
-                activation: ScheduledActivation::new(new_time(23, 0, 0), 60)?,
-                start_every: 60 * 60,
-                times: 2,
-                result: Err(anyhow!("I find it highly suspicious that we jumped over midnight when repeating, I suspect we didn't want this to happen")),
-            },
-            ];
+            Ok(())
+        }
 
-            for test_case in &test_cases {
-                println!("test case: {}", test_case.name);
+        #[test]
+        fn test_daylight_saving_time() -> Result<()> {
+            let outputs = OutputDefinitions::new(&[new_output(
+                "misting",
+                1,
+                &[ScheduledActivation::new(
+                    new_time(2, 30, 0),
+                    TimeDelta::seconds(10 * 60),
+                )?],
+            )?])?;
 
-                let result = test_case
-                    .activation
-                    .repeat(test_case.start_every, test_case.times);
-                match &test_case.result {
-                    Ok(a) => match result {
-                        Ok(b) => assert_eq!(a, &b),
-                        Err(err_b) => panic!("got an error when a value was expected: {:?}", err_b),
-                    },
-                    Err(err_a) => match result {
-                        Ok(b) => panic!("got a value when an error was expected: {:?}", b),
-                        Err(err_b) => assert_eq!(err_a.to_string(), err_b.to_string()),
-                    },
-                }
+            // in 2024 the clocks in Poland went forward from 02:00 to 03:00 on the 31st of March
+            // and back from 03:00 to 02:00 on the 27th of October, both at 01:00 UTC
+            let utc = |month: u32, day: u32, hour: u32, min: u32| {
+                new_date(2024, month, day)
+                    .and_time(new_time(hour, min, 0))
+                    .and_utc()
+            };
+            let timezone = Timezone::new("Europe/Warsaw")?;
+            let mut controller = Controller::new(
+                &outputs,
+                MockGPIO::new(),
+                Clock::new(FixedTimeProvider(utc(3, 31, 0, 0)), timezone),
+            )?;
+
+            for (name, time, expected_state) in [
+                ("normal_day", utc(3, 30, 1, 35), OutputState::On),
+                ("skipped_time", utc(3, 31, 0, 35), OutputState::Off),
+                ("moved_with_the_clocks", utc(3, 31, 1, 35), OutputState::On),
+                (
+                    "moved_with_the_clocks_end",
+                    utc(3, 31, 1, 45),
+                    OutputState::Off,
+                ),
+                (
+                    "repeated_hour_first_time",
+                    utc(10, 27, 0, 35),
+                    OutputState::On,
+                ),
+                (
+                    "repeated_hour_first_time_end",
+                    utc(10, 27, 0, 45),
+                    OutputState::Off,
+                ),
+                (
+                    "repeated_hour_second_time",
+                    utc(10, 27, 1, 35),
+                    OutputState::Off,
+                ),
+            ] {
+                println!("step: {}", name);
+                controller.update_outputs_for_time(time);
+                assert_eq!(controller.status().remove(0).state, expected_state);
             }
 
             Ok(())
         }
-    }
-
-    mod scheduled_activations {
-        use super::*;
-        use anyhow::Error;
-        use core::panic;
 
         #[test]
-        fn test_construct() -> Result<()> {
-            struct TestCase<'a> {
-                name: &'a str,
-                activations: Vec<ScheduledActivation>,
-                expected_error: Option<Error>,
-            }
+        fn test_timers_when_clocks_go_back() -> Result<()> {
+            let sensor = SensorName::new("sensor")?;
+            let outputs = OutputDefinitions::new(&[
+                new_output("heater", 1, &[])?.with_regulation(
+                    Regulation::new(
+                        sensor.clone(),
+                        Quantity::Temperature,
+                        Direction::Raise,
+                        Setpoint::new(25.0, 1.0)?,
+                    )
+                    .with_pid(PidSettings::new(Gains::new(0.1, 0.0, 0.0)?, 60)?),
+                ),
+                new_output("refill pump", 2, &[])?.with_top_off(TopOff::new(
+                    sensor.clone(),
+                    40.0,
+                    60.0,
+                    20 * 60,
+                )?),
+            ])?;
 
-            let test_cases = vec![
-                TestCase {
-                    name: "empty",
-                    activations: vec![],
-                    expected_error: None,
-                },
-                TestCase {
-                    name: "overlap",
-                    activations: vec![
-                        ScheduledActivation::new(new_time(12, 0, 0), 10)?,
-                        ScheduledActivation::new(new_time(12, 0, 5), 10)?,
-                    ],
-                    expected_error: Some(anyhow!("activations can't overlap")),
-                },
-                TestCase {
-                    name: "ok",
-                    activations: vec![
-                        ScheduledActivation::new(new_time(12, 0, 0), 10)?,
-                        ScheduledActivation::new(new_time(18, 0, 0), 10)?,
-                    ],
-                    expected_error: None,
-                },
-            ];
+            // the wall clock stands still between 01:00 and 02:00 UTC as the clocks in Poland go
+            // back from 03:00 to 02:00
+            let start = new_date(2024, 10, 27)
+                .and_time(new_time(0, 55, 0))
+                .and_utc();
+            let now = Rc::new(Cell::new(start));
+            let mut controller = Controller::new(
+                &outputs,
+                MockGPIO::new(),
+                Clock::new(
+                    SharedTimeProvider(now.clone()),
+                    Timezone::new("Europe/Warsaw")?,
+                ),
+            )?;
 
-            for test_case in &test_cases {
-                println!("test case: {}", test_case.name);
+            let mut heater_on = 0;
+            let mut top_off_failed = None;
+            for i in 0..(25 * 6) {
+                now.set(start + TimeDelta::seconds(i * 10));
+                controller.report_reading(&sensor, Quantity::Temperature, 20.0);
+                controller.report_reading(&sensor, Quantity::WaterLevel, 35.0);
+                controller.update_outputs_for_time(now.get());
 
-                let result = ScheduledActivations::new(&test_case.activations);
-                match &test_case.expected_error {
-                    Some(expected_err) => match result {
-                        Ok(_) => {
-                            panic!("no error encountered even though an error was expected")
-                        }
-                        Err(err) => {
-                            assert_eq!(err.to_string(), expected_err.to_string());
-                        }
-                    },
-                    None => {
-                        match result {
-                            Ok(_) => {
-                                // ok
-                            }
-                            Err(_) => {
-                                panic!("error encountered even though no error was expected")
-                            }
-                        }
-                    }
+                let status = controller.status();
+                if now.get() >= start + TimeDelta::minutes(5) && status[0].state == OutputState::On
+                {
+                    heater_on += 1;
+                }
+                if top_off_failed.is_none() && status[1].top_off_failed.is_some() {
+                    top_off_failed = Some(now.get());
                 }
             }
 
+            // the heater keeps cycling with a duty cycle of 50% and the pump gives up after
+            // pumping for 20 minutes
+            assert_eq!(heater_on, 20 * 6 / 2);
+            assert_eq!(top_off_failed, Some(start + TimeDelta::minutes(20)));
+
             Ok(())
         }
-    }
-
-    mod controlled_output {
-        use super::*;
-        use crate::adapters::MockOutputPin;
 
         #[test]
-        fn test_target_state() -> Result<()> {
-            struct TestCase<'a> {
-                name: &'a str,
-                activations: Vec<ScheduledActivation>,
-                overrides: Vec<Override>,
-                expected_state: OutputState,
+        fn test_overrides_when_clocks_go_back() -> Result<()> {
+            let outputs = OutputDefinitions::new(&[new_output("misting", 1, &[])?])?;
+
+            // 02:30 for the second time, the wall clock stands still at 03:00 until 02:00 UTC
+            let start = new_date(2024, 10, 27)
+                .and_time(new_time(1, 30, 0))
+                .and_utc();
+            let now = Rc::new(Cell::new(start));
+            let mut controller = Controller::new(
+                &outputs,
+                MockGPIO::new(),
+                Clock::new(
+                    SharedTimeProvider(now.clone()),
+                    Timezone::new("Europe/Warsaw")?,
+                ),
+            )?;
+
+            let misting = OutputName::new("misting")?;
+            controller.add_override(
+                misting.clone(),
+                NewOverride::new(OutputState::On, start, TimeDelta::seconds(60))?,
+            )?;
+            assert_eq!(
+                controller.overrides()[0].end(),
+                start + TimeDelta::seconds(60)
+            );
+            assert_eq!(
+                controller.next_change(),
+                Some(TimeDelta::seconds(60) + TimeDelta::milliseconds(1))
+            );
+
+            for (seconds, expected_state) in [
+                (0, OutputState::On),
+                (60, OutputState::On),
+                (61, OutputState::Off),
+            ] {
+                now.set(start + TimeDelta::seconds(seconds));
+                controller.update_outputs_for_time(now.get());
+                assert_eq!(controller.status().remove(0).state, expected_state);
             }
+            assert!(controller.overrides().is_empty());
 
-            let time = new_time(12, 00, 00);
-            let test_cases = vec![
-                TestCase {
-                    name: "empty",
-                    activations: vec![],
-                    overrides: vec![],
-                    expected_state: OutputState::Off,
-                },
-                TestCase {
-                    name: "no_override",
-                    activations: vec![ScheduledActivation::new(new_time(11, 59, 55), 10)?],
-                    overrides: vec![],
-                    expected_state: OutputState::On,
-                },
-                TestCase {
-                    name: "override_off",
-                    activations: vec![ScheduledActivation::new(new_time(11, 59, 55), 10)?],
-                    overrides: vec![Override::new(
-                        OutputState::Off,
-                        ScheduledActivation::new(new_time(11, 59, 55), 10)?,
-                    )],
-                    expected_state: OutputState::Off,
-                },
-                TestCase {
-                    name: "override_on",
-                    activations: vec![ScheduledActivation::new(new_time(18, 00, 00), 10)?],
-                    overrides: vec![Override::new(
-                        OutputState::On,
-                        ScheduledActivation::new(new_time(11, 59, 55), 10)?,
-                    )],
-                    expected_state: OutputState::On,
-                },
-            ];
+            Ok(())
+        }
 
-            for test_case in &test_cases {
-                println!("test case: {}", test_case.name);
+        #[test]
+        fn test_hold_when_clocks_go_back() -> Result<()> {
+            let outputs = OutputDefinitions::new(&[
+                new_output("fan", 1, &[])?.with_safe_state(OutputState::On)?
+            ])?;
 
-                let pin_number = PinNumber::new(1)?;
-                let activations = ScheduledActivations::new(&test_case.activations)?;
-                let definition =
-                    OutputDefinition::new(OutputName::new("output")?, pin_number, activations);
-                let mut output = ControlledOutput {
-                    definition,
-                    pin: MockOutputPin::new(pin_number),
-                    overrides: test_case.overrides.clone(),
-                };
+            // 02:30 for the second time, the wall clock stands still at 03:00 until 02:00 UTC
+            let start = new_date(2024, 10, 27)
+                .and_time(new_time(1, 30, 0))
+                .and_utc();
+            let now = Rc::new(Cell::new(start));
+            let mut controller = Controller::new(
+                &outputs,
+                MockGPIO::new(),
+                Clock::new(
+                    SharedTimeProvider(now.clone()),
+                    Timezone::new("Europe/Warsaw")?,
+                ),
+            )?;
 
-                let result = output.target_state(&time);
-                assert_eq!(result, test_case.expected_state);
+            controller.hold(Hold::new().with_until(start + TimeDelta::seconds(60)))?;
+            assert_eq!(
+                controller.next_change(),
+                Some(TimeDelta::seconds(60) + TimeDelta::milliseconds(1))
+            );
+
+            for (seconds, expected_state) in [
+                (0, OutputState::On),
+                (60, OutputState::On),
+                (61, OutputState::Off),
+            ] {
+                now.set(start + TimeDelta::seconds(seconds));
+                controller.update_outputs_for_time(now.get());
+                assert_eq!(controller.status().remove(0).state, expected_state);
             }
+            assert_eq!(controller.current_hold(), None);
 
             Ok(())
         }
 
         #[test]
-        fn test_cleanup_overrides() -> Result<()> {
-            struct TestCase<'a> {
-                name: &'a str,
-                overrides: Vec<Override>,
-                expected_overrides: Vec<Override>,
-            }
+        fn test_with_interlocks() -> Result<()> {
+            let outputs = OutputDefinitions::new(&[
+                new_output("misting", 1, &[])?,
+                new_output("fan", 2, &[])?,
+            ])?;
+            let interlock = Interlock::new(
+                OutputName::new("misting")?,
+                InterlockKind::Excludes,
+                OutputName::new("fan")?,
+            )?;
+            let unknown = Interlock::new(
+                OutputName::new("misting")?,
+                InterlockKind::Requires,
+                OutputName::new("pump")?,
+            )?;
 
-            let time = new_time(12, 00, 00);
-            let test_cases = vec![
-                TestCase {
-                    name: "future_override",
-                    overrides: vec![Override {
-                        state: OutputState::On,
-                        activation: ScheduledActivation::new(new_time(18, 00, 00), 10)?,
-                        was_triggered: false,
-                    }],
-                    expected_overrides: vec![Override {
-                        state: OutputState::On,
-                        activation: ScheduledActivation::new(new_time(18, 00, 00), 10)?,
-                        was_triggered: false,
-                    }],
-                },
-                TestCase {
-                    name: "past_override",
-                    overrides: vec![
-                        Override {
-                            state: OutputState::On,
-                            activation: ScheduledActivation::new(new_time(18, 00, 00), 10)?,
-                            was_triggered: false,
-                        },
-                        Override {
-                            state: OutputState::On,
-                            activation: ScheduledActivation::new(new_time(6, 00, 00), 10)?,
-                            was_triggered: true,
-                        },
-                    ],
-                    expected_overrides: vec![Override {
-                        state: OutputState::On,
-                        activation: ScheduledActivation::new(new_time(18, 00, 00), 10)?,
-                        was_triggered: false,
-                    }],
-                },
-                TestCase {
-                    name: "current_override",
-                    overrides: vec![
-                        Override {
-                            state: OutputState::On,
-                            activation: ScheduledActivation::new(new_time(18, 00, 00), 10)?,
-                            was_triggered: false,
-                        },
-                        Override {
-                            state: OutputState::On,
-                            activation: ScheduledActivation::new(new_time(11, 59, 55), 10)?,
-                            was_triggered: true,
-                        },
-                    ],
-                    expected_overrides: vec![
-                        Override {
-                            state: OutputState::On,
-                            activation: ScheduledActivation::new(new_time(18, 00, 00), 10)?,
-                            was_triggered: false,
-                        },
-                        Override {
-                            state: OutputState::On,
-                            activation: ScheduledActivation::new(new_time(11, 59, 55), 10)?,
-                            was_triggered: true,
-                        },
-                    ],
-                },
-            ];
+            assert!(outputs
+                .clone()
+                .with_interlocks(std::slice::from_ref(&interlock))
+                .is_ok());
+            assert!(outputs.clone().with_interlocks(&[unknown]).is_err());
 
-            for test_case in &test_cases {
-                println!("test case: {}", test_case.name);
+            let safe_outputs = OutputDefinitions::new(&[
+                new_output("misting", 1, &[])?,
+                new_output("fan", 2, &[])?.with_safe_state(OutputState::On)?,
+            ])?;
+            assert!(safe_outputs
+                .clone()
+                .with_interlocks(std::slice::from_ref(&interlock))
+                .is_ok());
+            assert!(safe_outputs
+                .clone()
+                .with_interlocks(&[Interlock::new(
+                    OutputName::new("fan")?,
+                    InterlockKind::Requires,
+                    OutputName::new("misting")?,
+                )?])
+                .is_err());
+            assert!(safe_outputs
+                .with_interlocks(&[Interlock::new(
+                    OutputName::new("misting")?,
+                    InterlockKind::Requires,
+                    OutputName::new("fan")?,
+                )?])
+                .is_ok());
+            assert!(outputs
+                .with_interlocks(&[interlock.clone(), interlock])
+                .is_err());
+            assert!(Interlock::new(
+                OutputName::new("fan")?,
+                InterlockKind::Excludes,
+                OutputName::new("fan")?,
+            )
+            .is_err());
 
-                let pin_number = PinNumber::new(1)?;
-                let activations = ScheduledActivations::new(&[])?;
-                let definition =
-                    OutputDefinition::new(OutputName::new("output")?, pin_number, activations);
-                let mut output = ControlledOutput {
-                    definition,
-                    pin: MockOutputPin::new(pin_number),
-                    overrides: test_case.overrides.clone(),
-                };
+            Ok(())
+        }
 
-                output.cleanup_overrides(&time);
-                assert_eq!(output.overrides, test_case.expected_overrides);
+        struct FixedTimeProvider(DateTime<Utc>);
+
+        impl CurrentTimeProvider for FixedTimeProvider {
+            fn now(&self) -> DateTime<Utc> {
+                self.0
+            }
+        }
+
+        struct SharedTimeProvider(Rc<Cell<DateTime<Utc>>>);
+
+        impl CurrentTimeProvider for SharedTimeProvider {
+            fn now(&self) -> DateTime<Utc> {
+                self.0.get()
             }
+        }
 
-            Ok(())
+        fn fixed_clock(now: DateTime<Utc>) -> Clock<FixedTimeProvider> {
+            Clock::new(FixedTimeProvider(now), Timezone::Local)
+        }
+
+        fn local(time: &NaiveDateTime) -> DateTime<Utc> {
+            Local.from_local_datetime(time).unwrap().to_utc()
+        }
+
+        fn new_output(
+            name: &str,
+            pin: u8,
+            activations: &[ScheduledActivation],
+        ) -> Result<OutputDefinition> {
+            Ok(OutputDefinition::new(
+                OutputName::new(name)?,
+                PinNumber::new(pin)?,
+                ScheduledActivations::new(activations)?,
+            ))
+        }
+
+        fn states<OP: OutputPin, CTP: CurrentTimeProvider>(
+            controller: &Controller<OP, CTP>,
+        ) -> Vec<(&str, OutputState, bool)> {
+            controller
+                .status()
+                .into_iter()
+                .zip(&controller.outputs)
+                .map(|(status, output)| {
+                    (
+                        output.definition.name.name(),
+                        status.state,
+                        status.blocked_by.is_some(),
+                    )
+                })
+                .collect()
         }
     }
 
     pub fn new_time(hour: u32, min: u32, sec: u32) -> NaiveTime {
         NaiveTime::from_hms_opt(hour, min, sec).expect("from_hms_opt")
     }
+
+    pub fn new_date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).expect("from_ymd_opt")
+    }
+
+    pub fn new_datetime(hour: u32, min: u32, sec: u32) -> NaiveDateTime {
+        new_date(2024, 6, 15).and_time(new_time(hour, min, sec))
+    }
 }
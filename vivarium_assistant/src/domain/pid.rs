@@ -0,0 +1,294 @@
+use crate::errors::Result;
+use anyhow::anyhow;
+use chrono::{DateTime, TimeDelta, Utc};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gains {
+    proportional: f64,
+    integral: f64,
+    derivative: f64,
+}
+
+impl Gains {
+    pub fn new(proportional: f64, integral: f64, derivative: f64) -> Result<Self> {
+        for gain in [proportional, integral, derivative] {
+            if !gain.is_finite() {
+                return Err(anyhow!("WHY CAN'T YOU JUST BE NORMAL?!"));
+            }
+
+            if gain < 0.0 {
+                return Err(anyhow!(
+                    "negative gains make no sense, use the direction of the regulation instead"
+                ));
+            }
+        }
+
+        Ok(Self {
+            proportional,
+            integral,
+            derivative,
+        })
+    }
+
+    pub fn proportional(&self) -> f64 {
+        self.proportional
+    }
+
+    pub fn integral(&self) -> f64 {
+        self.integral
+    }
+
+    pub fn derivative(&self) -> f64 {
+        self.derivative
+    }
+}
+
+// Output of the controller is clamped to be between 0 and 1 so that it can be used as a duty
+// cycle directly.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pid {
+    gains: Gains,
+    integral: f64,
+    previous_error: Option<f64>,
+}
+
+impl Pid {
+    const MIN_OUTPUT: f64 = 0.0;
+    const MAX_OUTPUT: f64 = 1.0;
+
+    pub fn new(gains: Gains) -> Self {
+        Self {
+            gains,
+            integral: 0.0,
+            previous_error: None,
+        }
+    }
+
+    // Positive errors mean that the output should go up.
+    pub fn update(&mut self, error: f64, dt_seconds: f64) -> f64 {
+        let integral = self.integral + error * dt_seconds;
+        let derivative = match self.previous_error {
+            Some(previous_error) if dt_seconds > 0.0 => (error - previous_error) / dt_seconds,
+            _ => 0.0,
+        };
+        self.previous_error = Some(error);
+
+        let output = self.gains.proportional * error
+            + self.gains.integral * integral
+            + self.gains.derivative * derivative;
+        let clamped = output.clamp(Pid::MIN_OUTPUT, Pid::MAX_OUTPUT);
+
+        // Anti-windup, the integral only keeps accumulating while the output isn't saturated or
+        // when the error pulls it back out of saturation. Otherwise heating an enclosure which
+        // can't reach the setpoint for a couple of hours would result in it overheating for the
+        // next couple of hours.
+        let saturated_high = output > Pid::MAX_OUTPUT && error > 0.0;
+        let saturated_low = output < Pid::MIN_OUTPUT && error < 0.0;
+        if !saturated_high && !saturated_low {
+            self.integral = integral;
+        }
+
+        clamped
+    }
+
+    pub fn reset(&mut self) {
+        self.integral = 0.0;
+        self.previous_error = None;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PidSettings {
+    gains: Gains,
+    window_seconds: u32,
+}
+
+impl PidSettings {
+    pub fn new(gains: Gains, window_seconds: u32) -> Result<Self> {
+        if window_seconds == 0 {
+            return Err(anyhow!("the window can't be zero seconds long"));
+        }
+
+        Ok(Self {
+            gains,
+            window_seconds,
+        })
+    }
+
+    pub fn gains(&self) -> Gains {
+        self.gains
+    }
+
+    pub fn window_seconds(&self) -> u32 {
+        self.window_seconds
+    }
+}
+
+// Drives a binary relay with the output of a PID controller by keeping it on for a part of each
+// window e.g. 60% of a 5 minute window means on for 3 minutes and off for 2 minutes. The duty
+// cycle is only recalculated at the start of each window.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimeProportionedPid {
+    window: TimeDelta,
+    pid: Pid,
+    window_start: Option<DateTime<Utc>>,
+    duty_cycle: f64,
+}
+
+impl TimeProportionedPid {
+    pub fn new(settings: PidSettings) -> Self {
+        Self {
+            window: TimeDelta::seconds(settings.window_seconds.into()),
+            pid: Pid::new(settings.gains),
+            window_start: None,
+            duty_cycle: 0.0,
+        }
+    }
+
+    pub fn is_on(&mut self, error: f64, now: &DateTime<Utc>) -> bool {
+        let window_start = match self.window_start {
+            Some(window_start) if *now - window_start < self.window => window_start,
+            Some(window_start) => {
+                let dt = (*now - window_start).num_milliseconds() as f64 / 1000.0;
+                self.duty_cycle = self.pid.update(error, dt);
+                *now
+            }
+            None => {
+                self.duty_cycle = self.pid.update(error, 0.0);
+                *now
+            }
+        };
+        self.window_start = Some(window_start);
+
+        let elapsed = (*now - window_start).num_milliseconds() as f64;
+        elapsed < self.duty_cycle * self.window.num_milliseconds() as f64
+    }
+
+    pub fn duty_cycle(&self) -> f64 {
+        self.duty_cycle
+    }
+
+    pub fn reset(&mut self) {
+        self.pid.reset();
+        self.window_start = None;
+        self.duty_cycle = 0.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    // Enclosure heated by a heat mat which could keep it 15 degrees above the ambient temperature
+    // and which loses heat proportionally to the difference between the temperatures.
+    struct SimulatedEnclosure {
+        temperature: f64,
+    }
+
+    impl SimulatedEnclosure {
+        const AMBIENT: f64 = 18.0;
+        const MAX_HEATING: f64 = 15.0;
+        const TIME_CONSTANT_SECONDS: f64 = 1800.0;
+
+        fn step(&mut self, heating: bool, dt_seconds: f64) {
+            let heating = if heating {
+                SimulatedEnclosure::MAX_HEATING
+            } else {
+                0.0
+            };
+            let loss = self.temperature - SimulatedEnclosure::AMBIENT;
+            self.temperature +=
+                (heating - loss) / SimulatedEnclosure::TIME_CONSTANT_SECONDS * dt_seconds;
+        }
+    }
+
+    #[test]
+    fn test_simulated_enclosure() -> Result<()> {
+        let setpoint = 25.0;
+        let mut enclosure = SimulatedEnclosure {
+            temperature: SimulatedEnclosure::AMBIENT,
+        };
+        let mut pid =
+            TimeProportionedPid::new(PidSettings::new(Gains::new(0.3, 0.0005, 0.0)?, 5 * 60)?);
+
+        let start = NaiveDate::from_ymd_opt(2024, 6, 15)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+            .and_utc();
+        let step = 10;
+        let mut max_temperature: f64 = 0.0;
+        let mut settled = vec![];
+        for i in 0..(12 * 60 * 60 / step) {
+            let now = start + TimeDelta::seconds(i * step);
+            let on = pid.is_on(setpoint - enclosure.temperature, &now);
+            enclosure.step(on, step as f64);
+
+            max_temperature = max_temperature.max(enclosure.temperature);
+            if now - start > TimeDelta::hours(6) {
+                settled.push(enclosure.temperature);
+            }
+        }
+
+        // the error is only sampled at the start of each window which is when the enclosure is
+        // the coldest so it ends up being slightly warmer on average
+        let average = settled.iter().sum::<f64>() / settled.len() as f64;
+        assert!(
+            (average - setpoint).abs() < 0.5,
+            "average temperature was {average}"
+        );
+        for temperature in &settled {
+            assert!(
+                (temperature - setpoint).abs() < 1.0,
+                "temperature was {temperature}"
+            );
+        }
+        assert!(
+            max_temperature < setpoint + 1.5,
+            "overshot to {max_temperature}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_anti_windup() -> Result<()> {
+        let mut pid = Pid::new(Gains::new(0.3, 0.0005, 0.0)?);
+
+        // the heater can't keep up for hours
+        for _ in 0..1000 {
+            assert_eq!(pid.update(10.0, 60.0), 1.0);
+        }
+
+        // it should back off as soon as the setpoint is exceeded
+        assert_eq!(pid.update(-1.0, 60.0), 0.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_time_proportioning() -> Result<()> {
+        let mut pid = TimeProportionedPid::new(PidSettings::new(Gains::new(0.1, 0.0, 0.0)?, 100)?);
+        let start = NaiveDate::from_ymd_opt(2024, 6, 15)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+            .and_utc();
+
+        let on_seconds = (0..100)
+            .filter(|i| pid.is_on(5.0, &(start + TimeDelta::seconds(*i))))
+            .count();
+        assert_eq!(on_seconds, 50);
+        assert!((pid.duty_cycle() - 0.5).abs() < 0.0001);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_gains() {
+        assert!(Gains::new(1.0, 0.1, 0.01).is_ok());
+        assert!(Gains::new(-1.0, 0.1, 0.01).is_err());
+        assert!(Gains::new(1.0, f64::NAN, 0.01).is_err());
+    }
+}
@@ -0,0 +1,524 @@
+use super::pid::PidSettings;
+use super::sensors::SensorName;
+use crate::errors::Result;
+use anyhow::anyhow;
+use chrono::{DateTime, NaiveTime, TimeDelta, Utc};
+use std::collections::HashMap;
+use std::fmt::Display;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Quantity {
+    Temperature,
+    Humidity,
+    WaterLevel,
+}
+
+impl Display for Quantity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Quantity::Temperature => write!(f, "temperature"),
+            Quantity::Humidity => write!(f, "humidity"),
+            Quantity::WaterLevel => write!(f, "water level"),
+        }
+    }
+}
+
+// Whether turning the output on raises the measured quantity (e.g. a heat mat or a mister) or
+// lowers it (e.g. a fan).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Raise,
+    Lower,
+}
+
+// When raising the output is turned on once the reading drops below the setpoint and turned off
+// once it reaches the setpoint plus hysteresis. When lowering it's the other way around.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Setpoint {
+    value: f32,
+    hysteresis: f32,
+}
+
+impl Setpoint {
+    pub fn new(value: f32, hysteresis: f32) -> Result<Self> {
+        if !value.is_finite() || !hysteresis.is_finite() {
+            return Err(anyhow!("WHY CAN'T YOU JUST BE NORMAL?!"));
+        }
+
+        if hysteresis < 0.0 {
+            return Err(anyhow!("hysteresis can't be negative"));
+        }
+
+        Ok(Self { value, hysteresis })
+    }
+
+    pub fn value(&self) -> f32 {
+        self.value
+    }
+
+    pub fn hysteresis(&self) -> f32 {
+        self.hysteresis
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct SetpointWindow {
+    from: NaiveTime,
+    until: NaiveTime,
+    setpoint: Setpoint,
+}
+
+impl SetpointWindow {
+    fn contains(&self, time: &NaiveTime) -> bool {
+        if self.from < self.until {
+            time >= &self.from && time < &self.until
+        } else {
+            time >= &self.from || time < &self.until
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Regulation {
+    sensor: SensorName,
+    quantity: Quantity,
+    direction: Direction,
+    setpoint: Setpoint,
+    windows: Vec<SetpointWindow>,
+    pid: Option<PidSettings>,
+}
+
+impl Regulation {
+    pub fn new(
+        sensor: SensorName,
+        quantity: Quantity,
+        direction: Direction,
+        setpoint: Setpoint,
+    ) -> Self {
+        Self {
+            sensor,
+            quantity,
+            direction,
+            setpoint,
+            windows: vec![],
+            pid: None,
+        }
+    }
+
+    // Drives the output with a PID controller instead of simply switching it at the setpoint, the
+    // hysteresis is then ignored.
+    pub fn with_pid(self, pid: PidSettings) -> Self {
+        Self {
+            pid: Some(pid),
+            ..self
+        }
+    }
+
+    pub fn pid(&self) -> Option<PidSettings> {
+        self.pid
+    }
+
+    // Uses a different setpoint between the given times e.g. during the night. The window may wrap
+    // around midnight.
+    pub fn with_window(
+        self,
+        from: NaiveTime,
+        until: NaiveTime,
+        setpoint: Setpoint,
+    ) -> Result<Self> {
+        if from == until {
+            return Err(anyhow!(
+                "a setpoint window can't start and end at the same time"
+            ));
+        }
+
+        let window = SetpointWindow {
+            from,
+            until,
+            setpoint,
+        };
+        for other in &self.windows {
+            if other.contains(&window.from) || window.contains(&other.from) {
+                return Err(anyhow!("setpoint windows can't overlap"));
+            }
+        }
+
+        let mut windows = self.windows;
+        windows.push(window);
+        Ok(Self { windows, ..self })
+    }
+
+    pub fn sensor(&self) -> &SensorName {
+        &self.sensor
+    }
+
+    pub fn quantity(&self) -> Quantity {
+        self.quantity
+    }
+
+    pub fn setpoint_at(&self, time: &NaiveTime) -> Setpoint {
+        self.windows
+            .iter()
+            .find(|v| v.contains(time))
+            .map(|v| v.setpoint)
+            .unwrap_or(self.setpoint)
+    }
+
+    // Positive when the output should be on.
+    pub fn error(&self, reading: f32, time: &NaiveTime) -> f64 {
+        let setpoint = self.setpoint_at(time);
+        match self.direction {
+            Direction::Raise => (setpoint.value - reading).into(),
+            Direction::Lower => (reading - setpoint.value).into(),
+        }
+    }
+
+    // Keeps the previous state while the reading is within the hysteresis.
+    pub fn is_on(&self, reading: f32, was_on: bool, time: &NaiveTime) -> bool {
+        let setpoint = self.setpoint_at(time);
+        match self.direction {
+            Direction::Raise => {
+                if reading < setpoint.value {
+                    true
+                } else if reading >= setpoint.value + setpoint.hysteresis {
+                    false
+                } else {
+                    was_on
+                }
+            }
+            Direction::Lower => {
+                if reading > setpoint.value {
+                    true
+                } else if reading <= setpoint.value - setpoint.hysteresis {
+                    false
+                } else {
+                    was_on
+                }
+            }
+        }
+    }
+}
+
+// Keeps pumps and misters off while the water level in the reservoir they draw from is below
+// min_level. They are only allowed to run again once it rises above min_level plus hysteresis so
+// that the pump doesn't flap around the threshold. Levels are in percent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DryRunProtection {
+    sensor: SensorName,
+    min_level: f32,
+    hysteresis: f32,
+}
+
+impl DryRunProtection {
+    pub fn new(sensor: SensorName, min_level: f32, hysteresis: f32) -> Result<Self> {
+        if !min_level.is_finite() || !hysteresis.is_finite() {
+            return Err(anyhow!("WHY CAN'T YOU JUST BE NORMAL?!"));
+        }
+
+        if !(0.0..100.0).contains(&min_level) {
+            return Err(anyhow!("minimum water level must be between 0% and 100%"));
+        }
+
+        if hysteresis < 0.0 {
+            return Err(anyhow!("hysteresis can't be negative"));
+        }
+
+        if min_level + hysteresis > 100.0 {
+            return Err(anyhow!(
+                "the water level would have to go above 100% for the output to run again"
+            ));
+        }
+
+        Ok(Self {
+            sensor,
+            min_level,
+            hysteresis,
+        })
+    }
+
+    pub fn sensor(&self) -> &SensorName {
+        &self.sensor
+    }
+
+    pub fn min_level(&self) -> f32 {
+        self.min_level
+    }
+
+    pub fn hysteresis(&self) -> f32 {
+        self.hysteresis
+    }
+
+    // Not knowing the water level counts as running dry.
+    pub fn is_running_dry(&self, level: Option<f32>, was_running_dry: bool) -> bool {
+        match level {
+            Some(level) if level < self.min_level => true,
+            Some(level) if level >= self.min_level + self.hysteresis => false,
+            Some(_) => was_running_dry,
+            None => true,
+        }
+    }
+}
+
+// Latest sensor readings. Old readings are ignored so that a broken sensor doesn't keep a heater
+// on forever.
+#[derive(Debug, Default)]
+pub struct Readings {
+    latest: HashMap<(SensorName, Quantity), (f32, DateTime<Utc>)>,
+}
+
+impl Readings {
+    const MAX_AGE: TimeDelta = TimeDelta::minutes(5);
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn put(
+        &mut self,
+        sensor: &SensorName,
+        quantity: Quantity,
+        value: f32,
+        time: DateTime<Utc>,
+    ) {
+        self.latest
+            .insert((sensor.clone(), quantity), (value, time));
+    }
+
+    pub fn get(&self, sensor: &SensorName, quantity: Quantity, now: &DateTime<Utc>) -> Option<f32> {
+        let (value, time) = self.latest.get(&(sensor.clone(), quantity))?;
+        if *now - *time > Readings::MAX_AGE {
+            return None;
+        }
+        Some(*value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn test_is_on() -> Result<()> {
+        struct TestCase<'a> {
+            name: &'a str,
+            direction: Direction,
+            reading: f32,
+            was_on: bool,
+            time: NaiveTime,
+            expected_on: bool,
+        }
+
+        let day = new_time(12, 0, 0);
+        let night = new_time(23, 0, 0);
+
+        let test_cases = vec![
+            TestCase {
+                name: "raise_below",
+                direction: Direction::Raise,
+                reading: 74.0,
+                was_on: false,
+                time: day,
+                expected_on: true,
+            },
+            TestCase {
+                name: "raise_within_hysteresis_off",
+                direction: Direction::Raise,
+                reading: 80.0,
+                was_on: false,
+                time: day,
+                expected_on: false,
+            },
+            TestCase {
+                name: "raise_within_hysteresis_on",
+                direction: Direction::Raise,
+                reading: 80.0,
+                was_on: true,
+                time: day,
+                expected_on: true,
+            },
+            TestCase {
+                name: "raise_above",
+                direction: Direction::Raise,
+                reading: 85.0,
+                was_on: true,
+                time: day,
+                expected_on: false,
+            },
+            TestCase {
+                name: "raise_night_setpoint",
+                direction: Direction::Raise,
+                reading: 85.0,
+                was_on: false,
+                time: night,
+                expected_on: true,
+            },
+            TestCase {
+                name: "lower_above",
+                direction: Direction::Lower,
+                reading: 76.0,
+                was_on: false,
+                time: day,
+                expected_on: true,
+            },
+            TestCase {
+                name: "lower_within_hysteresis_on",
+                direction: Direction::Lower,
+                reading: 70.0,
+                was_on: true,
+                time: day,
+                expected_on: true,
+            },
+            TestCase {
+                name: "lower_below",
+                direction: Direction::Lower,
+                reading: 65.0,
+                was_on: true,
+                time: day,
+                expected_on: false,
+            },
+        ];
+
+        for test_case in &test_cases {
+            println!("test case: {}", test_case.name);
+
+            let regulation = Regulation::new(
+                SensorName::new("sensor")?,
+                Quantity::Humidity,
+                test_case.direction,
+                Setpoint::new(75.0, 10.0)?,
+            )
+            .with_window(
+                new_time(20, 0, 0),
+                new_time(8, 0, 0),
+                Setpoint::new(90.0, 5.0)?,
+            )?;
+
+            assert_eq!(
+                regulation.is_on(test_case.reading, test_case.was_on, &test_case.time),
+                test_case.expected_on
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_with_window() -> Result<()> {
+        let regulation = Regulation::new(
+            SensorName::new("sensor")?,
+            Quantity::Temperature,
+            Direction::Raise,
+            Setpoint::new(22.0, 1.0)?,
+        );
+        let setpoint = Setpoint::new(20.0, 1.0)?;
+
+        let regulation = regulation.with_window(new_time(20, 0, 0), new_time(8, 0, 0), setpoint)?;
+        assert!(regulation
+            .clone()
+            .with_window(new_time(7, 0, 0), new_time(9, 0, 0), setpoint)
+            .is_err());
+        assert!(regulation
+            .clone()
+            .with_window(new_time(10, 0, 0), new_time(10, 0, 0), setpoint)
+            .is_err());
+        assert!(regulation
+            .with_window(new_time(8, 0, 0), new_time(9, 0, 0), setpoint)
+            .is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_readings() -> Result<()> {
+        let sensor = SensorName::new("sensor")?;
+        let now = NaiveDate::from_ymd_opt(2024, 6, 15)
+            .unwrap()
+            .and_time(new_time(12, 0, 0))
+            .and_utc();
+
+        let mut readings = Readings::new();
+        assert_eq!(readings.get(&sensor, Quantity::Humidity, &now), None);
+
+        readings.put(&sensor, Quantity::Humidity, 80.0, now);
+        assert_eq!(readings.get(&sensor, Quantity::Humidity, &now), Some(80.0));
+        assert_eq!(readings.get(&sensor, Quantity::Temperature, &now), None);
+        assert_eq!(
+            readings.get(&sensor, Quantity::Humidity, &(now + TimeDelta::minutes(10))),
+            None
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_running_dry() -> Result<()> {
+        struct TestCase<'a> {
+            name: &'a str,
+            level: Option<f32>,
+            was_running_dry: bool,
+            expected_running_dry: bool,
+        }
+
+        let test_cases = vec![
+            TestCase {
+                name: "no_readings",
+                level: None,
+                was_running_dry: false,
+                expected_running_dry: true,
+            },
+            TestCase {
+                name: "below",
+                level: Some(19.0),
+                was_running_dry: false,
+                expected_running_dry: true,
+            },
+            TestCase {
+                name: "within_hysteresis_was_running_dry",
+                level: Some(22.0),
+                was_running_dry: true,
+                expected_running_dry: true,
+            },
+            TestCase {
+                name: "within_hysteresis_was_not_running_dry",
+                level: Some(22.0),
+                was_running_dry: false,
+                expected_running_dry: false,
+            },
+            TestCase {
+                name: "above",
+                level: Some(25.0),
+                was_running_dry: true,
+                expected_running_dry: false,
+            },
+        ];
+
+        for test_case in &test_cases {
+            println!("test case: {}", test_case.name);
+
+            let protection = DryRunProtection::new(SensorName::new("sensor")?, 20.0, 5.0)?;
+            assert_eq!(
+                protection.is_running_dry(test_case.level, test_case.was_running_dry),
+                test_case.expected_running_dry
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_new_dry_run_protection() -> Result<()> {
+        let sensor = SensorName::new("sensor")?;
+        assert!(DryRunProtection::new(sensor.clone(), 20.0, 5.0).is_ok());
+        assert!(DryRunProtection::new(sensor.clone(), -1.0, 5.0).is_err());
+        assert!(DryRunProtection::new(sensor.clone(), 100.0, 0.0).is_err());
+        assert!(DryRunProtection::new(sensor.clone(), 20.0, -5.0).is_err());
+        assert!(DryRunProtection::new(sensor.clone(), 90.0, 20.0).is_err());
+        assert!(DryRunProtection::new(sensor, f32::NAN, 5.0).is_err());
+        Ok(())
+    }
+
+    fn new_time(hour: u32, min: u32, sec: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, min, sec).expect("from_hms_opt")
+    }
+}
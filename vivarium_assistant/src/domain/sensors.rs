@@ -143,7 +143,7 @@ impl Display for WaterLevel {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
 pub struct SensorName {
     name: String,
 }
@@ -0,0 +1,170 @@
+use crate::errors::Result;
+use anyhow::anyhow;
+use chrono::{DateTime, Datelike, NaiveDate, TimeDelta, Utc};
+use std::f64::consts::PI;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Location {
+    latitude: f64,
+    longitude: f64,
+}
+
+impl Location {
+    pub fn new(latitude: f64, longitude: f64) -> Result<Self> {
+        if !latitude.is_finite() || !longitude.is_finite() {
+            return Err(anyhow!("WHY CAN'T YOU JUST BE NORMAL?!"));
+        }
+
+        if !(-90.0..=90.0).contains(&latitude) {
+            return Err(anyhow!("latitude must be between -90 and 90 degrees"));
+        }
+
+        if !(-180.0..=180.0).contains(&longitude) {
+            return Err(anyhow!("longitude must be between -180 and 180 degrees"));
+        }
+
+        Ok(Self {
+            latitude,
+            longitude,
+        })
+    }
+
+    pub fn latitude(&self) -> f64 {
+        self.latitude
+    }
+
+    pub fn longitude(&self) -> f64 {
+        self.longitude
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SolarEvent {
+    Sunrise,
+    Sunset,
+}
+
+// Based on the NOAA "General Solar Position Calculations" document which is good to about a minute
+// and requires no lookups whatsoever. In polar regions during days on which the sun doesn't rise
+// or set the hour angle gets clamped so we end up with solar noon or solar midnight which is as
+// good of an answer as any.
+pub fn calculate(event: SolarEvent, date: &NaiveDate, location: &Location) -> DateTime<Utc> {
+    let days_in_year = if date.leap_year() { 366.0 } else { 365.0 };
+    let gamma = 2.0 * PI / days_in_year * (date.ordinal0() as f64);
+
+    let equation_of_time_minutes = 229.18
+        * (0.000075 + 0.001868 * gamma.cos()
+            - 0.032077 * gamma.sin()
+            - 0.014615 * (2.0 * gamma).cos()
+            - 0.040849 * (2.0 * gamma).sin());
+
+    let declination = 0.006918 - 0.399912 * gamma.cos() + 0.070257 * gamma.sin()
+        - 0.006758 * (2.0 * gamma).cos()
+        + 0.000907 * (2.0 * gamma).sin()
+        - 0.002697 * (3.0 * gamma).cos()
+        + 0.00148 * (3.0 * gamma).sin();
+
+    let latitude = location.latitude.to_radians();
+    let zenith = 90.833_f64.to_radians(); // accounts for refraction and the size of the disc
+    let cos_hour_angle =
+        zenith.cos() / (latitude.cos() * declination.cos()) - latitude.tan() * declination.tan();
+    let hour_angle = cos_hour_angle.clamp(-1.0, 1.0).acos().to_degrees();
+
+    let minutes_after_midnight = match event {
+        SolarEvent::Sunrise => 720.0 - 4.0 * (location.longitude + hour_angle),
+        SolarEvent::Sunset => 720.0 - 4.0 * (location.longitude - hour_angle),
+    } - equation_of_time_minutes;
+
+    date.and_hms_opt(0, 0, 0).unwrap().and_utc()
+        + TimeDelta::seconds((minutes_after_midnight * 60.0).round() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDateTime;
+
+    #[test]
+    fn test_calculate() -> Result<()> {
+        struct TestCase<'a> {
+            name: &'a str,
+            event: SolarEvent,
+            date: NaiveDate,
+            location: Location,
+            expected: NaiveDateTime,
+        }
+
+        let london = Location::new(51.5074, -0.1278)?;
+        let singapore = Location::new(1.3521, 103.8198)?;
+
+        let test_cases = vec![
+            TestCase {
+                name: "london_summer_sunrise",
+                event: SolarEvent::Sunrise,
+                date: new_date(2024, 6, 21),
+                location: london,
+                expected: new_datetime(2024, 6, 21, 3, 43),
+            },
+            TestCase {
+                name: "london_summer_sunset",
+                event: SolarEvent::Sunset,
+                date: new_date(2024, 6, 21),
+                location: london,
+                expected: new_datetime(2024, 6, 21, 20, 21),
+            },
+            TestCase {
+                name: "london_winter_sunrise",
+                event: SolarEvent::Sunrise,
+                date: new_date(2024, 12, 21),
+                location: london,
+                expected: new_datetime(2024, 12, 21, 8, 4),
+            },
+            TestCase {
+                name: "london_winter_sunset",
+                event: SolarEvent::Sunset,
+                date: new_date(2024, 12, 21),
+                location: london,
+                expected: new_datetime(2024, 12, 21, 15, 53),
+            },
+            TestCase {
+                name: "singapore_sunrise_is_on_the_previous_day_in_utc",
+                event: SolarEvent::Sunrise,
+                date: new_date(2024, 3, 20),
+                location: singapore,
+                expected: new_datetime(2024, 3, 19, 23, 8),
+            },
+        ];
+
+        for test_case in &test_cases {
+            println!("test case: {}", test_case.name);
+
+            let result = calculate(test_case.event, &test_case.date, &test_case.location);
+            let difference = (result.naive_utc() - test_case.expected).abs();
+            assert!(
+                difference <= TimeDelta::minutes(2),
+                "got {result} expected {}",
+                test_case.expected
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_location() {
+        assert!(Location::new(52.0, 21.0).is_ok());
+        assert!(Location::new(91.0, 21.0).is_err());
+        assert!(Location::new(52.0, -181.0).is_err());
+        assert!(Location::new(f64::NAN, 21.0).is_err());
+    }
+
+    fn new_date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).expect("from_ymd_opt")
+    }
+
+    fn new_datetime(year: i32, month: u32, day: u32, hour: u32, min: u32) -> NaiveDateTime {
+        new_date(year, month, day)
+            .and_hms_opt(hour, min, 0)
+            .expect("and_hms_opt")
+    }
+}
@@ -0,0 +1,277 @@
+use crate::errors::Result;
+use anyhow::anyhow;
+use chrono::{DateTime, FixedOffset, LocalResult, NaiveDateTime, Offset, TimeDelta, TimeZone, Utc};
+use chrono_tz::Tz;
+
+pub trait CurrentTimeProvider {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+// Schedules are expressed using the time shown by the clock on the wall so they only make sense
+// in a specific timezone. Unless one is configured the timezone of the system is used.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Timezone {
+    #[default]
+    Local,
+    Named(Tz),
+}
+
+impl Timezone {
+    pub fn new(name: &str) -> Result<Self> {
+        name.parse::<Tz>().map(Timezone::Named).map_err(|_| {
+            anyhow!("unknown timezone '{name}', it should look like e.g. 'Europe/Warsaw'")
+        })
+    }
+
+    pub fn local(&self, time: &DateTime<Utc>) -> NaiveDateTime {
+        match self {
+            Timezone::Local => time.with_timezone(&chrono::Local).naive_local(),
+            Timezone::Named(tz) => time.with_timezone(tz).naive_local(),
+        }
+    }
+
+    // Same as local but keeps the UTC offset e.g. to show the time to the user.
+    pub fn fixed_offset(&self, time: &DateTime<Utc>) -> DateTime<FixedOffset> {
+        match self {
+            Timezone::Local => time.with_timezone(&chrono::Local).fixed_offset(),
+            Timezone::Named(tz) => time.with_timezone(tz).fixed_offset(),
+        }
+    }
+
+    // Same as local but never goes backwards. When the clocks go back the wall clock stands still
+    // at the last moment before the change until the repeated hour is over so that nothing
+    // happens twice. Activations overlapping the repeated hour are stretched by it.
+    pub fn wall_clock(&self, time: &DateTime<Utc>) -> NaiveDateTime {
+        match self {
+            Timezone::Local => wall_clock(&chrono::Local, time),
+            Timezone::Named(tz) => wall_clock(tz, time),
+        }
+    }
+
+    // Times which are skipped when the clocks go forward are interpreted using the offset from
+    // before the change e.g. 02:30 becomes 03:30 if the clocks jump from 02:00 to 03:00. Times
+    // which happen twice when the clocks go back refer to the first time the clock shows them.
+    pub fn resolve(&self, time: &NaiveDateTime) -> DateTime<FixedOffset> {
+        match self {
+            Timezone::Local => resolve(&chrono::Local, time),
+            Timezone::Named(tz) => resolve(tz, time),
+        }
+    }
+
+    pub fn skip_gap(&self, time: &NaiveDateTime) -> NaiveDateTime {
+        self.resolve(time).naive_local()
+    }
+}
+
+fn wall_clock<TZ: TimeZone>(tz: &TZ, time: &DateTime<Utc>) -> NaiveDateTime {
+    let local = time.with_timezone(tz).naive_local();
+    let LocalResult::Ambiguous(first, _) = tz.from_local_datetime(&local) else {
+        return local;
+    };
+    if first.to_utc() == *time {
+        return local;
+    }
+
+    // Offsets only ever change on a full second so looking for the change with a precision of a
+    // second is enough.
+    let offset = |timestamp: i64| {
+        DateTime::from_timestamp(timestamp, 0)
+            .map(|v| tz.offset_from_utc_datetime(&v.naive_utc()).fix())
+    };
+    let before = offset(first.timestamp());
+    let (mut lo, mut hi) = (first.timestamp(), time.timestamp());
+    while hi - lo > 1 {
+        let mid = lo + (hi - lo) / 2;
+        if offset(mid) == before {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+
+    match DateTime::from_timestamp(hi, 0) {
+        Some(change) => (change - TimeDelta::milliseconds(1))
+            .with_timezone(tz)
+            .naive_local(),
+        None => local,
+    }
+}
+
+fn resolve<TZ: TimeZone>(tz: &TZ, time: &NaiveDateTime) -> DateTime<FixedOffset> {
+    match tz.from_local_datetime(time) {
+        LocalResult::Single(v) | LocalResult::Ambiguous(v, _) => v.fixed_offset(),
+        LocalResult::None => {
+            // gaps are much shorter than a day
+            let before = tz
+                .offset_from_utc_datetime(&(*time - TimeDelta::days(1)))
+                .fix();
+            let utc = *time - TimeDelta::seconds(before.local_minus_utc().into());
+            tz.from_utc_datetime(&utc).fixed_offset()
+        }
+    }
+}
+
+// All components have to get the current time from the same clock as otherwise e.g. an override
+// could be scheduled using a different timezone than the one the schedule is evaluated in.
+#[derive(Clone)]
+pub struct Clock<CTP> {
+    current_time_provider: CTP,
+    timezone: Timezone,
+}
+
+impl<CTP: CurrentTimeProvider> Clock<CTP> {
+    pub fn new(current_time_provider: CTP, timezone: Timezone) -> Self {
+        Self {
+            current_time_provider,
+            timezone,
+        }
+    }
+
+    pub fn now(&self) -> NaiveDateTime {
+        self.timezone.wall_clock(&self.now_utc())
+    }
+
+    pub fn now_utc(&self) -> DateTime<Utc> {
+        self.current_time_provider.now()
+    }
+
+    pub fn timezone(&self) -> Timezone {
+        self.timezone
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn test_wall_clock() -> Result<()> {
+        struct TestCase<'a> {
+            name: &'a str,
+            time: DateTime<Utc>,
+            expected_wall_clock: NaiveDateTime,
+        }
+
+        // in 2024 the clocks in Poland went forward from 02:00 to 03:00 on the 31st of March and
+        // back from 03:00 to 02:00 on the 27th of October, both at 01:00 UTC
+        let test_cases = vec![
+            TestCase {
+                name: "winter",
+                time: new_utc(1, 15, 12, 0, 0),
+                expected_wall_clock: new_datetime(1, 15, 13, 0, 0, 0),
+            },
+            TestCase {
+                name: "summer",
+                time: new_utc(7, 15, 12, 0, 0),
+                expected_wall_clock: new_datetime(7, 15, 14, 0, 0, 0),
+            },
+            TestCase {
+                name: "before_clocks_go_forward",
+                time: new_utc(3, 31, 0, 59, 59),
+                expected_wall_clock: new_datetime(3, 31, 1, 59, 59, 0),
+            },
+            TestCase {
+                name: "clocks_go_forward",
+                time: new_utc(3, 31, 1, 0, 0),
+                expected_wall_clock: new_datetime(3, 31, 3, 0, 0, 0),
+            },
+            TestCase {
+                name: "repeated_hour_first_time",
+                time: new_utc(10, 27, 0, 30, 0),
+                expected_wall_clock: new_datetime(10, 27, 2, 30, 0, 0),
+            },
+            TestCase {
+                name: "clocks_go_back",
+                time: new_utc(10, 27, 1, 0, 0),
+                expected_wall_clock: new_datetime(10, 27, 2, 59, 59, 999),
+            },
+            TestCase {
+                name: "repeated_hour_second_time",
+                time: new_utc(10, 27, 1, 30, 0),
+                expected_wall_clock: new_datetime(10, 27, 2, 59, 59, 999),
+            },
+            TestCase {
+                name: "after_repeated_hour",
+                time: new_utc(10, 27, 2, 0, 0),
+                expected_wall_clock: new_datetime(10, 27, 3, 0, 0, 0),
+            },
+        ];
+
+        let timezone = Timezone::new("Europe/Warsaw")?;
+        for test_case in &test_cases {
+            println!("test case: {}", test_case.name);
+            assert_eq!(
+                timezone.wall_clock(&test_case.time),
+                test_case.expected_wall_clock
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve() -> Result<()> {
+        struct TestCase<'a> {
+            name: &'a str,
+            time: NaiveDateTime,
+            expected_utc: DateTime<Utc>,
+        }
+
+        let test_cases = vec![
+            TestCase {
+                name: "normal",
+                time: new_datetime(7, 15, 14, 0, 0, 0),
+                expected_utc: new_utc(7, 15, 12, 0, 0),
+            },
+            TestCase {
+                name: "skipped",
+                time: new_datetime(3, 31, 2, 30, 0, 0),
+                expected_utc: new_utc(3, 31, 1, 30, 0),
+            },
+            TestCase {
+                name: "repeated",
+                time: new_datetime(10, 27, 2, 30, 0, 0),
+                expected_utc: new_utc(10, 27, 0, 30, 0),
+            },
+        ];
+
+        let timezone = Timezone::new("Europe/Warsaw")?;
+        for test_case in &test_cases {
+            println!("test case: {}", test_case.name);
+            assert_eq!(timezone.resolve(&test_case.time), test_case.expected_utc);
+        }
+
+        assert_eq!(
+            timezone.skip_gap(&new_datetime(3, 31, 2, 30, 0, 0)),
+            new_datetime(3, 31, 3, 30, 0, 0)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_new() {
+        assert!(Timezone::new("Europe/Warsaw").is_ok());
+        assert!(Timezone::new("UTC").is_ok());
+        assert!(Timezone::new("Mars/Olympus_Mons").is_err());
+    }
+
+    fn new_utc(month: u32, day: u32, hour: u32, min: u32, sec: u32) -> DateTime<Utc> {
+        new_datetime(month, day, hour, min, sec, 0).and_utc()
+    }
+
+    fn new_datetime(
+        month: u32,
+        day: u32,
+        hour: u32,
+        min: u32,
+        sec: u32,
+        milli: u32,
+    ) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, month, day)
+            .unwrap()
+            .and_hms_milli_opt(hour, min, sec, milli)
+            .unwrap()
+    }
+}
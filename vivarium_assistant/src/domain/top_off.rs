@@ -0,0 +1,396 @@
+use super::sensors::SensorName;
+use crate::errors::Result;
+use anyhow::anyhow;
+use chrono::{DateTime, TimeDelta, Utc};
+use std::fmt::Display;
+
+// Runs a refill pump once the water level drops below low_mark until it reaches high_mark. Levels
+// are in percent. Since the water level readings are smoothed the level reported during filling
+// lags behind the real one which has to be taken into account when picking max_fill_seconds and
+// the minimum rise.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopOff {
+    sensor: SensorName,
+    low_mark: f32,
+    high_mark: f32,
+    max_fill_seconds: u32,
+    min_rise: Option<MinRise>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct MinRise {
+    rise: f32,
+    within_seconds: u32,
+}
+
+impl TopOff {
+    const SECONDS_IN_A_DAY: u32 = 24 * 60 * 60;
+
+    pub fn new(
+        sensor: SensorName,
+        low_mark: f32,
+        high_mark: f32,
+        max_fill_seconds: u32,
+    ) -> Result<Self> {
+        if !low_mark.is_finite() || !high_mark.is_finite() {
+            return Err(anyhow!("WHY CAN'T YOU JUST BE NORMAL?!"));
+        }
+
+        if !(0.0..=100.0).contains(&low_mark) || !(0.0..=100.0).contains(&high_mark) {
+            return Err(anyhow!("water level marks must be between 0% and 100%"));
+        }
+
+        if low_mark >= high_mark {
+            return Err(anyhow!("the low mark must be below the high mark"));
+        }
+
+        if max_fill_seconds == 0 {
+            return Err(anyhow!("the pump has to be allowed to run for a bit"));
+        }
+
+        if max_fill_seconds >= TopOff::SECONDS_IN_A_DAY {
+            return Err(anyhow!(
+                "filling for a whole day or more isn't topping off, check for leaks"
+            ));
+        }
+
+        Ok(Self {
+            sensor,
+            low_mark,
+            high_mark,
+            max_fill_seconds,
+            min_rise: None,
+        })
+    }
+
+    // Gives up on filling if the level doesn't rise by at least the given amount within the given
+    // time e.g. because the source container is empty or the sensor is stuck.
+    pub fn with_min_rise(self, rise: f32, within_seconds: u32) -> Result<Self> {
+        if !rise.is_finite() {
+            return Err(anyhow!("WHY CAN'T YOU JUST BE NORMAL?!"));
+        }
+
+        if rise <= 0.0 {
+            return Err(anyhow!("the minimum rise must be positive"));
+        }
+
+        if rise > self.high_mark - self.low_mark {
+            return Err(anyhow!(
+                "the minimum rise can't be larger than the difference between the marks"
+            ));
+        }
+
+        if within_seconds == 0 || within_seconds >= self.max_fill_seconds {
+            return Err(anyhow!(
+                "the rise has to be checked before the maximum fill time is reached"
+            ));
+        }
+
+        Ok(Self {
+            min_rise: Some(MinRise {
+                rise,
+                within_seconds,
+            }),
+            ..self
+        })
+    }
+
+    pub fn sensor(&self) -> &SensorName {
+        &self.sensor
+    }
+
+    pub fn low_mark(&self) -> f32 {
+        self.low_mark
+    }
+
+    pub fn high_mark(&self) -> f32 {
+        self.high_mark
+    }
+
+    pub fn max_fill_seconds(&self) -> u32 {
+        self.max_fill_seconds
+    }
+
+    // Failures are latched until the level is seen at or above the high mark which means that
+    // someone topped off the water and presumably fixed whatever went wrong. Readings going
+    // missing during filling count as a failure as otherwise a flaky sensor could keep restarting
+    // the filling forever. The fill time only counts while the pump was actually running since the
+    // last update, e.g. an interlock can keep it off for a while.
+    pub fn update(
+        &self,
+        state: TopOffState,
+        level: Option<f32>,
+        pumping: bool,
+        now: &DateTime<Utc>,
+    ) -> TopOffState {
+        match state {
+            TopOffState::Idle => match level {
+                Some(level) if level < self.low_mark => TopOffState::Filling {
+                    filling_for: TimeDelta::zero(),
+                    checked: *now,
+                    level,
+                },
+                _ => TopOffState::Idle,
+            },
+            TopOffState::Filling {
+                filling_for,
+                checked,
+                level: start_level,
+            } => {
+                let Some(level) = level else {
+                    return TopOffState::Failed(TopOffFailure::NoReadings);
+                };
+
+                if level >= self.high_mark {
+                    return TopOffState::Idle;
+                }
+
+                let filling_for = if pumping {
+                    filling_for + (*now - checked).max(TimeDelta::zero())
+                } else {
+                    filling_for
+                };
+                if filling_for >= TimeDelta::seconds(self.max_fill_seconds.into()) {
+                    return TopOffState::Failed(TopOffFailure::MaxFillTime);
+                }
+
+                if let Some(min_rise) = &self.min_rise {
+                    if filling_for >= TimeDelta::seconds(min_rise.within_seconds.into())
+                        && level - start_level < min_rise.rise
+                    {
+                        return TopOffState::Failed(TopOffFailure::NoRise);
+                    }
+                }
+
+                TopOffState::Filling {
+                    filling_for,
+                    checked: *now,
+                    level: start_level,
+                }
+            }
+            TopOffState::Failed(_) => match level {
+                Some(level) if level >= self.high_mark => TopOffState::Idle,
+                _ => state,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum TopOffState {
+    #[default]
+    Idle,
+    Filling {
+        filling_for: TimeDelta,
+        checked: DateTime<Utc>,
+        level: f32,
+    },
+    Failed(TopOffFailure),
+}
+
+impl TopOffState {
+    pub fn is_filling(&self) -> bool {
+        matches!(self, TopOffState::Filling { .. })
+    }
+
+    pub fn failure(&self) -> Option<TopOffFailure> {
+        match self {
+            TopOffState::Failed(failure) => Some(*failure),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TopOffFailure {
+    MaxFillTime,
+    NoRise,
+    NoReadings,
+}
+
+impl Display for TopOffFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TopOffFailure::MaxFillTime => write!(f, "maximum fill time was exceeded"),
+            TopOffFailure::NoRise => write!(f, "water level didn't rise as expected"),
+            TopOffFailure::NoReadings => write!(f, "water level readings went missing"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn test_update() -> Result<()> {
+        struct TestCase<'a> {
+            name: &'a str,
+            state: TopOffState,
+            level: Option<f32>,
+            pumping: bool,
+            now: DateTime<Utc>,
+            expected_state: TopOffState,
+        }
+
+        let start = new_datetime(12, 0, 0);
+        let filling_for = |seconds, checked| TopOffState::Filling {
+            filling_for: TimeDelta::seconds(seconds),
+            checked,
+            level: 35.0,
+        };
+        let filling = filling_for(0, start);
+
+        let test_cases = vec![
+            TestCase {
+                name: "idle_above_low_mark",
+                state: TopOffState::Idle,
+                level: Some(45.0),
+                pumping: true,
+                now: start,
+                expected_state: TopOffState::Idle,
+            },
+            TestCase {
+                name: "idle_no_readings",
+                state: TopOffState::Idle,
+                level: None,
+                pumping: true,
+                now: start,
+                expected_state: TopOffState::Idle,
+            },
+            TestCase {
+                name: "idle_below_low_mark",
+                state: TopOffState::Idle,
+                level: Some(35.0),
+                pumping: true,
+                now: start,
+                expected_state: filling,
+            },
+            TestCase {
+                name: "filling_rising",
+                state: filling,
+                level: Some(50.0),
+                pumping: true,
+                now: new_datetime(12, 2, 0),
+                expected_state: filling_for(2 * 60, new_datetime(12, 2, 0)),
+            },
+            TestCase {
+                name: "filling_held_off",
+                state: filling,
+                level: Some(35.0),
+                pumping: false,
+                now: new_datetime(12, 10, 0),
+                expected_state: filling_for(0, new_datetime(12, 10, 0)),
+            },
+            TestCase {
+                name: "filling_after_being_held_off",
+                state: filling_for(30, new_datetime(12, 10, 0)),
+                level: Some(50.0),
+                pumping: true,
+                now: new_datetime(12, 14, 0),
+                expected_state: filling_for(4 * 60 + 30, new_datetime(12, 14, 0)),
+            },
+            TestCase {
+                name: "filling_reached_high_mark",
+                state: filling,
+                level: Some(60.0),
+                pumping: true,
+                now: new_datetime(12, 3, 0),
+                expected_state: TopOffState::Idle,
+            },
+            TestCase {
+                name: "filling_no_rise_yet",
+                state: filling,
+                level: Some(35.0),
+                pumping: true,
+                now: new_datetime(12, 0, 30),
+                expected_state: filling_for(30, new_datetime(12, 0, 30)),
+            },
+            TestCase {
+                name: "filling_no_rise",
+                state: filling,
+                level: Some(36.0),
+                pumping: true,
+                now: new_datetime(12, 1, 0),
+                expected_state: TopOffState::Failed(TopOffFailure::NoRise),
+            },
+            TestCase {
+                name: "filling_too_long",
+                state: filling,
+                level: Some(55.0),
+                pumping: true,
+                now: new_datetime(12, 5, 0),
+                expected_state: TopOffState::Failed(TopOffFailure::MaxFillTime),
+            },
+            TestCase {
+                name: "filling_no_readings",
+                state: filling,
+                level: None,
+                pumping: true,
+                now: new_datetime(12, 1, 0),
+                expected_state: TopOffState::Failed(TopOffFailure::NoReadings),
+            },
+            TestCase {
+                name: "failed_stays_failed",
+                state: TopOffState::Failed(TopOffFailure::NoRise),
+                level: Some(30.0),
+                pumping: true,
+                now: new_datetime(13, 0, 0),
+                expected_state: TopOffState::Failed(TopOffFailure::NoRise),
+            },
+            TestCase {
+                name: "failed_topped_off_manually",
+                state: TopOffState::Failed(TopOffFailure::NoRise),
+                level: Some(65.0),
+                pumping: true,
+                now: new_datetime(13, 0, 0),
+                expected_state: TopOffState::Idle,
+            },
+        ];
+
+        for test_case in &test_cases {
+            println!("test case: {}", test_case.name);
+
+            let top_off = TopOff::new(SensorName::new("sensor")?, 40.0, 60.0, 5 * 60)?
+                .with_min_rise(2.0, 60)?;
+            assert_eq!(
+                top_off.update(
+                    test_case.state,
+                    test_case.level,
+                    test_case.pumping,
+                    &test_case.now
+                ),
+                test_case.expected_state
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_new() -> Result<()> {
+        let sensor = SensorName::new("sensor")?;
+        assert!(TopOff::new(sensor.clone(), 40.0, 60.0, 300).is_ok());
+        assert!(TopOff::new(sensor.clone(), 60.0, 40.0, 300).is_err());
+        assert!(TopOff::new(sensor.clone(), 40.0, 110.0, 300).is_err());
+        assert!(TopOff::new(sensor.clone(), f32::NAN, 60.0, 300).is_err());
+        assert!(TopOff::new(sensor.clone(), 40.0, 60.0, 0).is_err());
+
+        let top_off = TopOff::new(sensor, 40.0, 60.0, 300)?;
+        assert!(top_off.clone().with_min_rise(2.0, 60).is_ok());
+        assert!(top_off.clone().with_min_rise(0.0, 60).is_err());
+        assert!(top_off.clone().with_min_rise(30.0, 60).is_err());
+        assert!(top_off.with_min_rise(2.0, 300).is_err());
+
+        Ok(())
+    }
+
+    fn new_datetime(hour: u32, min: u32, sec: u32) -> DateTime<Utc> {
+        NaiveDate::from_ymd_opt(2024, 6, 15)
+            .unwrap()
+            .and_hms_opt(hour, min, sec)
+            .unwrap()
+            .and_utc()
+    }
+}
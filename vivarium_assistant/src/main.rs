@@ -1,26 +1,32 @@
 #![feature(duration_constructors)]
 
 use anyhow::anyhow;
+use chrono::TimeDelta;
 use env_logger::Env;
 use log::{error, info};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use std::{env, fs};
 use tokio::time;
+use vivarium_assistant::adapters::state::{State, StateFile};
 use vivarium_assistant::adapters::{self, config, metrics};
 use vivarium_assistant::config::Config;
-use vivarium_assistant::domain::outputs::{CurrentTimeProvider, OutputStatus};
+use vivarium_assistant::domain::outputs::OutputStatus;
+use vivarium_assistant::domain::regulation::Quantity;
 use vivarium_assistant::domain::sensors::{MedianCache, WaterLevel};
+use vivarium_assistant::domain::time::{Clock, CurrentTimeProvider};
 use vivarium_assistant::domain::{self, GPIO};
-use vivarium_assistant::domain::{outputs, sensors};
+use vivarium_assistant::domain::{outputs, sensors, top_off};
 use vivarium_assistant::errors::Result;
 use vivarium_assistant::ports::http::{self, Server};
 
 #[cfg(feature = "raspberry_pi")]
 use vivarium_assistant::adapters::raspberrypi;
 
+const USAGE: &str = "usage: program path_to_config_file.toml [preview [duration]]";
 const UPDATE_SENSORS_EVERY: Duration = Duration::from_secs(10);
 const UPDATE_OUTPUTS_EVERY: Duration = Duration::from_millis(100);
+const SAVE_STATE_EVERY: Duration = Duration::from_secs(1);
 const WATER_SENSOR_SMOOTHING_PERIOD: Duration = Duration::from_mins(5); // should presumably be
                                                                         // significantly larger
                                                                         // than
@@ -30,6 +36,14 @@ const WATER_SENSOR_SMOOTHING_PERIOD: Duration = Duration::from_mins(5); // shoul
 async fn main() -> Result<()> {
     env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
 
+    let args: Vec<String> = env::args().collect();
+    let config = load_config(&args)?;
+    match args.get(2).map(|v| v.as_str()) {
+        None => {}
+        Some("preview") if args.len() <= 4 => return preview(&config, args.get(3)),
+        Some(_) => return Err(anyhow!(USAGE)),
+    }
+
     #[cfg(not(feature = "raspberry_pi"))]
     let gpio = adapters::MockGPIO::new();
 
@@ -48,18 +62,11 @@ async fn main() -> Result<()> {
     let mut metrics = metrics::Metrics::new()?;
     metrics.set_startup_time(&current_time_provider.now());
 
-    let config = load_config()?;
-
-    let controller = SafeController::new(outputs::Controller::new(
-        config.outputs(),
-        gpio.clone(),
-        current_time_provider.clone(),
-    )?);
-    let server = Server::new();
+    let clock = Clock::new(current_time_provider, config.timezone());
 
     let mut water_level_sensors = vec![];
     for definition in config.water_level_sensors().sensors() {
-        let trig = gpio.output(&definition.trig_pin())?;
+        let trig = gpio.output(&definition.trig_pin(), domain::OutputPinState::Low)?;
         let echo = gpio.input(&definition.echo_pin())?;
         let sensor = sensors::HCSR04::new(trig, echo)?;
         let sensor = sensors::WaterLevelSensor::new(
@@ -74,25 +81,55 @@ async fn main() -> Result<()> {
         });
     }
 
+    // Everything that can fail has to happen before this point. The pins keep their levels when
+    // the program exits so returning an error past this point could leave the outputs on. The
+    // controller puts the pins it already took over back into their safe states if it fails.
+    let mut controller = outputs::Controller::new(config.outputs(), gpio.clone(), clock.clone())?;
+
+    let state_file = config
+        .state_file()
+        .map(|v| Arc::new(StateFile::new(v, config.timezone())));
+    if let Some(state_file) = &state_file {
+        match state_file.load() {
+            Ok(state) => restore_state(&mut controller, &state),
+            Err(err) => error!("error loading the state file, starting from scratch: {err}"),
+        }
+    }
+
+    let controller = SafeController::new(controller, state_file.clone());
     setup_failsafe_hook(controller.clone());
 
+    // the outputs are kept in their safe states until the first update
+    controller.update_outputs();
+
+    let server = Server::new();
+
     tokio::spawn({
         let metrics = metrics.clone();
-        async move { update_water_sensors_loop(water_level_sensors, metrics).await }
+        let controller = controller.clone();
+        async move { update_water_sensors_loop(water_level_sensors, metrics, controller).await }
     });
 
+    if let Some(state_file) = state_file {
+        tokio::spawn({
+            let controller = controller.clone();
+            async move { save_state_loop(state_file, controller).await }
+        });
+    }
+
     if let Some(aht_20_name) = config.aht_20() {
         tokio::spawn({
             let metrics = metrics.clone();
+            let controller = controller.clone();
             let aht_20_name = aht_20_name.clone();
-            async move { update_aht20_loop(&aht_20_name, aht20, metrics).await }
+            async move { update_aht20_loop(&aht_20_name, aht20, metrics, controller).await }
         });
     }
 
     tokio::spawn({
         let metrics = metrics.clone();
         let controller = controller.clone();
-        async move { server_loop(&server, &config, metrics, controller).await }
+        async move { server_loop(&server, &config, metrics, controller, clock).await }
     });
     update_outputs_loop(controller, metrics.clone()).await;
     Ok(())
@@ -111,22 +148,69 @@ where
     }));
 }
 
-fn load_config() -> Result<Config> {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        return Err(anyhow!("usage: program path_to_config_file.toml"));
-    }
+fn load_config(args: &[String]) -> Result<Config> {
+    let Some(path) = args.get(1) else {
+        return Err(anyhow!(USAGE));
+    };
 
-    let config_string = fs::read_to_string(args.get(1).unwrap())?;
+    let config_string = fs::read_to_string(path)?;
     config::load(&config_string)
 }
 
-async fn server_loop<M, C>(server: &Server, config: &Config, metrics: M, controller: C)
+// Prints what the outputs are going to do without touching the real pins so that changes to the
+// config can be checked before deploying them.
+fn preview(config: &Config, duration: Option<&String>) -> Result<()> {
+    let duration = match duration {
+        Some(duration) => TimeDelta::from_std(config::DURATION_PARSER.parse(duration)?)?,
+        None => outputs::DEFAULT_PREVIEW,
+    };
+
+    let clock = Clock::new(adapters::CurrentTimeProvider::new(), config.timezone());
+    let mut controller =
+        outputs::Controller::new(config.outputs(), adapters::MockGPIO::new(), clock.clone())?;
+    if let Some(state_file) = config.state_file() {
+        restore_state(
+            &mut controller,
+            &StateFile::new(state_file, config.timezone()).load()?,
+        );
+    }
+
+    for transition in controller.preview(duration)? {
+        println!(
+            "{time} '{output}' {state}",
+            time = clock.timezone().resolve(&transition.time).to_rfc3339(),
+            output = transition.output,
+            state = config::format_state(&transition.state),
+        );
+    }
+    Ok(())
+}
+
+fn restore_state<OP, CTP>(controller: &mut outputs::Controller<OP, CTP>, state: &State)
 where
+    OP: domain::OutputPin,
+    CTP: CurrentTimeProvider,
+{
+    controller.restore_overrides(state.overrides());
+    if let Some(hold) = state.hold() {
+        controller.restore_hold(hold);
+    }
+    if let Some(profile) = state.profile() {
+        controller.restore_profile(profile.clone());
+    }
+}
+
+async fn server_loop<M, C>(
+    server: &Server,
+    config: &Config,
+    metrics: M,
+    controller: C,
+    clock: Clock<adapters::CurrentTimeProvider>,
+) where
     M: http::Metrics + Sync + Send + Clone + 'static,
     C: http::Controller + Sync + Send + Clone + 'static,
 {
-    let deps = http::Deps::new(metrics, controller);
+    let deps = http::Deps::new(metrics, controller, clock);
 
     loop {
         match server.run(config, deps.clone()).await {
@@ -140,12 +224,33 @@ where
     }
 }
 
-async fn update_water_sensors_loop<T, M>(
+// Only writes to the state file when the overrides, the hold or the profile change.
+async fn save_state_loop<C>(state_file: Arc<StateFile>, controller: C)
+where
+    C: Controller,
+{
+    let mut saved = None;
+
+    loop {
+        let state = controller.state();
+        if saved.as_ref() != Some(&state) {
+            match state_file.save(&state) {
+                Ok(_) => saved = Some(state),
+                Err(err) => error!("error saving the state file: {err}"),
+            }
+        }
+        time::sleep(SAVE_STATE_EVERY).await;
+    }
+}
+
+async fn update_water_sensors_loop<T, M, C>(
     mut sensors: Vec<QueriedWaterLevelSensor<T>>,
     mut metrics: M,
+    controller: C,
 ) where
     T: sensors::DistanceSensor,
     M: Metrics,
+    C: Controller,
 {
     let zero = sensors::WaterLevel::new(0.0).unwrap();
 
@@ -169,8 +274,16 @@ async fn update_water_sensors_loop<T, M>(
                 }
             };
 
+            // without any recent measurements the controller considers the reservoir empty
             let level = match sensor.cache.get() {
-                Some(value) => value,
+                Some(value) => {
+                    controller.report_reading(
+                        &sensor.name,
+                        Quantity::WaterLevel,
+                        value.percentage() * 100.0,
+                    );
+                    value
+                }
                 None => &zero,
             };
             metrics.report_water_level(&sensor.name, level);
@@ -179,13 +292,15 @@ async fn update_water_sensors_loop<T, M>(
     }
 }
 
-async fn update_aht20_loop<M, I>(
+async fn update_aht20_loop<M, I, C>(
     sensor_name: &sensors::SensorName,
     mut sensor: sensors::AHT20<I>,
     mut metrics: M,
+    controller: C,
 ) where
     M: Metrics,
     I: domain::I2C,
+    C: Controller,
 {
     let zero_temperature = sensors::Temperature::new(0.0).unwrap();
     let zero_humidity = sensors::Humidity::new(0.0).unwrap();
@@ -201,6 +316,16 @@ async fn update_aht20_loop<M, I>(
                     );
                 metrics.report_temperature(sensor_name, &value.temperature());
                 metrics.report_humidity(sensor_name, &value.humidity());
+                controller.report_reading(
+                    sensor_name,
+                    Quantity::Temperature,
+                    value.temperature().celcius(),
+                );
+                controller.report_reading(
+                    sensor_name,
+                    Quantity::Humidity,
+                    value.humidity().percentage() * 100.0,
+                );
             }
             Err(err) => {
                 error!(
@@ -226,8 +351,20 @@ where
         controller.update_outputs();
         for entry in controller.status() {
             metrics.report_output(&entry.name, &entry.state);
+            metrics.report_output_limit(&entry.name, entry.limit_exceeded);
+            metrics.report_top_off_failure(&entry.name, entry.top_off_failed);
+            metrics.report_override(&entry.name, entry.override_in_effect.as_ref());
+            metrics.report_on_today(&entry.name, &entry.on_today);
         }
-        time::sleep(UPDATE_OUTPUTS_EVERY).await;
+        metrics.report_hold(controller.current_hold().is_some());
+        metrics.report_profile(&controller.profiles(), controller.current_profile().name());
+
+        // short activations would otherwise be rounded up to the update interval
+        let next_update = controller
+            .next_change()
+            .and_then(|v| v.to_std().ok())
+            .map_or(UPDATE_OUTPUTS_EVERY, |v| v.min(UPDATE_OUTPUTS_EVERY));
+        time::sleep(next_update).await;
     }
 }
 
@@ -239,6 +376,28 @@ struct QueriedWaterLevelSensor<T: sensors::DistanceSensor> {
 
 trait Metrics {
     fn report_output(&mut self, output: &outputs::OutputName, state: &outputs::OutputState);
+    fn report_output_limit(
+        &mut self,
+        output: &outputs::OutputName,
+        exceeded: Option<outputs::Limit>,
+    );
+    fn report_top_off_failure(
+        &mut self,
+        output: &outputs::OutputName,
+        failed: Option<top_off::TopOffFailure>,
+    );
+    fn report_override(
+        &mut self,
+        output: &outputs::OutputName,
+        in_effect: Option<&outputs::OverrideStatus>,
+    );
+    fn report_on_today(&mut self, output: &outputs::OutputName, on_today: &TimeDelta);
+    fn report_hold(&mut self, held: bool);
+    fn report_profile(
+        &mut self,
+        profiles: &[outputs::Profile],
+        active: Option<&outputs::ProfileName>,
+    );
     fn report_water_level(&mut self, sensor: &sensors::SensorName, level: &sensors::WaterLevel);
     fn report_temperature(
         &mut self,
@@ -253,6 +412,46 @@ impl Metrics for metrics::Metrics {
         metrics::Metrics::report_output(self, output, state);
     }
 
+    fn report_output_limit(
+        &mut self,
+        output: &outputs::OutputName,
+        exceeded: Option<outputs::Limit>,
+    ) {
+        metrics::Metrics::report_output_limit(self, output, exceeded);
+    }
+
+    fn report_top_off_failure(
+        &mut self,
+        output: &outputs::OutputName,
+        failed: Option<top_off::TopOffFailure>,
+    ) {
+        metrics::Metrics::report_top_off_failure(self, output, failed);
+    }
+
+    fn report_override(
+        &mut self,
+        output: &outputs::OutputName,
+        in_effect: Option<&outputs::OverrideStatus>,
+    ) {
+        metrics::Metrics::report_override(self, output, in_effect);
+    }
+
+    fn report_on_today(&mut self, output: &outputs::OutputName, on_today: &TimeDelta) {
+        metrics::Metrics::report_on_today(self, output, on_today);
+    }
+
+    fn report_hold(&mut self, held: bool) {
+        metrics::Metrics::report_hold(self, held);
+    }
+
+    fn report_profile(
+        &mut self,
+        profiles: &[outputs::Profile],
+        active: Option<&outputs::ProfileName>,
+    ) {
+        metrics::Metrics::report_profile(self, profiles, active);
+    }
+
     fn report_water_level(&mut self, sensor: &sensors::SensorName, level: &sensors::WaterLevel) {
         metrics::Metrics::report_water_level(self, sensor, level);
     }
@@ -272,36 +471,79 @@ impl Metrics for metrics::Metrics {
 
 trait Controller: Send + Sync {
     fn update_outputs(&self);
+    fn report_reading(&self, sensor: &sensors::SensorName, quantity: Quantity, value: f32);
     fn status(&self) -> Vec<OutputStatus>;
+    fn next_change(&self) -> Option<TimeDelta>;
+    fn current_hold(&self) -> Option<outputs::Hold>;
+    fn current_profile(&self) -> outputs::ActiveProfile;
+    fn profiles(&self) -> Vec<outputs::Profile>;
+    fn state(&self) -> State;
     fn fail_safe(&self);
 }
 
 trait WrappedController: Send {
     fn update_outputs(&mut self);
+    fn report_reading(&mut self, sensor: &sensors::SensorName, quantity: Quantity, value: f32);
     fn status(&mut self) -> Vec<OutputStatus>;
+    fn overrides(&mut self) -> Vec<outputs::OverrideStatus>;
+    fn next_change(&mut self) -> Option<TimeDelta>;
     fn clear_overrides(&mut self, output_name: outputs::OutputName) -> Result<()>;
     fn add_override(
         &mut self,
         output_name: outputs::OutputName,
-        state: outputs::OutputState,
-        activation: outputs::ScheduledActivation,
+        new_override: outputs::NewOverride,
+    ) -> Result<outputs::OverrideId>;
+    fn output_overrides(
+        &mut self,
+        output_name: &outputs::OutputName,
+    ) -> Result<Vec<outputs::OverrideStatus>>;
+    fn override_in_effect(
+        &mut self,
+        output_name: &outputs::OutputName,
+    ) -> Result<Option<outputs::OverrideId>>;
+    fn remove_override(
+        &mut self,
+        output_name: outputs::OutputName,
+        id: outputs::OverrideId,
     ) -> Result<()>;
+    fn schedule(&mut self) -> outputs::ScheduleSnapshot;
+    fn apply_scene(&mut self, name: outputs::SceneName) -> Result<Vec<outputs::OverrideId>>;
+    fn cancel_scene(&mut self, name: outputs::SceneName) -> Result<()>;
+    fn hold(&mut self, hold: outputs::Hold) -> Result<()>;
+    fn resume(&mut self);
+    fn current_hold(&mut self) -> Option<outputs::Hold>;
+    fn restore_hold(&mut self, hold: outputs::Hold);
+    fn switch_profile(&mut self, name: Option<outputs::ProfileName>) -> Result<()>;
+    fn current_profile(&mut self) -> outputs::ActiveProfile;
+    fn profiles(&mut self) -> Vec<outputs::Profile>;
     fn fail_safe(&mut self);
 }
 
 impl<OP, CTP> WrappedController for outputs::Controller<OP, CTP>
 where
     OP: domain::OutputPin + Send,
-    CTP: outputs::CurrentTimeProvider + Send,
+    CTP: CurrentTimeProvider + Send,
 {
     fn update_outputs(&mut self) {
         outputs::Controller::update_outputs(self);
     }
 
+    fn report_reading(&mut self, sensor: &sensors::SensorName, quantity: Quantity, value: f32) {
+        outputs::Controller::report_reading(self, sensor, quantity, value);
+    }
+
     fn status(&mut self) -> Vec<OutputStatus> {
         outputs::Controller::status(self)
     }
 
+    fn overrides(&mut self) -> Vec<outputs::OverrideStatus> {
+        outputs::Controller::overrides(self)
+    }
+
+    fn next_change(&mut self) -> Option<TimeDelta> {
+        outputs::Controller::next_change(self)
+    }
+
     fn clear_overrides(&mut self, output_name: outputs::OutputName) -> Result<()> {
         outputs::Controller::clear_overrides(self, output_name)
     }
@@ -313,10 +555,71 @@ where
     fn add_override(
         &mut self,
         output_name: outputs::OutputName,
-        state: outputs::OutputState,
-        activation: outputs::ScheduledActivation,
+        new_override: outputs::NewOverride,
+    ) -> Result<outputs::OverrideId> {
+        outputs::Controller::add_override(self, output_name, new_override)
+    }
+
+    fn output_overrides(
+        &mut self,
+        output_name: &outputs::OutputName,
+    ) -> Result<Vec<outputs::OverrideStatus>> {
+        outputs::Controller::output_overrides(self, output_name)
+    }
+
+    fn override_in_effect(
+        &mut self,
+        output_name: &outputs::OutputName,
+    ) -> Result<Option<outputs::OverrideId>> {
+        outputs::Controller::override_in_effect(self, output_name)
+    }
+
+    fn remove_override(
+        &mut self,
+        output_name: outputs::OutputName,
+        id: outputs::OverrideId,
     ) -> Result<()> {
-        outputs::Controller::add_override(self, output_name, state, activation)
+        outputs::Controller::remove_override(self, output_name, id)
+    }
+
+    fn schedule(&mut self) -> outputs::ScheduleSnapshot {
+        outputs::Controller::schedule(self)
+    }
+
+    fn apply_scene(&mut self, name: outputs::SceneName) -> Result<Vec<outputs::OverrideId>> {
+        outputs::Controller::apply_scene(self, &name)
+    }
+
+    fn cancel_scene(&mut self, name: outputs::SceneName) -> Result<()> {
+        outputs::Controller::cancel_scene(self, &name)
+    }
+
+    fn hold(&mut self, hold: outputs::Hold) -> Result<()> {
+        outputs::Controller::hold(self, hold)
+    }
+
+    fn resume(&mut self) {
+        outputs::Controller::resume(self)
+    }
+
+    fn current_hold(&mut self) -> Option<outputs::Hold> {
+        outputs::Controller::current_hold(self)
+    }
+
+    fn restore_hold(&mut self, hold: outputs::Hold) {
+        outputs::Controller::restore_hold(self, hold)
+    }
+
+    fn switch_profile(&mut self, name: Option<outputs::ProfileName>) -> Result<()> {
+        outputs::Controller::switch_profile(self, name.as_ref())
+    }
+
+    fn current_profile(&mut self) -> outputs::ActiveProfile {
+        outputs::Controller::current_profile(self)
+    }
+
+    fn profiles(&mut self) -> Vec<outputs::Profile> {
+        outputs::Controller::profiles(self).to_vec()
     }
 }
 
@@ -325,19 +628,27 @@ where
     T: WrappedController,
 {
     controller: Arc<Mutex<T>>,
+    state_file: Option<Arc<StateFile>>,
 }
 
 impl<T> SafeController<T>
 where
     T: WrappedController,
 {
-    fn new(controller: T) -> Self {
+    fn new(controller: T, state_file: Option<Arc<StateFile>>) -> Self {
         Self {
             controller: Arc::new(Mutex::new(controller)),
+            state_file,
         }
     }
 }
 
+fn current_state<T: WrappedController>(controller: &mut T) -> State {
+    State::new(&controller.overrides())
+        .with_hold(controller.current_hold())
+        .with_profile(Some(controller.current_profile()))
+}
+
 impl<T> Controller for SafeController<T>
 where
     T: WrappedController,
@@ -347,11 +658,41 @@ where
         (*controller).update_outputs();
     }
 
+    fn report_reading(&self, sensor: &sensors::SensorName, quantity: Quantity, value: f32) {
+        let mut controller = self.controller.lock().unwrap();
+        (*controller).report_reading(sensor, quantity, value);
+    }
+
     fn status(&self) -> Vec<OutputStatus> {
         let mut controller = self.controller.lock().unwrap();
         (*controller).status()
     }
 
+    fn next_change(&self) -> Option<TimeDelta> {
+        let mut controller = self.controller.lock().unwrap();
+        (*controller).next_change()
+    }
+
+    fn current_hold(&self) -> Option<outputs::Hold> {
+        let mut controller = self.controller.lock().unwrap();
+        (*controller).current_hold()
+    }
+
+    fn current_profile(&self) -> outputs::ActiveProfile {
+        let mut controller = self.controller.lock().unwrap();
+        (*controller).current_profile()
+    }
+
+    fn profiles(&self) -> Vec<outputs::Profile> {
+        let mut controller = self.controller.lock().unwrap();
+        (*controller).profiles()
+    }
+
+    fn state(&self) -> State {
+        let mut controller = self.controller.lock().unwrap();
+        current_state(&mut *controller)
+    }
+
     fn fail_safe(&self) {
         let mut controller = self.controller.lock().unwrap();
         (*controller).fail_safe()
@@ -370,11 +711,102 @@ where
     fn add_override(
         &mut self,
         output_name: outputs::OutputName,
-        state: outputs::OutputState,
-        activation: outputs::ScheduledActivation,
+        new_override: outputs::NewOverride,
+    ) -> Result<outputs::OverrideId> {
+        let mut controller = self.controller.lock().unwrap();
+        (*controller).add_override(output_name, new_override)
+    }
+
+    fn output_overrides(
+        &self,
+        output_name: &outputs::OutputName,
+    ) -> Result<Vec<outputs::OverrideStatus>> {
+        let mut controller = self.controller.lock().unwrap();
+        (*controller).output_overrides(output_name)
+    }
+
+    fn override_in_effect(
+        &self,
+        output_name: &outputs::OutputName,
+    ) -> Result<Option<outputs::OverrideId>> {
+        let mut controller = self.controller.lock().unwrap();
+        (*controller).override_in_effect(output_name)
+    }
+
+    fn remove_override(
+        &mut self,
+        output_name: outputs::OutputName,
+        id: outputs::OverrideId,
     ) -> Result<()> {
         let mut controller = self.controller.lock().unwrap();
-        (*controller).add_override(output_name, state, activation)
+        (*controller).remove_override(output_name, id)
+    }
+
+    fn schedule(&self) -> outputs::ScheduleSnapshot {
+        let mut controller = self.controller.lock().unwrap();
+        (*controller).schedule()
+    }
+
+    fn apply_scene(&mut self, name: outputs::SceneName) -> Result<Vec<outputs::OverrideId>> {
+        let mut controller = self.controller.lock().unwrap();
+        (*controller).apply_scene(name)
+    }
+
+    fn cancel_scene(&mut self, name: outputs::SceneName) -> Result<()> {
+        let mut controller = self.controller.lock().unwrap();
+        (*controller).cancel_scene(name)
+    }
+
+    // The hold is there to keep the outputs where they are so it is saved right away instead of
+    // waiting for the save loop, losing it to a restart would resume the automation.
+    fn hold(&mut self, hold: outputs::Hold) -> Result<()> {
+        let Some(state_file) = &self.state_file else {
+            return Err(anyhow!(
+                "holds would be lost when the program restarts, configure a state file to use them"
+            ));
+        };
+
+        let mut controller = self.controller.lock().unwrap();
+        let previous = (*controller).current_hold();
+        (*controller).hold(hold)?;
+        if let Err(err) = state_file.save(&current_state(&mut *controller)) {
+            (*controller).resume();
+            if let Some(previous) = previous {
+                (*controller).restore_hold(previous);
+            }
+            return Err(anyhow!("the hold couldn't be saved: {err}"));
+        }
+        Ok(())
+    }
+
+    fn resume(&mut self) {
+        let mut controller = self.controller.lock().unwrap();
+        (*controller).resume();
+        if let Some(state_file) = &self.state_file {
+            if let Err(err) = state_file.save(&current_state(&mut *controller)) {
+                error!("error saving the state file: {err}");
+            }
+        }
+    }
+
+    fn current_hold(&self) -> Option<outputs::Hold> {
+        let mut controller = self.controller.lock().unwrap();
+        (*controller).current_hold()
+    }
+
+    fn switch_profile(&mut self, name: Option<outputs::ProfileName>) -> Result<()> {
+        let mut controller = self.controller.lock().unwrap();
+        (*controller).switch_profile(name)
+    }
+
+    fn current_profile(&self) -> outputs::ActiveProfile {
+        let mut controller = self.controller.lock().unwrap();
+        (*controller).current_profile()
+    }
+
+    fn profiles(&self) -> Vec<outputs::Profile> {
+        let mut controller = self.controller.lock().unwrap();
+        (*controller).profiles()
     }
 }
 
@@ -385,6 +817,7 @@ where
     fn clone(&self) -> Self {
         Self {
             controller: self.controller.clone(),
+            state_file: self.state_file.clone(),
         }
     }
 }
@@ -1,15 +1,18 @@
 use crate::{
     adapters::{
-        config::DURATION_PARSER,
+        self,
+        config::{self as config_adapter, DURATION_PARSER},
         metrics::{self},
     },
     config,
-    domain::outputs::{self},
+    domain::{
+        outputs::{self},
+        time::Clock,
+    },
     errors::{Error, Result},
 };
-use anyhow::anyhow;
 use axum::{
-    extract::{Json, Path, State},
+    extract::{Json, Path, Query, State},
     http::StatusCode,
     response::{IntoResponse, Response},
 };
@@ -17,8 +20,9 @@ use axum::{
     routing::{delete, get, post},
     Router,
 };
+use chrono::TimeDelta;
 use prometheus::{Registry, TextEncoder};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 pub struct Server {}
 
@@ -42,6 +46,19 @@ impl Server {
             .route("/metrics", get(handle_metrics))
             .route("/outputs/:name/overrides", delete(handle_overrides_delete))
             .route("/outputs/:name/overrides", post(handle_overrides_post))
+            .route("/outputs/:name/overrides", get(handle_overrides_get))
+            .route(
+                "/outputs/:name/overrides/:id",
+                delete(handle_override_delete),
+            )
+            .route("/schedule/preview", get(handle_schedule_preview_get))
+            .route("/scenes/:name", post(handle_scene_post))
+            .route("/scenes/:name", delete(handle_scene_delete))
+            .route("/hold", get(handle_hold_get))
+            .route("/hold", post(handle_hold_post))
+            .route("/hold", delete(handle_hold_delete))
+            .route("/profile", get(handle_profile_get))
+            .route("/profile", post(handle_profile_post))
             .with_state(deps);
 
         let listener = tokio::net::TcpListener::bind(config.address()).await?;
@@ -73,36 +90,221 @@ where
     Ok(deps.controller.clear_overrides(name)?)
 }
 
+async fn handle_override_delete<M, C>(
+    State(mut deps): State<Deps<M, C>>,
+    Path((name, id)): Path<(String, u64)>,
+) -> std::result::Result<(), AppError>
+where
+    C: Controller,
+{
+    let name = outputs::OutputName::new(name)?;
+    Ok(deps
+        .controller
+        .remove_override(name, outputs::OverrideId::new(id))?)
+}
+
+async fn handle_overrides_get<M, C>(
+    State(deps): State<Deps<M, C>>,
+    Path(name): Path<String>,
+) -> std::result::Result<Json<Vec<SerializedOverrideStatus>>, AppError>
+where
+    C: Controller,
+{
+    let name = outputs::OutputName::new(name)?;
+    let now = deps.clock.now_utc();
+    let timezone = deps.clock.timezone();
+    let overrides = deps.controller.output_overrides(&name)?;
+    let in_effect = deps.controller.override_in_effect(&name)?;
+    Ok(Json(
+        overrides
+            .iter()
+            .map(|v| SerializedOverrideStatus {
+                id: v.id().id(),
+                state: config_adapter::format_state(&v.state()),
+                priority: v.priority().priority(),
+                start: timezone.fixed_offset(&v.start()).to_rfc3339(),
+                end: timezone.fixed_offset(&v.end()).to_rfc3339(),
+                remaining_seconds: (v.end() - now).num_milliseconds().max(0) as f64 / 1000.0,
+                scene: v.scene().map(|v| v.name().to_string()),
+                in_effect: in_effect == Some(v.id()),
+            })
+            .collect(),
+    ))
+}
+
 async fn handle_overrides_post<M, C>(
     State(mut deps): State<Deps<M, C>>,
     Path(name): Path<String>,
     Json(payload): Json<SerializedOverride>,
-) -> std::result::Result<(), AppError>
+) -> std::result::Result<Json<SerializedOverrideId>, AppError>
 where
     C: Controller,
 {
     let name = outputs::OutputName::new(name)?;
-    let state = parse_state(&payload.state)?;
-    let when = chrono::Local::now().naive_local().time();
-    let for_seconds = DURATION_PARSER
-        .parse(&payload.for_string)?
-        .as_secs()
-        .try_into()?;
-    let activation = outputs::ScheduledActivation::new(when, for_seconds)?;
-    Ok(deps.controller.add_override(name, state, activation)?)
+    let state = config_adapter::parse_state(&payload.state)?;
+    let now = deps.clock.now_utc();
+    let start = match &payload.start {
+        Some(start) => config_adapter::parse_override_start(start, &now, &deps.clock.timezone())?,
+        None => now,
+    };
+    let duration = TimeDelta::from_std(DURATION_PARSER.parse(&payload.for_string)?)?;
+    let mut new_override = outputs::NewOverride::new(state, start, duration)?;
+    if let Some(priority) = payload.priority {
+        new_override = new_override.with_priority(outputs::OverridePriority::new(priority));
+    }
+    let id = deps.controller.add_override(name, new_override)?;
+    Ok(Json(SerializedOverrideId { id: id.id() }))
+}
+
+async fn handle_scene_post<M, C>(
+    State(mut deps): State<Deps<M, C>>,
+    Path(name): Path<String>,
+) -> std::result::Result<Json<SerializedSceneOverrideIds>, AppError>
+where
+    C: Controller,
+{
+    let name = outputs::SceneName::new(name)?;
+    let ids = deps.controller.apply_scene(name)?;
+    Ok(Json(SerializedSceneOverrideIds {
+        ids: ids.iter().map(|v| v.id()).collect(),
+    }))
+}
+
+async fn handle_scene_delete<M, C>(
+    State(mut deps): State<Deps<M, C>>,
+    Path(name): Path<String>,
+) -> std::result::Result<(), AppError>
+where
+    C: Controller,
+{
+    let name = outputs::SceneName::new(name)?;
+    Ok(deps.controller.cancel_scene(name)?)
+}
+
+async fn handle_hold_get<M, C>(
+    State(deps): State<Deps<M, C>>,
+) -> std::result::Result<Json<SerializedHoldStatus>, AppError>
+where
+    C: Controller,
+{
+    let timezone = deps.clock.timezone();
+    let hold = deps.controller.current_hold();
+    Ok(Json(SerializedHoldStatus {
+        held: hold.is_some(),
+        state: hold
+            .and_then(|v| v.state())
+            .map(|v| config_adapter::format_state(&v)),
+        until: hold
+            .and_then(|v| v.until())
+            .map(|v| timezone.fixed_offset(&v).to_rfc3339()),
+    }))
+}
+
+// Without a state the outputs are held in their safe states and without a duration until resumed.
+async fn handle_hold_post<M, C>(
+    State(mut deps): State<Deps<M, C>>,
+    Json(payload): Json<SerializedHold>,
+) -> std::result::Result<(), AppError>
+where
+    C: Controller,
+{
+    let mut hold = outputs::Hold::new();
+    if let Some(state) = &payload.state {
+        hold = hold.with_state(config_adapter::parse_state(state)?)?;
+    }
+    if let Some(for_string) = &payload.for_string {
+        let duration = TimeDelta::from_std(DURATION_PARSER.parse(for_string)?)?;
+        hold = hold.with_until(deps.clock.now_utc() + duration);
+    }
+    Ok(deps.controller.hold(hold)?)
+}
+
+async fn handle_hold_delete<M, C>(
+    State(mut deps): State<Deps<M, C>>,
+) -> std::result::Result<(), AppError>
+where
+    C: Controller,
+{
+    deps.controller.resume();
+    Ok(())
+}
+
+async fn handle_profile_get<M, C>(
+    State(deps): State<Deps<M, C>>,
+) -> std::result::Result<Json<SerializedProfileStatus>, AppError>
+where
+    C: Controller,
+{
+    Ok(Json(SerializedProfileStatus {
+        active: deps
+            .controller
+            .current_profile()
+            .name()
+            .map(|v| v.name().to_string()),
+        profiles: deps
+            .controller
+            .profiles()
+            .iter()
+            .map(|v| v.name().name().to_string())
+            .collect(),
+    }))
+}
+
+// Without a name switches back to the default schedule.
+async fn handle_profile_post<M, C>(
+    State(mut deps): State<Deps<M, C>>,
+    Json(payload): Json<SerializedProfile>,
+) -> std::result::Result<(), AppError>
+where
+    C: Controller,
+{
+    let name = match &payload.name {
+        Some(name) => Some(outputs::ProfileName::new(name)?),
+        None => None,
+    };
+    Ok(deps.controller.switch_profile(name)?)
+}
+
+async fn handle_schedule_preview_get<M, C>(
+    State(deps): State<Deps<M, C>>,
+    Query(query): Query<SerializedPreviewQuery>,
+) -> std::result::Result<Json<Vec<SerializedTransition>>, AppError>
+where
+    C: Controller,
+{
+    let duration = match &query.for_string {
+        Some(for_string) => TimeDelta::from_std(DURATION_PARSER.parse(for_string)?)?,
+        None => outputs::DEFAULT_PREVIEW,
+    };
+    let timezone = deps.clock.timezone();
+    // simulating a long period takes a while so it mustn't block the controller or the runtime
+    let schedule = deps.controller.schedule();
+    let transitions = tokio::task::spawn_blocking(move || schedule.preview(duration)).await??;
+    Ok(Json(
+        transitions
+            .iter()
+            .map(|v| SerializedTransition {
+                time: timezone.resolve(&v.time).to_rfc3339(),
+                output: v.output.name().to_string(),
+                state: config_adapter::format_state(&v.state),
+            })
+            .collect(),
+    ))
 }
 
 #[derive(Clone)]
 pub struct Deps<M, C> {
     metrics: M,
     controller: C,
+    clock: Clock<adapters::CurrentTimeProvider>,
 }
 
 impl<M, C> Deps<M, C> {
-    pub fn new(metrics: M, controller: C) -> Self {
+    pub fn new(metrics: M, controller: C, clock: Clock<adapters::CurrentTimeProvider>) -> Self {
         Self {
             metrics,
             controller,
+            clock,
         }
     }
 }
@@ -122,15 +324,52 @@ pub trait Controller {
     fn add_override(
         &mut self,
         output_name: outputs::OutputName,
-        state: outputs::OutputState,
-        activation: outputs::ScheduledActivation,
+        new_override: outputs::NewOverride,
+    ) -> Result<outputs::OverrideId>;
+    fn output_overrides(
+        &self,
+        output_name: &outputs::OutputName,
+    ) -> Result<Vec<outputs::OverrideStatus>>;
+    fn override_in_effect(
+        &self,
+        output_name: &outputs::OutputName,
+    ) -> Result<Option<outputs::OverrideId>>;
+    fn remove_override(
+        &mut self,
+        output_name: outputs::OutputName,
+        id: outputs::OverrideId,
     ) -> Result<()>;
+    fn schedule(&self) -> outputs::ScheduleSnapshot;
+    fn apply_scene(&mut self, name: outputs::SceneName) -> Result<Vec<outputs::OverrideId>>;
+    fn cancel_scene(&mut self, name: outputs::SceneName) -> Result<()>;
+    fn hold(&mut self, hold: outputs::Hold) -> Result<()>;
+    fn resume(&mut self);
+    fn current_hold(&self) -> Option<outputs::Hold>;
+    fn switch_profile(&mut self, name: Option<outputs::ProfileName>) -> Result<()>;
+    fn current_profile(&self) -> outputs::ActiveProfile;
+    fn profiles(&self) -> Vec<outputs::Profile>;
 }
 
 struct AppError(Error);
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
+        if let Some(err) = self.0.downcast_ref::<outputs::BlockedByInterlock>() {
+            return (StatusCode::CONFLICT, err.to_string()).into_response();
+        }
+
+        if let Some(err) = self.0.downcast_ref::<outputs::RunningDry>() {
+            return (StatusCode::CONFLICT, err.to_string()).into_response();
+        }
+
+        if let Some(err) = self.0.downcast_ref::<outputs::ConflictingOverride>() {
+            return (StatusCode::CONFLICT, err.to_string()).into_response();
+        }
+
+        if let Some(err) = self.0.downcast_ref::<outputs::UnknownOverride>() {
+            return (StatusCode::NOT_FOUND, err.to_string()).into_response();
+        }
+
         (
             StatusCode::INTERNAL_SERVER_ERROR,
             format!("Something went wrong: {}", self.0),
@@ -153,12 +392,66 @@ struct SerializedOverride {
     state: String,
     #[serde(rename = "for")]
     for_string: String,
+    start: Option<String>,
+    priority: Option<u32>,
 }
 
-fn parse_state(s: &str) -> Result<outputs::OutputState> {
-    match s.to_uppercase().as_str() {
-        "ON" => Ok(outputs::OutputState::On),
-        "OFF" => Ok(outputs::OutputState::Off),
-        _ => Err(anyhow!("invalid state")),
-    }
+#[derive(Serialize)]
+struct SerializedOverrideId {
+    id: u64,
+}
+
+#[derive(Serialize)]
+struct SerializedOverrideStatus {
+    id: u64,
+    state: String,
+    priority: u32,
+    start: String,
+    end: String,
+    remaining_seconds: f64,
+    scene: Option<String>,
+    in_effect: bool,
+}
+
+#[derive(Serialize)]
+struct SerializedSceneOverrideIds {
+    ids: Vec<u64>,
+}
+
+#[derive(Deserialize)]
+struct SerializedHold {
+    state: Option<String>,
+    #[serde(rename = "for")]
+    for_string: Option<String>,
+}
+
+#[derive(Serialize)]
+struct SerializedHoldStatus {
+    held: bool,
+    state: Option<String>,
+    until: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SerializedProfile {
+    name: Option<String>,
+}
+
+#[derive(Serialize)]
+struct SerializedProfileStatus {
+    active: Option<String>,
+    profiles: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct SerializedPreviewQuery {
+    #[serde(rename = "for")]
+    for_string: Option<String>,
+}
+
+#[derive(Serialize)]
+struct SerializedTransition {
+    time: String,
+    output: String,
+    state: String,
 }